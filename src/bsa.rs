@@ -0,0 +1,680 @@
+//! Reading scripts out of Bethesda's archives: Skyrim's `.bsa`, versions 104 (Oldrim) and 105 (Special Edition),
+//! and the general `.ba2` archives of Fallout 4 and Starfield.
+
+use crate::pex::{self, Pex, PexError};
+use std::{
+	io::{Read, Seek, SeekFrom},
+	path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BsaError {
+	#[error("Not a BSA or BA2 archive")]
+	Magic,
+
+	#[error("Unsupported archive version: {0}")]
+	UnsupportedVersion(u32),
+
+	#[error("Unsupported BA2 type {0:?}, only GNRL archives hold scripts")]
+	UnsupportedType(String),
+
+	#[error("Failed to decompress {0}: {1}")]
+	Decompress(String, String),
+
+	#[error("Failed to parse {0}: {1}")]
+	Pex(String, PexError),
+
+	#[error("IO error: {0}")]
+	IO(#[from] std::io::Error),
+}
+
+pub type BsaResult<T> = Result<T, BsaError>;
+
+/// Folders and file names are stored.
+const FLAG_DIRECTORY_NAMES: u32 = 0x1;
+const FLAG_FILE_NAMES: u32 = 0x2;
+/// Files are compressed unless their size says otherwise.
+const FLAG_COMPRESSED: u32 = 0x4;
+/// File data starts with its full path.
+const FLAG_EMBED_NAMES: u32 = 0x100;
+
+/// Set in a file's size when it's compressed the other way from the archive's default.
+const SIZE_TOGGLE_COMPRESSION: u32 = 0x4000_0000;
+
+/// How much bigger than its packed size an entry may claim to unpack to. Zlib's worst case is about 1032:1.
+const MAX_UNPACK_RATIO: u64 = 1032;
+
+/// Largest entry that gets extracted. Scripts are kilobytes, so anything bigger is a broken archive.
+const MAX_UNPACKED_SIZE: u32 = 64 << 20;
+
+/// [`Bsa::version`] 3 BA2s say which of these they use, earlier ones are all zlib.
+const BA2_COMPRESSION_LZ4: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+	None,
+	Zlib,
+	/// Skyrim Special Edition
+	Lz4Frame,
+	/// Starfield
+	Lz4Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	/// Lowercase with backslashes, as stored, e.g. `scripts\myscript.pex`.
+	pub path: String,
+	offset: u64,
+	/// Of the data as stored.
+	size: u32,
+	/// BSAs store this in front of compressed data instead.
+	unpacked_size: Option<u32>,
+	compression: Compression,
+}
+
+impl Entry {
+	/// The folder the entry is in, e.g. `scripts`.
+	pub fn folder(&self) -> &str {
+		self.path.rsplit_once('\\').map_or("", |(folder, _)| folder)
+	}
+
+	pub fn file_name(&self) -> &str {
+		self.path
+			.rsplit_once('\\')
+			.map_or(&self.path, |(_, name)| name)
+	}
+
+	/// The path to extract the entry to, relative to the output folder.
+	/// `None` if it has a `..`, a root or a drive, which would put it somewhere else.
+	pub fn relative_path(&self) -> Option<PathBuf> {
+		let path = self.path.replace('\\', "/");
+		let relative = Path::new(&path)
+			.components()
+			.filter(|c| *c != Component::CurDir)
+			.map(|c| match c {
+				// A colon would make it drive relative on Windows.
+				Component::Normal(part) if !part.to_string_lossy().contains(':') => Some(part),
+				_ => None,
+			})
+			.collect::<Option<PathBuf>>()?;
+		(!relative.as_os_str().is_empty()).then_some(relative)
+	}
+
+	/// Whether it's a compiled script in `scripts\` or a folder below it.
+	pub fn is_script(&self) -> bool {
+		self.path.starts_with("scripts\\") && self.path.ends_with(".pex")
+	}
+}
+
+/// An archive's file listing. Data is read from disk when asked for, since archives can be large.
+#[derive(Debug, Clone)]
+pub struct Bsa {
+	pub path: PathBuf,
+	/// 104 or 105 for BSAs, 1 through 8 for BA2s.
+	pub version: u32,
+	/// Archive flags of BSAs, always 0 for BA2s.
+	flags: u32,
+	pub entries: Vec<Entry>,
+}
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+	let mut buf = [0; 1];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+	let mut buf = [0; 4];
+	r.read_exact(&mut buf)?;
+	Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u16(r: &mut impl Read) -> std::io::Result<u16> {
+	let mut buf = [0; 2];
+	r.read_exact(&mut buf)?;
+	Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+	let mut buf = [0; 8];
+	r.read_exact(&mut buf)?;
+	Ok(u64::from_le_bytes(buf))
+}
+
+/// A string prefixed by its length, which counts the trailing null if `nulled`.
+fn read_bstring(r: &mut impl Read, nulled: bool) -> std::io::Result<String> {
+	let mut buf = vec![0; read_u8(r)? as usize];
+	r.read_exact(&mut buf)?;
+	if nulled {
+		buf.pop();
+	}
+	Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+impl Bsa {
+	pub fn open(path: impl AsRef<Path>) -> BsaResult<Self> {
+		let path = path.as_ref();
+		let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+		let mut bsa = Self::read(&mut file)?;
+		bsa.path = path.to_owned();
+		Ok(bsa)
+	}
+
+	/// Reads the listing of an archive, telling BSAs and BA2s apart by their magic. [`Bsa::path`] is left empty.
+	pub fn read(r: &mut (impl Read + Seek)) -> BsaResult<Self> {
+		let mut magic = [0; 4];
+		r.read_exact(&mut magic)?;
+		match &magic {
+			b"BSA\0" => Self::read_bsa(r),
+			b"BTDX" => Self::read_ba2(r),
+			_ => Err(BsaError::Magic),
+		}
+	}
+
+	fn read_bsa(r: &mut (impl Read + Seek)) -> BsaResult<Self> {
+		let version = read_u32(r)?;
+		if version != 104 && version != 105 {
+			return Err(BsaError::UnsupportedVersion(version));
+		}
+
+		let folder_offset = read_u32(r)?;
+		let flags = read_u32(r)?;
+		let folder_count = read_u32(r)?;
+		let file_count = read_u32(r)?;
+		let _total_folder_name_length = read_u32(r)?;
+		let _total_file_name_length = read_u32(r)?;
+
+		// (file count, where its file records are) of each folder
+		r.seek(SeekFrom::Start(folder_offset.into()))?;
+		let mut folders = vec![];
+		for _ in 0..folder_count {
+			let _hash = read_u64(r)?;
+			let count = read_u32(r)?;
+			let offset = if version == 105 {
+				let _padding = read_u32(r)?;
+				read_u64(r)?
+			} else {
+				read_u32(r)?.into()
+			};
+			folders.push((count, offset));
+		}
+
+		// Folder names and file records come in one block per folder, right after the folder records.
+		let mut files = vec![];
+		for (count, _) in &folders {
+			let folder = if flags & FLAG_DIRECTORY_NAMES != 0 {
+				read_bstring(r, true)?
+			} else {
+				String::new()
+			};
+
+			for _ in 0..*count {
+				let _hash = read_u64(r)?;
+				let size = read_u32(r)?;
+				let offset = read_u32(r)?;
+				files.push((folder.clone(), size, offset));
+			}
+		}
+
+		// Then every file name, null terminated, in the same order.
+		let mut names = vec![];
+		if flags & FLAG_FILE_NAMES != 0 {
+			for _ in 0..file_count {
+				let mut name = vec![];
+				loop {
+					match read_u8(r)? {
+						0 => break,
+						c => name.push(c),
+					}
+				}
+				names.push(String::from_utf8_lossy(&name).into_owned());
+			}
+		}
+
+		let entries = files
+			.into_iter()
+			.enumerate()
+			.map(|(i, (folder, size, offset))| {
+				let name = names.get(i).map_or("", String::as_str);
+				let path = match folder.as_str() {
+					"" => name.to_owned(),
+					folder => format!("{folder}\\{name}"),
+				};
+
+				let compressed =
+					(flags & FLAG_COMPRESSED != 0) != (size & SIZE_TOGGLE_COMPRESSION != 0);
+				Entry {
+					path: path.to_lowercase().replace('/', "\\"),
+					offset: offset.into(),
+					size: size & !SIZE_TOGGLE_COMPRESSION,
+					unpacked_size: None,
+					compression: match (compressed, version) {
+						(false, _) => Compression::None,
+						(true, 105) => Compression::Lz4Frame,
+						(true, _) => Compression::Zlib,
+					},
+				}
+			})
+			.collect();
+
+		Ok(Self {
+			path: PathBuf::new(),
+			version,
+			flags,
+			entries,
+		})
+	}
+
+	fn read_ba2(r: &mut (impl Read + Seek)) -> BsaResult<Self> {
+		let version = read_u32(r)?;
+		if !matches!(version, 1..=3 | 7 | 8) {
+			return Err(BsaError::UnsupportedVersion(version));
+		}
+
+		// Texture archives store files in chunks, and never hold scripts anyway.
+		let mut kind = [0; 4];
+		r.read_exact(&mut kind)?;
+		if kind != *b"GNRL" {
+			return Err(BsaError::UnsupportedType(
+				String::from_utf8_lossy(&kind).into_owned(),
+			));
+		}
+
+		let file_count = read_u32(r)?;
+		let name_table_offset = read_u64(r)?;
+
+		let mut compression = Compression::Zlib;
+		if version == 2 || version == 3 {
+			let _unknown = read_u64(r)?;
+		}
+		if version == 3 && read_u32(r)? == BA2_COMPRESSION_LZ4 {
+			compression = Compression::Lz4Block;
+		}
+
+		let mut files = vec![];
+		for _ in 0..file_count {
+			let _name_hash = read_u32(r)?;
+			let _extension = read_u32(r)?;
+			let _dir_hash = read_u32(r)?;
+			let _flags = read_u32(r)?;
+			let offset = read_u64(r)?;
+			let packed_size = read_u32(r)?;
+			let unpacked_size = read_u32(r)?;
+			let _align = read_u32(r)?;
+			files.push((offset, packed_size, unpacked_size));
+		}
+
+		r.seek(SeekFrom::Start(name_table_offset))?;
+		let entries = files
+			.into_iter()
+			.map(|(offset, packed_size, unpacked_size)| {
+				let mut name = vec![0; read_u16(r)? as usize];
+				r.read_exact(&mut name)?;
+
+				// Stored as is when packing didn't make it smaller.
+				let packed = packed_size != 0;
+				Ok(Entry {
+					path: String::from_utf8_lossy(&name)
+						.to_lowercase()
+						.replace('/', "\\"),
+					offset,
+					size: if packed { packed_size } else { unpacked_size },
+					unpacked_size: Some(unpacked_size),
+					compression: if packed {
+						compression
+					} else {
+						Compression::None
+					},
+				})
+			})
+			.collect::<std::io::Result<_>>()?;
+
+		Ok(Self {
+			path: PathBuf::new(),
+			version,
+			flags: 0,
+			entries,
+		})
+	}
+
+	/// Every compiled script in the archive.
+	pub fn scripts(&self) -> impl Iterator<Item = &Entry> {
+		self.entries.iter().filter(|e| e.is_script())
+	}
+
+	/// Finds an entry by path, ignoring case and which way the slashes go.
+	pub fn find(&self, path: &str) -> Option<&Entry> {
+		let path = path.to_lowercase().replace('/', "\\");
+		self.entries.iter().find(|e| e.path == path)
+	}
+
+	/// Reads and decompresses an entry's data from the archive on disk.
+	pub fn extract(&self, entry: &Entry) -> BsaResult<Vec<u8>> {
+		let mut file = std::fs::File::open(&self.path)?;
+		self.extract_from(&mut file, entry)
+	}
+
+	/// Like [`Bsa::extract`], reading from an already open archive.
+	pub fn extract_from(&self, r: &mut (impl Read + Seek), entry: &Entry) -> BsaResult<Vec<u8>> {
+		r.seek(SeekFrom::Start(entry.offset))?;
+		let mut r = r.take(entry.size.into());
+
+		if self.flags & FLAG_EMBED_NAMES != 0 {
+			read_bstring(&mut r, false)?;
+		}
+
+		let unpacked_size = match entry.unpacked_size {
+			Some(size) => size,
+			None if entry.compression != Compression::None => read_u32(&mut r)?,
+			None => entry.size,
+		};
+
+		let decompress_error = |e: String| BsaError::Decompress(entry.path.clone(), e);
+		if unpacked_size > MAX_UNPACKED_SIZE {
+			return Err(decompress_error(format!(
+				"unpacks to {unpacked_size} bytes, more than the {MAX_UNPACKED_SIZE} extracted"
+			)));
+		}
+		if entry.compression != Compression::None
+			&& u64::from(unpacked_size) > u64::from(entry.size) * MAX_UNPACK_RATIO
+		{
+			return Err(decompress_error(format!(
+				"claims to unpack {} bytes to {unpacked_size}",
+				entry.size
+			)));
+		}
+
+		// One byte over the expected size is enough to tell the data is lying about it.
+		// The buffer grows with what's actually read rather than trusting the size up front.
+		let limit = u64::from(unpacked_size) + 1;
+		let mut data = vec![];
+		let result = match entry.compression {
+			Compression::None => r.read_to_end(&mut data).map(drop),
+			Compression::Zlib => flate2::read::ZlibDecoder::new(r)
+				.take(limit)
+				.read_to_end(&mut data)
+				.map(drop),
+			Compression::Lz4Frame => lz4_flex::frame::FrameDecoder::new(r)
+				.take(limit)
+				.read_to_end(&mut data)
+				.map(drop),
+			Compression::Lz4Block => {
+				let mut packed = vec![];
+				r.read_to_end(&mut packed)?;
+
+				// Block decompression allocates the whole output at once, so the size has to
+				// match the data that's really there, not just what the entry claims.
+				if u64::from(unpacked_size) > packed.len() as u64 * MAX_UNPACK_RATIO {
+					return Err(decompress_error(format!(
+						"claims to unpack {} bytes to {unpacked_size}",
+						packed.len()
+					)));
+				}
+				lz4_flex::block::decompress(&packed, unpacked_size as usize)
+					.map(|unpacked| data = unpacked)
+					.map_err(std::io::Error::other)
+			}
+		};
+		result.map_err(|e| decompress_error(e.to_string()))?;
+		if data.len() as u64 > u64::from(unpacked_size) {
+			return Err(decompress_error(format!(
+				"unpacks to more than {unpacked_size} bytes"
+			)));
+		}
+
+		Ok(data)
+	}
+
+	/// Extracts and parses a script.
+	pub fn read_pex(&self, entry: &Entry) -> BsaResult<Pex> {
+		pex::parse(&self.extract(entry)?).map_err(|e| BsaError::Pex(entry.path.clone(), e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Write};
+
+	const A: &[u8] = b"Scriptname A extends Quest";
+	const B: &[u8] = b"Scriptname B extends Actor Actor Actor Actor Actor Actor";
+
+	fn zlib(data: &[u8]) -> Vec<u8> {
+		let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+		encoder.write_all(data).unwrap();
+		encoder.finish().unwrap()
+	}
+
+	fn lz4_frame(data: &[u8]) -> Vec<u8> {
+		let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+		encoder.write_all(data).unwrap();
+		encoder.finish().unwrap()
+	}
+
+	/// A compressed BSA with one `Scripts` folder. `a.pex` is compressed, `b.pex` toggled to be stored as is.
+	fn bsa(version: u32) -> Vec<u8> {
+		let folder_record = if version == 105 { 24 } else { 16 };
+		let names = b"a.pex\0b.pex\0";
+		let records_end = 36 + folder_record + 9 + 2 * 16;
+		let data_start = records_end + names.len();
+
+		let packed = if version == 105 {
+			lz4_frame(A)
+		} else {
+			zlib(A)
+		};
+		let mut a = (A.len() as u32).to_le_bytes().to_vec();
+		a.extend(packed);
+
+		let mut out = b"BSA\0".to_vec();
+		for field in [
+			version,
+			36,
+			FLAG_DIRECTORY_NAMES | FLAG_FILE_NAMES | FLAG_COMPRESSED,
+			1,
+			2,
+			8,
+			names.len() as u32,
+			0,
+		] {
+			out.extend(field.to_le_bytes());
+		}
+
+		out.extend(0u64.to_le_bytes());
+		out.extend(2u32.to_le_bytes());
+		if version == 105 {
+			out.extend(0u32.to_le_bytes());
+			out.extend((36 + folder_record as u64).to_le_bytes());
+		} else {
+			out.extend((36 + folder_record as u32).to_le_bytes());
+		}
+
+		out.push(8);
+		out.extend(b"Scripts\0");
+		for (size, offset) in [
+			(a.len() as u32, data_start),
+			(
+				B.len() as u32 | SIZE_TOGGLE_COMPRESSION,
+				data_start + a.len(),
+			),
+		] {
+			out.extend(0u64.to_le_bytes());
+			out.extend(size.to_le_bytes());
+			out.extend((offset as u32).to_le_bytes());
+		}
+		assert_eq!(out.len(), records_end);
+
+		out.extend(names);
+		out.extend(a);
+		out.extend(B);
+		out
+	}
+
+	/// A GNRL BA2 holding `a.pex` packed and `b.pex` stored as is, with `unpacked` as `a.pex`'s claimed size.
+	fn ba2(version: u32, unpacked: u32) -> Vec<u8> {
+		let header = if version == 3 { 36 } else { 24 };
+		let data_start = header + 2 * 36;
+		let a = if version == 3 {
+			lz4_flex::block::compress(A)
+		} else {
+			zlib(A)
+		};
+
+		let mut out = b"BTDX".to_vec();
+		out.extend(version.to_le_bytes());
+		out.extend(b"GNRL");
+		out.extend(2u32.to_le_bytes());
+		out.extend(((data_start + a.len() + B.len()) as u64).to_le_bytes());
+		if version == 3 {
+			out.extend(0u64.to_le_bytes());
+			out.extend(BA2_COMPRESSION_LZ4.to_le_bytes());
+		}
+
+		for (offset, packed, unpacked) in [
+			(data_start, a.len() as u32, unpacked),
+			(data_start + a.len(), 0, B.len() as u32),
+		] {
+			out.extend([0; 4]);
+			out.extend(b"pex\0");
+			out.extend([0; 8]);
+			out.extend((offset as u64).to_le_bytes());
+			out.extend(packed.to_le_bytes());
+			out.extend(unpacked.to_le_bytes());
+			out.extend(0xBAAD_F00Du32.to_le_bytes());
+		}
+		assert_eq!(out.len(), data_start);
+
+		out.extend(a);
+		out.extend(B);
+		for name in ["Scripts/A.pex", "Scripts/Sub/B.pex"] {
+			out.extend((name.len() as u16).to_le_bytes());
+			out.extend(name.as_bytes());
+		}
+		out
+	}
+
+	/// Every entry's path and extracted data.
+	fn extract_all(bytes: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+		let mut r = Cursor::new(bytes);
+		let archive = Bsa::read(&mut r).unwrap();
+		archive
+			.entries
+			.iter()
+			.map(|e| (e.path.clone(), archive.extract_from(&mut r, e).unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn bsa_104_zlib() {
+		assert_eq!(
+			extract_all(bsa(104)),
+			[
+				("scripts\\a.pex".to_owned(), A.to_vec()),
+				("scripts\\b.pex".to_owned(), B.to_vec()),
+			]
+		);
+	}
+
+	#[test]
+	fn bsa_105_lz4() {
+		assert_eq!(
+			extract_all(bsa(105)),
+			[
+				("scripts\\a.pex".to_owned(), A.to_vec()),
+				("scripts\\b.pex".to_owned(), B.to_vec()),
+			]
+		);
+	}
+
+	#[test]
+	fn ba2_gnrl() {
+		for version in [1, 3] {
+			let bytes = ba2(version, A.len() as u32);
+			let archive = Bsa::read(&mut Cursor::new(bytes.clone())).unwrap();
+			assert_eq!(archive.version, version);
+			assert_eq!(
+				archive.scripts().map(Entry::folder).collect::<Vec<_>>(),
+				["scripts", "scripts\\sub"]
+			);
+			assert_eq!(
+				extract_all(bytes),
+				[
+					("scripts\\a.pex".to_owned(), A.to_vec()),
+					("scripts\\sub\\b.pex".to_owned(), B.to_vec()),
+				]
+			);
+		}
+	}
+
+	#[test]
+	fn oversized_unpacked_size() {
+		for (version, unpacked) in [(1, u32::MAX), (1, A.len() as u32 - 1), (3, u32::MAX)] {
+			let mut r = Cursor::new(ba2(version, unpacked));
+			let archive = Bsa::read(&mut r).unwrap();
+			let result = archive.extract_from(&mut r, &archive.entries[0]);
+			assert!(
+				matches!(result, Err(BsaError::Decompress(..))),
+				"{version} {unpacked}: {result:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn size_limits() {
+		let mut r = Cursor::new(ba2(3, A.len() as u32));
+		let archive = Bsa::read(&mut r).unwrap();
+		let mut extract = |size, unpacked_size, compression| {
+			let entry = Entry {
+				size,
+				unpacked_size: Some(unpacked_size),
+				compression,
+				..archive.entries[0].clone()
+			};
+			archive.extract_from(&mut r, &entry)
+		};
+
+		// Too big to extract even though the ratio is believable.
+		let result = extract(u32::MAX, u32::MAX, Compression::None);
+		assert!(
+			matches!(result, Err(BsaError::Decompress(..))),
+			"{result:?}"
+		);
+		let result = extract(1 << 20, MAX_UNPACKED_SIZE + 1, Compression::Lz4Block);
+		assert!(
+			matches!(result, Err(BsaError::Decompress(..))),
+			"{result:?}"
+		);
+
+		// Claims far more packed data than the archive has.
+		let result = extract(60_000, 60_000_000, Compression::Lz4Block);
+		assert!(
+			matches!(result, Err(BsaError::Decompress(..))),
+			"{result:?}"
+		);
+	}
+
+	#[test]
+	fn relative_path() {
+		let entry = |path: &str| Entry {
+			path: path.to_owned(),
+			offset: 0,
+			size: 0,
+			unpacked_size: None,
+			compression: Compression::None,
+		};
+
+		assert_eq!(
+			entry("scripts\\.\\a.pex").relative_path(),
+			Some(PathBuf::from("scripts/a.pex"))
+		);
+		for path in [
+			"scripts\\..\\..\\a.pex",
+			"\\scripts\\a.pex",
+			"c:\\scripts\\a.pex",
+			"c:a.pex",
+			"",
+		] {
+			assert_eq!(entry(path).relative_path(), None, "{path}");
+		}
+	}
+}
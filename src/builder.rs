@@ -0,0 +1,591 @@
+//! Building scripts from Rust code, for generated scripts that shouldn't need the Papyrus compiler.
+//!
+//! A [`ScriptBuilder`] makes a single object script like the compiler does. Names are interned,
+//! property flags follow from what's given, user flags are set by name and debug info is optional.
+
+use crate::{
+	model::{
+		DebugFunction, DebugInfo, Function, Instruction, Object, Property, Script, State, Variable,
+		VariableData, VariableType,
+	},
+	pex::{
+		FN_TYPE_GETTER, FN_TYPE_METHOD, FN_TYPE_SETTER, FunctionFlags, Pex, PexError, PexResult,
+		PropertyFlags,
+	},
+};
+
+/// User flags the compiler always declares, with the bits it gives them.
+const STANDARD_USER_FLAGS: [(&str, u8); 2] = [("hidden", 0), ("conditional", 1)];
+
+#[derive(Debug, Clone)]
+pub struct FunctionBuilder {
+	function: Function,
+	user_flags: Vec<String>,
+	lines: Vec<u16>,
+	line: u16,
+	/// First mnemonic that didn't exist, reported when the function is added.
+	unknown: Option<String>,
+}
+
+impl Default for FunctionBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FunctionBuilder {
+	/// A function returning `None`, with no parameters or instructions yet.
+	pub fn new() -> Self {
+		Self {
+			function: Function {
+				return_type: "None".to_owned(),
+				doc_string: String::new(),
+				user_flags: vec![],
+				flags: FunctionFlags::default(),
+				params: vec![],
+				locals: vec![],
+				instructions: vec![],
+			},
+			user_flags: vec![],
+			lines: vec![],
+			line: 0,
+			unknown: None,
+		}
+	}
+
+	pub fn returns(mut self, type_name: &str) -> Self {
+		self.function.return_type = type_name.to_owned();
+		self
+	}
+
+	pub fn param(mut self, name: &str, type_name: &str) -> Self {
+		self.function.params.push(VariableType {
+			name: name.to_owned(),
+			type_name: type_name.to_owned(),
+		});
+		self
+	}
+
+	pub fn local(mut self, name: &str, type_name: &str) -> Self {
+		self.function.locals.push(VariableType {
+			name: name.to_owned(),
+			type_name: type_name.to_owned(),
+		});
+		self
+	}
+
+	pub fn doc(mut self, doc: &str) -> Self {
+		self.function.doc_string = doc.to_owned();
+		self
+	}
+
+	pub fn global(mut self) -> Self {
+		self.function.flags.set(FunctionFlags::GLOBAL, true);
+		self
+	}
+
+	/// Marks the function as implemented by the game. Native functions can't have instructions.
+	pub fn native(mut self) -> Self {
+		self.function.flags.set(FunctionFlags::NATIVE, true);
+		self
+	}
+
+	pub fn user_flags(mut self, names: &[&str]) -> Self {
+		self.user_flags
+			.extend(names.iter().map(|n| (*n).to_owned()));
+		self
+	}
+
+	/// Source line given to the instructions added after this, for debug info.
+	pub fn line(mut self, line: u16) -> Self {
+		self.line = line;
+		self
+	}
+
+	pub fn instruction(mut self, instruction: Instruction) -> Self {
+		self.function.instructions.push(instruction);
+		self.lines.push(self.line);
+		self
+	}
+
+	/// Adds an instruction by mnemonic, e.g. `op("ASSIGN", vec![ident("x"), Int(1)])`.
+	/// Calls take their arguments through [`FunctionBuilder::call`] instead.
+	pub fn op(self, mnemonic: &str, operands: Vec<VariableData>) -> Self {
+		self.call(mnemonic, operands, vec![])
+	}
+
+	/// Adds a `CALLMETHOD`, `CALLPARENT` or `CALLSTATIC` with its arguments.
+	pub fn call(
+		mut self,
+		mnemonic: &str,
+		operands: Vec<VariableData>,
+		args: Vec<VariableData>,
+	) -> Self {
+		match Instruction::new(mnemonic, operands, args) {
+			Some(instruction) => self.instruction(instruction),
+			None => {
+				self.unknown.get_or_insert_with(|| mnemonic.to_owned());
+				self
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertyBuilder {
+	property: Property,
+	user_flags: Vec<String>,
+	/// Initial value of an auto property's variable.
+	value: Option<VariableData>,
+	read_handler: Option<FunctionBuilder>,
+	write_handler: Option<FunctionBuilder>,
+}
+
+impl PropertyBuilder {
+	/// A full property, read and written through the handlers given with
+	/// [`PropertyBuilder::getter`] and [`PropertyBuilder::setter`].
+	pub fn new(name: &str, type_name: &str) -> Self {
+		Self {
+			property: Property {
+				name: name.to_owned(),
+				type_name: type_name.to_owned(),
+				doc_string: String::new(),
+				user_flags: vec![],
+				flags: PropertyFlags::default(),
+				auto_var_name: None,
+				read_handler: None,
+				write_handler: None,
+			},
+			user_flags: vec![],
+			value: None,
+			read_handler: None,
+			write_handler: None,
+		}
+	}
+
+	/// An auto property, backed by a `::Name_var` variable starting out as `value`.
+	pub fn auto(name: &str, type_name: &str, value: VariableData) -> Self {
+		let mut builder = Self::new(name, type_name);
+		builder.property.auto_var_name = Some(format!("::{name}_var"));
+		builder.value = Some(value);
+		builder
+	}
+
+	pub fn doc(mut self, doc: &str) -> Self {
+		self.property.doc_string = doc.to_owned();
+		self
+	}
+
+	pub fn user_flags(mut self, names: &[&str]) -> Self {
+		self.user_flags
+			.extend(names.iter().map(|n| (*n).to_owned()));
+		self
+	}
+
+	/// Ignored for auto properties.
+	pub fn getter(mut self, function: FunctionBuilder) -> Self {
+		self.read_handler = Some(function.returns(&self.property.type_name));
+		self
+	}
+
+	/// Ignored for auto properties.
+	pub fn setter(mut self, function: FunctionBuilder) -> Self {
+		self.write_handler = Some(function);
+		self
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptBuilder {
+	script: Script,
+	debug_info: bool,
+	/// Line tables of every function added, kept whether or not debug info ends up being written.
+	debug: Vec<DebugFunction>,
+	/// First error from a function or property, reported when building.
+	error: Option<String>,
+}
+
+impl ScriptBuilder {
+	/// A script for an object extending `parent`, or nothing if it's empty.
+	pub fn new(name: &str, parent: &str) -> Self {
+		Self {
+			script: Script {
+				major: 3,
+				minor: 2,
+				gameid: 1,
+				comptime: 0,
+				src: format!("{name}.psc"),
+				username: String::new(),
+				machine: String::new(),
+				debuginfo: None,
+				userflags: STANDARD_USER_FLAGS
+					.iter()
+					.map(|(name, bit)| ((*name).to_owned(), *bit))
+					.collect(),
+				objects: vec![Object {
+					name: name.to_owned(),
+					parent: parent.to_owned(),
+					doc_string: String::new(),
+					user_flags: vec![],
+					auto_state: String::new(),
+					variables: vec![],
+					properties: vec![],
+					states: vec![State {
+						name: String::new(),
+						functions: vec![],
+					}],
+				}],
+			},
+			debug_info: false,
+			debug: vec![],
+			error: None,
+		}
+	}
+
+	fn object(&mut self) -> &mut Object {
+		&mut self.script.objects[0]
+	}
+
+	/// Declares the named user flags the script doesn't have yet, returning the names as declared.
+	/// Past 32 flags there's no bit left, which fails the build.
+	fn declare_user_flags(&mut self, names: &[String]) -> Vec<String> {
+		names
+			.iter()
+			.map(|name| {
+				let userflags = &mut self.script.userflags;
+				if let Some((declared, _)) =
+					userflags.iter().find(|(n, _)| n.eq_ignore_ascii_case(name))
+				{
+					return declared.clone();
+				}
+				match (0..32).find(|b| !userflags.iter().any(|(_, bit)| bit == b)) {
+					Some(bit) => userflags.push((name.clone(), bit)),
+					None => {
+						self.error.get_or_insert_with(|| {
+							format!("user flag {name}: all 32 user flag bits are taken")
+						});
+					}
+				}
+				name.clone()
+			})
+			.collect()
+	}
+
+	/// Turns a function builder into its function, keeping its line table.
+	fn finish(
+		&mut self,
+		state: &str,
+		name: &str,
+		fn_type: u8,
+		builder: FunctionBuilder,
+	) -> Function {
+		if let Some(mnemonic) = builder.unknown {
+			self.error
+				.get_or_insert_with(|| format!("{name}: unknown mnemonic {mnemonic}"));
+		}
+
+		let mut function = builder.function;
+		function.user_flags = self.declare_user_flags(&builder.user_flags);
+
+		if function.flags.contains(FunctionFlags::NATIVE) && !function.instructions.is_empty() {
+			self.error
+				.get_or_insert_with(|| format!("{name}: native functions can't have instructions"));
+		}
+
+		// Natives have no instructions to give lines to.
+		if !function.flags.contains(FunctionFlags::NATIVE) {
+			let object = self.object().name.clone();
+			self.debug.push(DebugFunction {
+				object,
+				state: state.to_owned(),
+				function: name.to_owned(),
+				fn_type,
+				lines: builder.lines,
+			});
+		}
+
+		function
+	}
+
+	/// The source file name written into the script, `Name.psc` by default.
+	pub fn source(mut self, src: &str) -> Self {
+		self.script.src = src.to_owned();
+		self
+	}
+
+	/// Compile time in seconds since the epoch, also used as the debug info's modification time.
+	/// 0 by default, so the same code always builds the same script.
+	pub fn comptime(mut self, time: u64) -> Self {
+		self.script.comptime = time;
+		self
+	}
+
+	pub fn doc(mut self, doc: &str) -> Self {
+		self.object().doc_string = doc.to_owned();
+		self
+	}
+
+	pub fn user_flags(mut self, names: &[&str]) -> Self {
+		let names = names.iter().map(|n| (*n).to_owned()).collect::<Vec<_>>();
+		for name in self.declare_user_flags(&names) {
+			let user_flags = &mut self.object().user_flags;
+			if !user_flags.contains(&name) {
+				user_flags.push(name);
+			}
+		}
+		self
+	}
+
+	/// Writes line tables for every function, from [`FunctionBuilder::line`].
+	pub fn debug_info(mut self) -> Self {
+		self.debug_info = true;
+		self
+	}
+
+	/// The state the script starts out in.
+	pub fn auto_state(mut self, state: &str) -> Self {
+		self.object().auto_state = state.to_owned();
+		self
+	}
+
+	pub fn variable(
+		mut self,
+		name: &str,
+		type_name: &str,
+		value: VariableData,
+		user_flags: &[&str],
+	) -> Self {
+		let names = user_flags
+			.iter()
+			.map(|n| (*n).to_owned())
+			.collect::<Vec<_>>();
+		let user_flags = self.declare_user_flags(&names);
+		self.object().variables.push(Variable {
+			name: name.to_owned(),
+			type_name: type_name.to_owned(),
+			user_flags,
+			value,
+		});
+		self
+	}
+
+	pub fn property(mut self, builder: PropertyBuilder) -> Self {
+		let mut property = builder.property;
+		property.user_flags = self.declare_user_flags(&builder.user_flags);
+
+		if let Some(var) = &property.auto_var_name {
+			property.flags = PropertyFlags::READ | PropertyFlags::WRITE | PropertyFlags::AUTOVAR;
+
+			let variable = Variable {
+				name: var.clone(),
+				type_name: property.type_name.clone(),
+				user_flags: vec![],
+				value: builder.value.unwrap_or(VariableData::Null),
+			};
+			self.object().variables.push(variable);
+		} else {
+			if let Some(getter) = builder.read_handler {
+				property.flags.set(PropertyFlags::READ, true);
+				property.read_handler =
+					Some(self.finish("", &property.name, FN_TYPE_GETTER, getter));
+			}
+			if let Some(setter) = builder.write_handler {
+				property.flags.set(PropertyFlags::WRITE, true);
+				property.write_handler =
+					Some(self.finish("", &property.name, FN_TYPE_SETTER, setter));
+			}
+		}
+
+		self.object().properties.push(property);
+		self
+	}
+
+	/// Adds a function to the empty state every script has.
+	pub fn function(self, name: &str, builder: FunctionBuilder) -> Self {
+		self.state_function("", name, builder)
+	}
+
+	/// Adds a function to a state, creating it if needed.
+	pub fn state_function(mut self, state: &str, name: &str, builder: FunctionBuilder) -> Self {
+		let function = self.finish(state, name, FN_TYPE_METHOD, builder);
+
+		let states = &mut self.object().states;
+		let idx = match states
+			.iter()
+			.position(|s| s.name.eq_ignore_ascii_case(state))
+		{
+			Some(idx) => idx,
+			None => {
+				states.push(State {
+					name: state.to_owned(),
+					functions: vec![],
+				});
+				states.len() - 1
+			}
+		};
+		states[idx].functions.push((name.to_owned(), function));
+		self
+	}
+
+	/// Finishes the script as a [`Script`], failing on the first function that couldn't be built.
+	pub fn build_script(self) -> Result<Script, String> {
+		if let Some(e) = self.error {
+			return Err(e);
+		}
+
+		let mut script = self.script;
+		if self.debug_info {
+			script.debuginfo = Some(DebugInfo {
+				modtime: script.comptime,
+				functions: self.debug,
+			});
+		}
+		Ok(script)
+	}
+
+	/// Finishes and lowers the script, ready for [`crate::pex::assemble`].
+	pub fn build(self) -> PexResult<Pex> {
+		self.build_script().map_err(PexError::Build)?.to_pex()
+	}
+}
+
+/// An identifier operand, like a variable or function name.
+pub fn ident(name: &str) -> VariableData {
+	VariableData::Ident(name.to_owned())
+}
+
+/// A string literal operand.
+pub fn string(s: &str) -> VariableData {
+	VariableData::String(s.to_owned())
+}
+
+/// Scripts for the tests across the crate.
+#[cfg(test)]
+pub(crate) mod fixture {
+	use super::*;
+	use crate::disasm;
+
+	/// `TestScript`, extending `ObjectReference`, for tests that need more than functions.
+	pub fn builder() -> ScriptBuilder {
+		ScriptBuilder::new("TestScript", "ObjectReference")
+	}
+
+	/// `TestScript` with `functions` in its empty state.
+	pub fn script<'a>(functions: impl IntoIterator<Item = (&'a str, FunctionBuilder)>) -> Pex {
+		functions
+			.into_iter()
+			.fold(builder(), |builder, (name, function)| {
+				builder.function(name, function)
+			})
+			.build()
+			.unwrap()
+	}
+
+	/// The disassembled instructions of a `TestScript` function in the empty state.
+	pub fn listing(pex: &Pex, function: &str) -> Vec<String> {
+		let function = pex.find_function("TestScript", "", function).unwrap();
+		(function.instructions.iter())
+			.map(|i| disasm::instruction(pex, i))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{fixture::*, *};
+	use crate::model::VariableData::{Bool, Int};
+
+	#[test]
+	fn builds_like_the_compiler() {
+		let pex = builder()
+			.debug_info()
+			.user_flags(&["Conditional"])
+			.variable("::count_var", "Int", Int(1), &["hidden"])
+			.property(PropertyBuilder::auto("Enabled", "Bool", Bool(true)))
+			.property(
+				PropertyBuilder::new("Count", "Int")
+					.getter(FunctionBuilder::new().op("RETURN", vec![ident("::count_var")])),
+			)
+			.function("Native", FunctionBuilder::new().native().global())
+			.state_function(
+				"Busy",
+				"Run",
+				FunctionBuilder::new()
+					.line(3)
+					.op("ASSIGN", vec![ident("::count_var"), Int(2)]),
+			)
+			.build()
+			.unwrap();
+
+		assert_eq!(pex.comptime, 0);
+		assert_eq!(pex.string(pex.objects[0].0), "TestScript");
+		assert_eq!(
+			pex.user_flag_names(pex.objects[0].1.user_flags),
+			["conditional"]
+		);
+
+		let object = &pex.objects[0].1;
+		let names = |idx: &[u16]| idx.iter().map(|i| pex.string(*i)).collect::<Vec<_>>();
+		assert_eq!(
+			names(&object.variables.iter().map(|v| v.0).collect::<Vec<_>>()),
+			["::count_var", "::Enabled_var"]
+		);
+		assert_eq!(pex.user_flag_names(object.variables[0].2), ["hidden"]);
+
+		let [enabled, count] = &object.properties[..] else {
+			panic!("{:?}", object.properties);
+		};
+		assert_eq!(
+			enabled.flags,
+			PropertyFlags::READ | PropertyFlags::WRITE | PropertyFlags::AUTOVAR
+		);
+		assert_eq!(count.flags, PropertyFlags::READ);
+		assert_eq!(
+			pex.string(count.read_handler.as_ref().unwrap().return_type_idx),
+			"Int"
+		);
+
+		// Natives get no line table.
+		let debug = pex.debuginfo.as_ref().unwrap();
+		assert_eq!(
+			debug
+				.functions
+				.iter()
+				.map(|d| (pex.string(d.fn_name_idx), d.fn_type, d.instructions.clone()))
+				.collect::<Vec<_>>(),
+			[
+				("Count", FN_TYPE_GETTER, vec![0]),
+				("Run", FN_TYPE_METHOD, vec![3])
+			]
+		);
+		assert_eq!(
+			pex.find_function("TestScript", "Busy", "Run")
+				.map(|f| f.instructions.len()),
+			Some(1)
+		);
+	}
+
+	#[test]
+	fn builds_the_same_every_time() {
+		let build = || script([("Run", FunctionBuilder::new().op("RETURN", vec![Int(1)]))]);
+		assert_eq!(listing(&build(), "Run"), ["RETURN 1"]);
+		assert_eq!(
+			crate::pex::assemble(&build()).unwrap(),
+			crate::pex::assemble(&build()).unwrap()
+		);
+	}
+
+	#[test]
+	fn reports_errors() {
+		let build = |function: FunctionBuilder| builder().function("Run", function).build();
+		assert!(build(FunctionBuilder::new().op("FROB", vec![])).is_err());
+		assert!(build(FunctionBuilder::new().native().op("NOP", vec![])).is_err());
+
+		// Two flags are standard, so 30 more fit and the next one doesn't.
+		let names = (0..31).map(|i| format!("flag{i}")).collect::<Vec<_>>();
+		let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+		assert!(builder().user_flags(&names[..30]).build().is_ok());
+		let error = builder().user_flags(&names).build().unwrap_err();
+		assert!(error.to_string().contains("flag30"), "{error}");
+	}
+}
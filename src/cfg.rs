@@ -0,0 +1,191 @@
+use crate::{
+	disasm,
+	pex::{Function, Instruction, Pex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+	/// Unconditional fallthrough onto the next block.
+	Next,
+	/// Unconditional `JMP`.
+	Jump,
+	/// Taken when the condition of a `JMPT`/`JMPF` is true.
+	True,
+	/// Taken when the condition of a `JMPT`/`JMPF` is false.
+	False,
+}
+
+#[derive(Debug)]
+pub struct Block {
+	/// First instruction of the block.
+	pub start: usize,
+	/// One past the last instruction of the block.
+	pub end: usize,
+	/// (block index, edge kind)
+	pub successors: Vec<(usize, Edge)>,
+}
+
+/// Splits instructions into basic blocks, in instruction order.
+pub fn build(instructions: &[Instruction]) -> Vec<Block> {
+	if instructions.is_empty() {
+		return vec![];
+	}
+
+	let mut leaders = vec![false; instructions.len()];
+	leaders[0] = true;
+
+	for (pc, instr) in instructions.iter().enumerate() {
+		if let Some(leader) = instr.jump_target(pc).and_then(|t| leaders.get_mut(t)) {
+			*leader = true;
+		}
+
+		if (instr.jump_offset().is_some() || !instr.falls_through()) && pc + 1 < instructions.len()
+		{
+			leaders[pc + 1] = true;
+		}
+	}
+
+	let starts = (0..instructions.len())
+		.filter(|&pc| leaders[pc])
+		.collect::<Vec<_>>();
+
+	let block_of = |pc: usize| starts.binary_search(&pc).ok();
+
+	starts
+		.iter()
+		.enumerate()
+		.map(|(i, &start)| {
+			let end = starts.get(i + 1).copied().unwrap_or(instructions.len());
+			let last = &instructions[end - 1];
+			let target = last.jump_target(end - 1).and_then(block_of);
+			let next = (end < instructions.len()).then_some(i + 1);
+
+			let successors = match last {
+				Instruction::JMP(..) => target.map(|t| (t, Edge::Jump)).into_iter().collect(),
+				Instruction::JMPT(..) => [
+					target.map(|t| (t, Edge::True)),
+					next.map(|n| (n, Edge::False)),
+				]
+				.into_iter()
+				.flatten()
+				.collect(),
+				Instruction::JMPF(..) => [
+					target.map(|t| (t, Edge::False)),
+					next.map(|n| (n, Edge::True)),
+				]
+				.into_iter()
+				.flatten()
+				.collect(),
+				Instruction::RETURN(..) => vec![],
+				_ => next.map(|n| (n, Edge::Next)).into_iter().collect(),
+			};
+
+			Block {
+				start,
+				end,
+				successors,
+			}
+		})
+		.collect()
+}
+
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Renders the control-flow graph of a function in Graphviz DOT format.
+pub fn to_dot(pex: &Pex, name: &str, function: &Function) -> String {
+	let mut out = format!("digraph \"{}\" {{\n", escape(name));
+	out.push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+
+	let blocks = build(&function.instructions);
+
+	for (i, block) in blocks.iter().enumerate() {
+		let label = function.instructions[block.start..block.end]
+			.iter()
+			.enumerate()
+			.map(|(offset, instr)| {
+				let pc = block.start + offset;
+				format!("{pc}: {}\\l", escape(&disasm::instruction(pex, instr)))
+			})
+			.collect::<String>();
+
+		out.push_str(&format!("\tb{i} [label=\"{label}\"];\n"));
+	}
+
+	for (i, block) in blocks.iter().enumerate() {
+		for (succ, edge) in &block.successors {
+			let attrs = match edge {
+				Edge::Next | Edge::Jump => "",
+				Edge::True => " [label=\"true\", color=\"darkgreen\"]",
+				Edge::False => " [label=\"false\", color=\"red\"]",
+			};
+
+			out.push_str(&format!("\tb{i} -> b{succ}{attrs};\n"));
+		}
+	}
+
+	out.push_str("}\n");
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture::script, ident, string},
+		model::VariableData::Int,
+	};
+
+	/// `0: NOP`, `1: JMPT x, +3`, `2: JMPF x, +2`, `3: JMP -3` and `4: RETURN "a\"b\c"`.
+	fn fixture() -> Pex {
+		script([(
+			"Run",
+			FunctionBuilder::new()
+				.returns("String")
+				.param("x", "Bool")
+				.op("NOP", vec![])
+				.op("JMPT", vec![ident("x"), Int(3)])
+				.op("JMPF", vec![ident("x"), Int(2)])
+				.op("JMP", vec![Int(-3)])
+				.op("RETURN", vec![string("a\"b\\c")]),
+		)])
+	}
+
+	fn run(pex: &Pex) -> &Function {
+		pex.find_function("TestScript", "", "Run").unwrap()
+	}
+
+	#[test]
+	fn jumps_split_blocks() {
+		let pex = fixture();
+		let blocks = build(&run(&pex).instructions);
+		assert_eq!(
+			blocks
+				.iter()
+				.map(|b| (b.start, b.end, b.successors.clone()))
+				.collect::<Vec<_>>(),
+			[
+				(0, 2, vec![(3, Edge::True), (1, Edge::False)]),
+				(2, 3, vec![(3, Edge::False), (2, Edge::True)]),
+				(3, 4, vec![(0, Edge::Jump)]),
+				(4, 5, vec![]),
+			]
+		);
+	}
+
+	#[test]
+	fn dot() {
+		let pex = fixture();
+		let dot = to_dot(&pex, "Run \"quoted\"", run(&pex));
+		assert!(dot.starts_with("digraph \"Run \\\"quoted\\\"\" {\n"));
+		// Quotes and backslashes in the string literal are escaped once more for DOT.
+		assert!(dot.contains(r#"b3 [label="4: RETURN \"a\\\"b\\\\c\"\l"];"#));
+		assert!(dot.contains("\tb0 -> b3 [label=\"true\", color=\"darkgreen\"];\n"));
+		assert!(dot.contains("\tb0 -> b1 [label=\"false\", color=\"red\"];\n"));
+		assert!(dot.contains("\tb1 -> b3 [label=\"false\", color=\"red\"];\n"));
+		assert!(dot.contains("\tb1 -> b2 [label=\"true\", color=\"darkgreen\"];\n"));
+		assert!(dot.contains("\tb2 -> b0;\n"));
+		assert!(dot.ends_with("}\n"));
+	}
+}
@@ -0,0 +1,630 @@
+use pexspy::{
+	bsa, cfg, coverage, diff, disasm, harness, interp, merge, optimize, pattern, pex, release,
+};
+
+type CliResult = Result<i32, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+Usage: pexSpy [command] [args..]
+
+Without a command, the GUI is opened. merge, rewrite and optimize leave out strings nothing
+uses anymore, renumbering the rest, unless given --keep-strings. Other commands keep the
+string table as it is.
+
+Commands:
+  cfg <file.pex> <object> <function> [state]
+      Prints the control-flow graph of a function in Graphviz DOT format.
+
+  diff <old.pex> <new.pex>
+      Compares two scripts by name. Exits with 1 if they differ.
+
+  merge <base.pex> <ours.pex> <theirs.pex> --out <merged.pex> [--ours <path>].. [--theirs <path>]..
+      Three-way merges two edits of the same script. Functions changed on both sides
+      are conflicts, resolved by path (Object.Function or Object.State.Function).
+      Unresolved conflicts keep our side and exit with 1.
+
+  find <pattern> <files..>
+      Lists instruction sequences matching a pattern, e.g. 'CALLSTATIC Debug, Trace, _, [..]'.
+      Exits with 1 if nothing matched.
+
+  rewrite <pattern> <template> <files..> [--out-dir <dir>]
+      Replaces every match of a pattern, e.g. 'CALLSTATIC Debug, Trace, _, [..]' 'NOP'.
+      Captured $values can be used in the template. Files are overwritten unless --out-dir is given.
+
+  optimize <files..> [--pass <name>].. [--out-dir <dir>]
+      Shrinks functions with the given passes, or all of them: fold-constants, thread-jumps,
+      remove-unreachable, remove-nops and remove-dead-stores. Files are overwritten unless --out-dir is given.
+
+  run <file.pex> <function> [args..] [--stub <name>=<value>]..
+      Runs a function of the script offline and prints what it returns. Arguments and stub
+      values are literals like 5, 1.5, true, None or \"text\". Debug.Trace prints its message,
+      and other native or external calls need a --stub, e.g. --stub Game.GetPlayer=None.
+
+  test <suite.ron..> [--lcov <out.info>]
+      Runs declarative test suites against compiled scripts. Exits with 1 if any case fails.
+      --lcov writes which source lines the tests ran, for scripts with debug info.
+
+  release <files..> [--out-dir <dir>]
+  release <file.pex> --from <earlier.pex> [--out-dir <dir>]
+      Strips debug info, doc strings and the strings only they used, printing the size saved.
+      With --from, brings them back from an earlier build instead, for functions whose
+      instructions match. Exits with 1 if some didn't. Files are overwritten unless --out-dir is given.
+
+  compact <files..> [--sort] [--out-dir <dir>]
+      Drops unused and duplicate strings from the string table. --sort also sorts it, so the same
+      script always assembles the same way. Files are overwritten unless --out-dir is given.
+
+  scrub <files..> [--username <name>] [--machine <name>] [--src-dir <dir>] [--time <time>] [--out-dir <dir>]
+      Replaces the username, machine name, source path and timestamps scripts were compiled with.
+      Names are blanked and only the source's file name is kept unless given. --time takes unix
+      seconds or a UTC date like 2024-01-31 12:00:00, falling back to $SOURCE_DATE_EPOCH, then to
+      a time derived from the script's content. Files are overwritten unless --out-dir is given.
+
+  natives <files..>
+      Lists the native functions scripts declare, the API they expect the game to provide.
+
+  archive <archive.bsa|archive.ba2> [--out-dir <dir>]
+      Lists the scripts in a Skyrim BSA or Fallout 4 / Starfield BA2 archive, or extracts them into --out-dir.
+      Entries whose path would leave --out-dir are skipped, and make it exit with 1.";
+
+fn read_pex(path: &str) -> Result<pex::Pex, Box<dyn std::error::Error>> {
+	let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+	Ok(pex::parse(&bytes).map_err(|e| format!("Failed to parse {path}: {e}"))?)
+}
+
+/// Removes every `--name <value>` pair from `args`, returning the values.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Vec<String>, String> {
+	let mut values = vec![];
+	while let Some(pos) = args.iter().position(|a| a == name) {
+		if pos + 1 >= args.len() {
+			return Err(format!("Missing value for {name}"));
+		}
+
+		values.push(args.remove(pos + 1));
+		args.remove(pos);
+	}
+	Ok(values)
+}
+
+/// Removes every `--name` flag from `args`, returning whether there was one.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+	let len = args.len();
+	args.retain(|a| a != name);
+	args.len() != len
+}
+
+/// What to do with the string table of scripts written by a command, taking `--keep-strings` out of `args`.
+fn take_strings(args: &mut Vec<String>) -> pex::StringTable {
+	if take_flag(args, "--keep-strings") {
+		pex::StringTable::Keep
+	} else {
+		pex::StringTable::Compact
+	}
+}
+
+fn write_pex(
+	path: &str,
+	pex: &pex::Pex,
+	strings: pex::StringTable,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let bytes =
+		pex::assemble_with(pex, strings).map_err(|e| format!("Failed to assemble {path}: {e}"))?;
+	std::fs::write(path, bytes).map_err(|e| format!("Failed to write {path}: {e}"))?;
+	Ok(())
+}
+
+/// Where to write a modified `file`: into `out_dir` if given, otherwise over the original.
+fn out_path(file: &str, out_dir: Option<&String>) -> Result<String, Box<dyn std::error::Error>> {
+	let Some(dir) = out_dir else {
+		return Ok(file.to_owned());
+	};
+
+	let name = std::path::Path::new(file)
+		.file_name()
+		.ok_or("Invalid file name")?;
+	Ok(std::path::Path::new(dir)
+		.join(name)
+		.to_string_lossy()
+		.into_owned())
+}
+
+fn cfg(args: &[String]) -> CliResult {
+	let [file, object, function, rest @ ..] = args else {
+		return Err(USAGE.into());
+	};
+
+	let state = rest.first().map(String::as_str).unwrap_or("");
+
+	let pex = read_pex(file)?;
+	let Some(func) = pex.find_function(object, state, function) else {
+		return Err(format!("No function {object}.{function} in state \"{state}\"").into());
+	};
+
+	print!("{}", cfg::to_dot(&pex, function, func));
+	Ok(0)
+}
+
+fn diff(args: &[String]) -> CliResult {
+	let [old, new] = args else {
+		return Err(USAGE.into());
+	};
+
+	let diff = diff::diff(&read_pex(old)?, &read_pex(new)?);
+	print!("{diff}");
+
+	Ok(if diff.is_empty() { 0 } else { 1 })
+}
+
+fn merge(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let strings = take_strings(&mut args);
+	let ours_paths = take_option(&mut args, "--ours")?;
+	let theirs_paths = take_option(&mut args, "--theirs")?;
+	let [out] = &take_option(&mut args, "--out")?[..] else {
+		return Err(USAGE.into());
+	};
+
+	let [base, ours, theirs] = &args[..] else {
+		return Err(USAGE.into());
+	};
+
+	let ours = read_pex(ours)?;
+	let mut merge = merge::merge(&read_pex(base)?, &ours, &read_pex(theirs)?);
+
+	let mut unresolved = 0;
+	for conflict in &mut merge.conflicts {
+		let path = conflict.path();
+		let matches = |paths: &[String]| paths.iter().any(|p| p.eq_ignore_ascii_case(&path));
+
+		if matches(&theirs_paths) {
+			conflict.resolve(&mut merge.pex, merge::Side::Theirs);
+			println!("conflict {path}: took theirs");
+		} else if matches(&ours_paths) {
+			println!("conflict {path}: took ours");
+		} else {
+			unresolved += 1;
+			println!("conflict {path}: unresolved, kept ours");
+		}
+	}
+
+	for path in &merge.overlapping {
+		println!("changed on both sides {path}: kept ours");
+	}
+	for name in &merge.revived {
+		println!("deleted on one side and changed on the other {name}: kept the changed object");
+	}
+
+	merge.pex.sync_debuginfo(Some(&ours));
+	write_pex(out, &merge.pex, strings)?;
+	Ok(if unresolved > 0 { 1 } else { 0 })
+}
+
+fn find(args: &[String]) -> CliResult {
+	let [pattern, files @ ..] = args else {
+		return Err(USAGE.into());
+	};
+
+	let pattern = pattern::parse(pattern)?;
+	let mut found = 0;
+
+	for file in files {
+		let pex = read_pex(file)?;
+		for (path, function) in pex.function_paths() {
+			for m in pattern.find(&pex, function) {
+				found += 1;
+				println!("{file}: {path} #{}", m.start);
+				for instruction in &function.instructions[m.start..m.start + m.len] {
+					println!("    {}", disasm::instruction(&pex, instruction));
+				}
+			}
+		}
+	}
+
+	Ok(if found > 0 { 0 } else { 1 })
+}
+
+fn rewrite(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let strings = take_strings(&mut args);
+	let out_dir = take_option(&mut args, "--out-dir")?;
+
+	let [pattern, template, files @ ..] = &args[..] else {
+		return Err(USAGE.into());
+	};
+
+	let pattern = pattern::parse(pattern)?;
+	let template = pattern::parse(template)?;
+
+	for file in files {
+		let original = read_pex(file)?;
+		let mut pex = original.clone();
+		let rewrites = pattern::rewrite(&mut pex, &pattern, &template)
+			.map_err(|e| format!("Failed to rewrite {file}: {e}"))?;
+
+		if rewrites.is_empty() {
+			continue;
+		}
+
+		for (path, count) in &rewrites {
+			println!("{file}: {path}: {count} rewrites");
+		}
+
+		pex.sync_debuginfo(Some(&original));
+		write_pex(&out_path(file, out_dir.last())?, &pex, strings)?;
+	}
+
+	Ok(0)
+}
+
+/// Passes open up work for each other, so they're repeated until nothing changes, up to this many times.
+const MAX_OPTIMIZE_ROUNDS: usize = 8;
+
+fn optimize(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let strings = take_strings(&mut args);
+	let out_dir = take_option(&mut args, "--out-dir")?;
+
+	let passes = take_option(&mut args, "--pass")?
+		.iter()
+		.map(|name| {
+			optimize::Pass::ALL
+				.into_iter()
+				.find(|p| p.name() == name)
+				.ok_or_else(|| format!("Unknown pass: {name}"))
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	let passes = if passes.is_empty() {
+		optimize::Pass::ALL.to_vec()
+	} else {
+		passes
+	};
+
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	for file in &args {
+		let original = read_pex(file)?;
+		let mut pex = original.clone();
+		let before = pex
+			.function_paths()
+			.iter()
+			.map(|(_, f)| f.instructions.len())
+			.sum::<usize>();
+
+		let mut reports = vec![];
+		for _ in 0..MAX_OPTIMIZE_ROUNDS {
+			let round = optimize::optimize(&mut pex, &passes);
+			if round.is_empty() {
+				break;
+			}
+			reports.extend(round);
+		}
+
+		if reports.is_empty() {
+			continue;
+		}
+
+		for report in &reports {
+			println!("{file}: {report}");
+		}
+
+		let after = pex
+			.function_paths()
+			.iter()
+			.map(|(_, f)| f.instructions.len())
+			.sum::<usize>();
+		println!("{file}: {before} -> {after} instructions");
+
+		pex.sync_debuginfo(Some(&original));
+		write_pex(&out_path(file, out_dir.last())?, &pex, strings)?;
+	}
+
+	Ok(0)
+}
+
+fn run_function(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let stubs = take_option(&mut args, "--stub")?;
+
+	let [file, function, rest @ ..] = &args[..] else {
+		return Err(USAGE.into());
+	};
+
+	let pex = read_pex(file)?;
+	let mut interp = interp::Interpreter::new(&pex)?;
+
+	interp.register("Debug.Trace", |_, args| {
+		println!("{}", args.first().cloned().unwrap_or_default());
+		Ok(interp::Value::None)
+	});
+
+	for stub in &stubs {
+		let (name, value) = stub
+			.split_once('=')
+			.ok_or_else(|| format!("Expected <name>=<value>, got {stub}"))?;
+		let value = interp::Value::parse(value);
+		interp.register(name, move |_, _| Ok(value.clone()));
+	}
+
+	let result = interp.call(
+		function,
+		rest.iter().map(|a| interp::Value::parse(a)).collect(),
+	)?;
+	println!("{result}");
+	Ok(0)
+}
+
+fn test(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let lcov = take_option(&mut args, "--lcov")?;
+
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	let (mut passed, mut failed) = (0, 0);
+	let mut report = String::new();
+
+	for file in &args {
+		let src =
+			std::fs::read_to_string(file).map_err(|e| format!("Failed to read {file}: {e}"))?;
+		let suite = harness::parse(&src).map_err(|e| format!("Failed to parse {file}: {e}"))?;
+
+		let dir = std::path::Path::new(file)
+			.parent()
+			.unwrap_or(std::path::Path::new(""));
+		let pex = read_pex(&dir.join(&suite.script).to_string_lossy())?;
+
+		let mut coverage = coverage::Coverage::default();
+		let outcomes = harness::run(&pex, &suite, (!lcov.is_empty()).then_some(&mut coverage));
+		if !lcov.is_empty() {
+			report.push_str(&coverage.to_lcov(&pex));
+		}
+
+		for outcome in outcomes {
+			if outcome.passed() {
+				passed += 1;
+				println!("PASS {file}: {}", outcome.name);
+			} else {
+				failed += 1;
+				println!("FAIL {file}: {}", outcome.name);
+				for failure in &outcome.failures {
+					println!("    {failure}");
+				}
+			}
+		}
+	}
+
+	if let Some(path) = lcov.last() {
+		std::fs::write(path, report).map_err(|e| format!("Failed to write {path}: {e}"))?;
+	}
+
+	println!("{passed} passed, {failed} failed");
+	Ok(if failed > 0 { 1 } else { 0 })
+}
+
+fn release(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let out_dir = take_option(&mut args, "--out-dir")?;
+	let from = take_option(&mut args, "--from")?;
+
+	if let Some(earlier) = from.last() {
+		let [file] = &args[..] else {
+			return Err(USAGE.into());
+		};
+
+		let mut pex = read_pex(file)?;
+		let restored = release::restore(&mut pex, &read_pex(earlier)?);
+		println!("{file}: {restored}");
+		for path in &restored.mismatched {
+			println!("{file}: {path}: instructions differ from {earlier}, skipped");
+		}
+
+		write_pex(
+			&out_path(file, out_dir.last())?,
+			&pex,
+			pex::StringTable::Keep,
+		)?;
+		return Ok(if restored.mismatched.is_empty() { 0 } else { 1 });
+	}
+
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	for file in &args {
+		let before = std::fs::metadata(file)
+			.map_err(|e| format!("Failed to read {file}: {e}"))?
+			.len();
+
+		let mut pex = read_pex(file)?;
+		let stripped = release::strip(&mut pex);
+
+		// Stripping already left out the strings it freed.
+		let out = out_path(file, out_dir.last())?;
+		write_pex(&out, &pex, pex::StringTable::Keep)?;
+		let after = std::fs::metadata(&out)
+			.map_err(|e| format!("Failed to read {out}: {e}"))?
+			.len();
+
+		println!(
+			"{file}: {stripped}, {before} -> {after} bytes ({} saved)",
+			before.saturating_sub(after)
+		);
+	}
+
+	Ok(0)
+}
+
+fn compact(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let out_dir = take_option(&mut args, "--out-dir")?;
+	let sort = take_flag(&mut args, "--sort");
+
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	for file in &args {
+		let mut pex = read_pex(file)?;
+		let before = pex.stringtable.len();
+		let dropped = pex.compact_strings(sort);
+		println!("{file}: {before} -> {} strings", before - dropped);
+
+		// Compacted just now, so written as is.
+		write_pex(
+			&out_path(file, out_dir.last())?,
+			&pex,
+			pex::StringTable::Keep,
+		)?;
+	}
+
+	Ok(0)
+}
+
+fn scrub(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let out_dir = take_option(&mut args, "--out-dir")?;
+	let username = take_option(&mut args, "--username")?;
+	let machine = take_option(&mut args, "--machine")?;
+	let src_dir = take_option(&mut args, "--src-dir")?;
+	let time = take_option(&mut args, "--time")?;
+
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	let time = time
+		.last()
+		.cloned()
+		.or_else(|| std::env::var("SOURCE_DATE_EPOCH").ok())
+		.map(|t| {
+			t.trim()
+				.parse()
+				.ok()
+				.or_else(|| pex::parse_timestamp(&t))
+				.ok_or_else(|| format!("Invalid time: {t}"))
+		})
+		.transpose()?;
+
+	let options = release::Scrub {
+		username: username.last().cloned().unwrap_or_default(),
+		machine: machine.last().cloned().unwrap_or_default(),
+		src_dir: src_dir.last().cloned(),
+		time,
+	};
+
+	for file in &args {
+		let mut pex = read_pex(file)?;
+		if release::scrub(&mut pex, &options) {
+			println!(
+				"{file}: scrubbed, compiled {} from {}",
+				pex::format_timestamp(pex.comptime),
+				pex.src
+			);
+		} else {
+			println!("{file}: already scrubbed");
+		}
+
+		write_pex(
+			&out_path(file, out_dir.last())?,
+			&pex,
+			pex::StringTable::Keep,
+		)?;
+	}
+
+	Ok(0)
+}
+
+fn natives(args: &[String]) -> CliResult {
+	if args.is_empty() {
+		return Err(USAGE.into());
+	}
+
+	for file in args {
+		for native in read_pex(file)?.native_functions() {
+			println!("{file}: {}: {native}", native.path);
+		}
+	}
+
+	Ok(0)
+}
+
+fn archive(args: &[String]) -> CliResult {
+	let mut args = args.to_vec();
+	let out_dir = take_option(&mut args, "--out-dir")?;
+
+	let [file] = &args[..] else {
+		return Err(USAGE.into());
+	};
+
+	let bsa = bsa::Bsa::open(file).map_err(|e| format!("Failed to read {file}: {e}"))?;
+	let mut skipped = 0;
+	for entry in bsa.scripts() {
+		let Some(dir) = out_dir.last() else {
+			println!("{}", entry.path);
+			continue;
+		};
+
+		let Some(relative) = entry.relative_path() else {
+			skipped += 1;
+			eprintln!("skipped {}: it would be written outside {dir}", entry.path);
+			continue;
+		};
+
+		let data = bsa.extract(entry)?;
+		let out = std::path::Path::new(dir).join(relative);
+		if let Some(parent) = out.parent() {
+			std::fs::create_dir_all(parent)
+				.map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+		}
+		std::fs::write(&out, data)
+			.map_err(|e| format!("Failed to write {}: {e}", out.display()))?;
+		println!("{}", out.display());
+	}
+
+	Ok(if skipped > 0 { 1 } else { 0 })
+}
+
+/// Subcommands, so anything else on the command line can be opened as a file instead.
+const COMMANDS: &[&str] = &[
+	"cfg", "diff", "merge", "find", "rewrite", "optimize", "run", "test", "release", "compact",
+	"scrub", "natives", "archive", "help", "-h", "--help",
+];
+
+pub fn is_command(arg: &str) -> bool {
+	COMMANDS.contains(&arg)
+}
+
+/// Runs a command line invocation, returning the process exit code.
+pub fn run(args: &[String]) -> i32 {
+	let result = match args[0].as_str() {
+		"cfg" => cfg(&args[1..]),
+		"diff" => diff(&args[1..]),
+		"merge" => merge(&args[1..]),
+		"find" => find(&args[1..]),
+		"rewrite" => rewrite(&args[1..]),
+		"optimize" => optimize(&args[1..]),
+		"run" => run_function(&args[1..]),
+		"test" => test(&args[1..]),
+		"release" => release(&args[1..]),
+		"compact" => compact(&args[1..]),
+		"scrub" => scrub(&args[1..]),
+		"natives" => natives(&args[1..]),
+		"archive" => archive(&args[1..]),
+		"help" | "-h" | "--help" => {
+			println!("{USAGE}");
+			Ok(0)
+		}
+		other => Err(format!("Unknown command: {other}\n\n{USAGE}").into()),
+	};
+
+	match result {
+		Ok(code) => code,
+		Err(e) => {
+			eprintln!("{e}");
+			2
+		}
+	}
+}
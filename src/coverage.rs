@@ -0,0 +1,241 @@
+//! Execution coverage, mapped back to source lines through debug info and written as lcov.
+
+use crate::pex::{Function, Instruction, Pex};
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt::Write,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+	/// Times each instruction ran, by function path.
+	pub hits: HashMap<String, Vec<u32>>,
+	/// Times each conditional jump was taken and not taken, by function path and instruction.
+	pub branches: HashMap<(String, usize), [u32; 2]>,
+	/// Times each function was called, by function path.
+	pub calls: HashMap<String, u32>,
+}
+
+impl Coverage {
+	pub fn call(&mut self, path: &str) {
+		*self.calls.entry(path.to_owned()).or_default() += 1;
+	}
+
+	pub fn hit(&mut self, path: &str, function: &Function, pc: usize) {
+		let hits = self
+			.hits
+			.entry(path.to_owned())
+			.or_insert_with(|| vec![0; function.instructions.len()]);
+
+		if let Some(hit) = hits.get_mut(pc) {
+			*hit += 1;
+		}
+	}
+
+	pub fn branch(&mut self, path: &str, pc: usize, taken: bool) {
+		let counts = self.branches.entry((path.to_owned(), pc)).or_default();
+		counts[!taken as usize] += 1;
+	}
+
+	/// Adds up the coverage of another run of the same script.
+	pub fn merge(&mut self, other: Coverage) {
+		for (path, hits) in other.hits {
+			let ours = self.hits.entry(path).or_default();
+			if ours.len() < hits.len() {
+				ours.resize(hits.len(), 0);
+			}
+			for (a, b) in ours.iter_mut().zip(hits) {
+				*a += b;
+			}
+		}
+
+		for (key, [taken, not_taken]) in other.branches {
+			let ours = self.branches.entry(key).or_default();
+			ours[0] += taken;
+			ours[1] += not_taken;
+		}
+
+		for (path, calls) in other.calls {
+			*self.calls.entry(path).or_default() += calls;
+		}
+	}
+
+	/// Writes an lcov tracefile for the script's source, named by [`Pex::src`].
+	/// Functions without debug info have no lines to report, so they're left out.
+	pub fn to_lcov(&self, pex: &Pex) -> String {
+		let mut out = format!("TN:\nSF:{}\n", pex.src);
+		let mut lines = BTreeMap::<u16, u32>::new();
+		let (mut found, mut hit) = (0, 0);
+		let mut branches = String::new();
+		let (mut branches_found, mut branches_hit) = (0, 0);
+
+		for (path, function, debug) in pex.functions_with_debug() {
+			let Some(debug) = debug else {
+				continue;
+			};
+			if function.instructions.is_empty() {
+				continue;
+			}
+
+			let hits = self.hits.get(&path);
+			let count = |pc: usize| hits.and_then(|h| h.get(pc)).copied().unwrap_or(0);
+
+			let first_line = debug.instructions.iter().min().copied().unwrap_or(0);
+			// Loops jumping back to the start would inflate the hits of the first instruction.
+			let calls = self.calls.get(&path).copied().unwrap_or(0);
+			writeln!(out, "FN:{first_line},{path}").unwrap();
+			writeln!(out, "FNDA:{calls},{path}").unwrap();
+			found += 1;
+			hit += (calls > 0) as usize;
+
+			for (pc, instruction) in function.instructions.iter().enumerate() {
+				let Some(&line) = debug.instructions.get(pc) else {
+					continue;
+				};
+
+				let entry = lines.entry(line).or_default();
+				*entry = (*entry).max(count(pc));
+
+				if matches!(instruction, Instruction::JMPT(..) | Instruction::JMPF(..)) {
+					let counts = self.branches.get(&(path.clone(), pc));
+					for branch in 0..2 {
+						// Branches on lines that never ran are "-" rather than 0.
+						let taken = match counts {
+							Some(c) => c[branch].to_string(),
+							None if count(pc) == 0 => "-".to_owned(),
+							None => "0".to_owned(),
+						};
+						branches_found += 1;
+						branches_hit += counts.is_some_and(|c| c[branch] > 0) as usize;
+						writeln!(branches, "BRDA:{line},{pc},{branch},{taken}").unwrap();
+					}
+				}
+			}
+		}
+
+		writeln!(out, "FNF:{found}\nFNH:{hit}").unwrap();
+		out.push_str(&branches);
+		writeln!(out, "BRF:{branches_found}\nBRH:{branches_hit}").unwrap();
+
+		for (line, count) in &lines {
+			writeln!(out, "DA:{line},{count}").unwrap();
+		}
+		let lines_hit = lines.values().filter(|c| **c > 0).count();
+		writeln!(out, "LF:{}\nLH:{lines_hit}", lines.len()).unwrap();
+
+		out.push_str("end_of_record\n");
+		out
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture, ident},
+		interp::{Interpreter, Value},
+		model::VariableData::{Bool, Int},
+	};
+
+	/// `Count` loops until `::count_var` is 3, `Once` has a branch that's never taken
+	/// and `Never` one that never runs.
+	fn script() -> Pex {
+		let count = FunctionBuilder::new()
+			.returns("Int")
+			.local("::temp0", "Bool")
+			.line(1)
+			.op(
+				"IADD",
+				vec![ident("::count_var"), ident("::count_var"), Int(1)],
+			)
+			.line(2)
+			.op(
+				"CMP_LT",
+				vec![ident("::temp0"), ident("::count_var"), Int(3)],
+			)
+			.op("JMPT", vec![ident("::temp0"), Int(-2)])
+			.line(3)
+			.op("RETURN", vec![ident("::count_var")]);
+		let untaken = |line| {
+			FunctionBuilder::new()
+				.line(line)
+				.op("JMPF", vec![Bool(true), Int(1)])
+				.line(line + 1)
+				.op("RETURN", vec![ident("::NoneVar")])
+		};
+
+		fixture::builder()
+			.debug_info()
+			.variable("::count_var", "Int", Int(0), &[])
+			.function("Count", count)
+			.function("Once", untaken(5))
+			.function("Never", untaken(8))
+			.build()
+			.unwrap()
+	}
+
+	fn run(pex: &Pex) -> Coverage {
+		let mut interp = Interpreter::new(pex).unwrap();
+		interp.record_coverage();
+		assert_eq!(interp.call("Count", vec![]).unwrap(), Value::Int(3));
+		interp.call("Once", vec![]).unwrap();
+		interp.take_coverage()
+	}
+
+	#[test]
+	fn merge() {
+		let pex = script();
+		let mut coverage = run(&pex);
+		assert_eq!(coverage.hits["TestScript.Count"], [3, 3, 3, 1]);
+		assert_eq!(
+			coverage.branches[&("TestScript.Count".to_owned(), 2)],
+			[2, 1]
+		);
+		assert_eq!(coverage.calls["TestScript.Count"], 1);
+
+		coverage.merge(run(&pex));
+		assert_eq!(coverage.hits["TestScript.Count"], [6, 6, 6, 2]);
+		assert_eq!(
+			coverage.branches[&("TestScript.Count".to_owned(), 2)],
+			[4, 2]
+		);
+		assert_eq!(coverage.calls["TestScript.Count"], 2);
+		assert!(!coverage.hits.contains_key("TestScript.Never"));
+	}
+
+	#[test]
+	fn to_lcov() {
+		let pex = script();
+		assert_eq!(
+			run(&pex).to_lcov(&pex),
+			"TN:\n\
+			SF:TestScript.psc\n\
+			FN:1,TestScript.Count\n\
+			FNDA:1,TestScript.Count\n\
+			FN:5,TestScript.Once\n\
+			FNDA:1,TestScript.Once\n\
+			FN:8,TestScript.Never\n\
+			FNDA:0,TestScript.Never\n\
+			FNF:3\n\
+			FNH:2\n\
+			BRDA:2,2,0,2\n\
+			BRDA:2,2,1,1\n\
+			BRDA:5,0,0,0\n\
+			BRDA:5,0,1,1\n\
+			BRDA:8,0,0,-\n\
+			BRDA:8,0,1,-\n\
+			BRF:6\n\
+			BRH:3\n\
+			DA:1,3\n\
+			DA:2,3\n\
+			DA:3,1\n\
+			DA:5,1\n\
+			DA:6,1\n\
+			DA:8,0\n\
+			DA:9,0\n\
+			LF:7\n\
+			LH:5\n\
+			end_of_record\n"
+		);
+	}
+}
@@ -0,0 +1,529 @@
+use crate::{
+	disasm,
+	pex::{Function, ObjectData, Pex, Property, State, UserFlags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+	Object,
+	State,
+	Function,
+	Property,
+	Variable,
+}
+
+impl std::fmt::Display for Kind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Kind::Object => "object",
+			Kind::State => "state",
+			Kind::Function => "function",
+			Kind::Property => "property",
+			Kind::Variable => "variable",
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+	Same(String),
+	Removed(String),
+	Added(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+	Added {
+		kind: Kind,
+		path: String,
+	},
+	Removed {
+		kind: Kind,
+		path: String,
+	},
+	Modified {
+		kind: Kind,
+		path: String,
+		field: &'static str,
+		old: String,
+		new: String,
+	},
+	/// Instruction-level diff of a function present on both sides.
+	Instructions {
+		path: String,
+		lines: Vec<Line>,
+	},
+}
+
+#[derive(Debug, Default)]
+pub struct Diff {
+	pub changes: Vec<Change>,
+}
+
+impl Diff {
+	pub fn is_empty(&self) -> bool {
+		self.changes.is_empty()
+	}
+
+	fn modified(
+		&mut self,
+		kind: Kind,
+		path: &str,
+		field: &'static str,
+		old: impl Into<String>,
+		new: impl Into<String>,
+	) {
+		let (old, new) = (old.into(), new.into());
+		if old != new {
+			self.changes.push(Change::Modified {
+				kind,
+				path: path.to_owned(),
+				field,
+				old,
+				new,
+			});
+		}
+	}
+}
+
+/// Lines of unchanged context shown around each hunk of an instruction diff.
+const CONTEXT: usize = 2;
+
+impl std::fmt::Display for Diff {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for change in &self.changes {
+			match change {
+				Change::Added { kind, path } => writeln!(f, "+ {kind} {path}")?,
+				Change::Removed { kind, path } => writeln!(f, "- {kind} {path}")?,
+				Change::Modified {
+					kind,
+					path,
+					field,
+					old,
+					new,
+				} => writeln!(f, "~ {kind} {path}: {field} {old:?} -> {new:?}")?,
+				Change::Instructions { path, lines } => {
+					writeln!(f, "~ function {path}: instructions")?;
+
+					let near_change = |i: usize| {
+						lines[i.saturating_sub(CONTEXT)..(i + CONTEXT + 1).min(lines.len())]
+							.iter()
+							.any(|l| !matches!(l, Line::Same(_)))
+					};
+
+					let mut skipped = false;
+					for (i, line) in lines.iter().enumerate() {
+						if !near_change(i) {
+							if !skipped {
+								writeln!(f, "    ...")?;
+								skipped = true;
+							}
+							continue;
+						}
+
+						skipped = false;
+						match line {
+							Line::Same(s) => writeln!(f, "      {s}")?,
+							Line::Removed(s) => writeln!(f, "    - {s}")?,
+							Line::Added(s) => writeln!(f, "    + {s}")?,
+						}
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Longest common subsequence diff of two sequences of lines.
+pub fn lines(old: &[String], new: &[String]) -> Vec<Line> {
+	let (n, m) = (old.len(), new.len());
+
+	// lcs[i][j] = length of the LCS of old[i..] and new[j..]
+	let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+	for i in (0..n).rev() {
+		for j in (0..m).rev() {
+			lcs[i][j] = if old[i] == new[j] {
+				lcs[i + 1][j + 1] + 1
+			} else {
+				lcs[i + 1][j].max(lcs[i][j + 1])
+			};
+		}
+	}
+
+	let mut out = Vec::with_capacity(n.max(m));
+	let (mut i, mut j) = (0, 0);
+	while i < n && j < m {
+		if old[i] == new[j] {
+			out.push(Line::Same(old[i].clone()));
+			i += 1;
+			j += 1;
+		} else if lcs[i + 1][j] >= lcs[i][j + 1] {
+			out.push(Line::Removed(old[i].clone()));
+			i += 1;
+		} else {
+			out.push(Line::Added(new[j].clone()));
+			j += 1;
+		}
+	}
+
+	out.extend(old[i..].iter().cloned().map(Line::Removed));
+	out.extend(new[j..].iter().cloned().map(Line::Added));
+	out
+}
+
+/// Pairs up named items from both sides. Papyrus names are case insensitive.
+fn pair<'a, T>(
+	old: impl IntoIterator<Item = (&'a str, T)>,
+	new: impl IntoIterator<Item = (&'a str, T)>,
+) -> Vec<(&'a str, Option<T>, Option<T>)> {
+	let mut out = old
+		.into_iter()
+		.map(|(name, item)| (name, Some(item), None))
+		.collect::<Vec<_>>();
+
+	for (name, item) in new {
+		match out
+			.iter_mut()
+			.find(|(n, _, new)| new.is_none() && n.eq_ignore_ascii_case(name))
+		{
+			Some(entry) => entry.2 = Some(item),
+			None => out.push((name, None, Some(item))),
+		}
+	}
+
+	out
+}
+
+struct Differ<'a> {
+	old: &'a Pex,
+	new: &'a Pex,
+	diff: Diff,
+}
+
+impl Differ<'_> {
+	fn presence<T>(&mut self, kind: Kind, path: &str, old: &Option<T>, new: &Option<T>) -> bool {
+		match (old, new) {
+			(Some(_), None) => self.diff.changes.push(Change::Removed {
+				kind,
+				path: path.to_owned(),
+			}),
+			(None, Some(_)) => self.diff.changes.push(Change::Added {
+				kind,
+				path: path.to_owned(),
+			}),
+			_ => return true,
+		}
+		false
+	}
+
+	fn user_flags(&mut self, kind: Kind, path: &str, old: UserFlags, new: UserFlags) {
+		let old = self.old.user_flag_names(old).join(" ");
+		let new = self.new.user_flag_names(new).join(" ");
+		self.diff.modified(kind, path, "user flags", old, new);
+	}
+
+	fn object(&mut self, path: &str, old: &ObjectData, new: &ObjectData) {
+		let (o, n) = (self.old, self.new);
+
+		self.diff.modified(
+			Kind::Object,
+			path,
+			"parent",
+			o.string(old.parent_name_idx),
+			n.string(new.parent_name_idx),
+		);
+		self.diff.modified(
+			Kind::Object,
+			path,
+			"auto state",
+			o.string(old.auto_state_name_idx),
+			n.string(new.auto_state_name_idx),
+		);
+		self.diff.modified(
+			Kind::Object,
+			path,
+			"doc string",
+			o.string(old.doc_string_idx),
+			n.string(new.doc_string_idx),
+		);
+		self.user_flags(Kind::Object, path, old.user_flags, new.user_flags);
+
+		let variables = pair(
+			old.variables.iter().map(|v| (o.string(v.0), v)),
+			new.variables.iter().map(|v| (n.string(v.0), v)),
+		);
+		for (name, old, new) in variables {
+			let path = format!("{path}.{name}");
+			if !self.presence(Kind::Variable, &path, &old, &new) {
+				continue;
+			}
+
+			let (old, new) = (old.unwrap(), new.unwrap());
+			self.diff.modified(
+				Kind::Variable,
+				&path,
+				"type",
+				o.string(old.1),
+				n.string(new.1),
+			);
+			self.user_flags(Kind::Variable, &path, old.2, new.2);
+			self.diff.modified(
+				Kind::Variable,
+				&path,
+				"initial value",
+				disasm::variable_data(o, &old.3),
+				disasm::variable_data(n, &new.3),
+			);
+		}
+
+		let properties = pair(
+			old.properties.iter().map(|p| (o.string(p.name_idx), p)),
+			new.properties.iter().map(|p| (n.string(p.name_idx), p)),
+		);
+		for (name, old, new) in properties {
+			let path = format!("{path}.{name}");
+			if self.presence(Kind::Property, &path, &old, &new) {
+				self.property(&path, old.unwrap(), new.unwrap());
+			}
+		}
+
+		let states = pair(
+			old.states.iter().map(|s| (o.string(s.name_idx), s)),
+			new.states.iter().map(|s| (n.string(s.name_idx), s)),
+		);
+		for (name, old, new) in states {
+			let path = if name.is_empty() {
+				path.to_owned()
+			} else {
+				format!("{path}.{name}")
+			};
+
+			if self.presence(Kind::State, &path, &old, &new) {
+				self.state(&path, old.unwrap(), new.unwrap());
+			}
+		}
+	}
+
+	fn property(&mut self, path: &str, old: &Property, new: &Property) {
+		let (o, n) = (self.old, self.new);
+
+		self.diff.modified(
+			Kind::Property,
+			path,
+			"type",
+			o.string(old.type_idx),
+			n.string(new.type_idx),
+		);
+		self.diff.modified(
+			Kind::Property,
+			path,
+			"doc string",
+			o.string(old.doc_string_idx),
+			n.string(new.doc_string_idx),
+		);
+		self.diff.modified(
+			Kind::Property,
+			path,
+			"flags",
+			old.flags.to_string(),
+			new.flags.to_string(),
+		);
+		self.user_flags(Kind::Property, path, old.user_flags, new.user_flags);
+		self.diff.modified(
+			Kind::Property,
+			path,
+			"auto var",
+			old.auto_var_name.map(|i| o.string(i)).unwrap_or_default(),
+			new.auto_var_name.map(|i| n.string(i)).unwrap_or_default(),
+		);
+
+		let handlers = [
+			("Get", &old.read_handler, &new.read_handler),
+			("Set", &old.write_handler, &new.write_handler),
+		];
+		for (name, old, new) in handlers {
+			let path = format!("{path}.{name}");
+			if self.presence(Kind::Function, &path, old, new)
+				&& let (Some(old), Some(new)) = (old, new)
+			{
+				self.function(&path, old, new);
+			}
+		}
+	}
+
+	fn state(&mut self, path: &str, old: &State, new: &State) {
+		let (o, n) = (self.old, self.new);
+
+		let functions = pair(
+			old.functions.iter().map(|(name, f)| (o.string(*name), f)),
+			new.functions.iter().map(|(name, f)| (n.string(*name), f)),
+		);
+		for (name, old, new) in functions {
+			let path = format!("{path}.{name}");
+			if self.presence(Kind::Function, &path, &old, &new) {
+				self.function(&path, old.unwrap(), new.unwrap());
+			}
+		}
+	}
+
+	fn function(&mut self, path: &str, old: &Function, new: &Function) {
+		let (o, n) = (self.old, self.new);
+		let name = path.rsplit('.').next().unwrap_or_default();
+
+		self.diff.modified(
+			Kind::Function,
+			path,
+			"signature",
+			disasm::signature(o, name, old),
+			disasm::signature(n, name, new),
+		);
+		self.diff.modified(
+			Kind::Function,
+			path,
+			"flags",
+			old.flags.to_string(),
+			new.flags.to_string(),
+		);
+		self.user_flags(Kind::Function, path, old.user_flags, new.user_flags);
+		self.diff.modified(
+			Kind::Function,
+			path,
+			"doc string",
+			o.string(old.doc_string_idx),
+			n.string(new.doc_string_idx),
+		);
+
+		let locals = |pex: &Pex, f: &Function| {
+			f.locals
+				.iter()
+				.map(|l| format!("{} {}", pex.string(l.type_idx), pex.string(l.name_idx)))
+				.collect::<Vec<_>>()
+				.join(", ")
+		};
+		self.diff.modified(
+			Kind::Function,
+			path,
+			"locals",
+			locals(o, old),
+			locals(n, new),
+		);
+
+		let old = old
+			.instructions
+			.iter()
+			.map(|i| disasm::instruction(o, i))
+			.collect::<Vec<_>>();
+		let new = new
+			.instructions
+			.iter()
+			.map(|i| disasm::instruction(n, i))
+			.collect::<Vec<_>>();
+
+		if old != new {
+			self.diff.changes.push(Change::Instructions {
+				path: path.to_owned(),
+				lines: lines(&old, &new),
+			});
+		}
+	}
+}
+
+/// Compares two scripts by resolved names rather than string table indices.
+pub fn diff(old: &Pex, new: &Pex) -> Diff {
+	let mut differ = Differ {
+		old,
+		new,
+		diff: Diff::default(),
+	};
+
+	let objects = pair(
+		old.objects.iter().map(|(name, o)| (old.string(*name), o)),
+		new.objects.iter().map(|(name, o)| (new.string(*name), o)),
+	);
+	for (name, o, n) in objects {
+		if differ.presence(Kind::Object, name, &o, &n) {
+			differ.object(name, o.unwrap(), n.unwrap());
+		}
+	}
+
+	differ.diff
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture::builder, ident},
+		model::VariableData::Int,
+	};
+
+	fn script(value: i32, ret: i32, extra: bool) -> Pex {
+		let mut builder = builder()
+			.variable("::count_var", "Int", Int(value), &[])
+			.function(
+				"Count",
+				FunctionBuilder::new()
+					.returns("Int")
+					.op("RETURN", vec![ident("::count_var")])
+					.op("RETURN", vec![Int(ret)]),
+			);
+		if extra {
+			builder = builder.function("Extra", FunctionBuilder::new());
+		}
+		builder.build().unwrap()
+	}
+
+	#[test]
+	fn same_script() {
+		assert!(diff(&script(1, 2, false), &script(1, 2, false)).is_empty());
+	}
+
+	#[test]
+	fn line_diff() {
+		let strings = |s: &[&str]| s.iter().map(|s| (*s).to_owned()).collect::<Vec<_>>();
+		let lines = lines(&strings(&["a", "b", "c"]), &strings(&["a", "x", "c"]));
+
+		assert_eq!(
+			lines,
+			[
+				Line::Same("a".into()),
+				Line::Removed("b".into()),
+				Line::Added("x".into()),
+				Line::Same("c".into()),
+			]
+		);
+	}
+
+	#[test]
+	fn changes() {
+		let changes = diff(&script(1, 2, true), &script(5, 3, false)).changes;
+
+		assert!(changes.iter().any(|c| matches!(
+			c,
+			Change::Modified { kind: Kind::Variable, field: "initial value", old, new, .. }
+				if old == "1" && new == "5"
+		)));
+		assert!(changes.iter().any(|c| matches!(
+			c,
+			Change::Removed { kind: Kind::Function, path } if path.ends_with(".Extra")
+		)));
+
+		let Some(Change::Instructions { path, lines }) = changes
+			.iter()
+			.find(|c| matches!(c, Change::Instructions { .. }))
+		else {
+			panic!("no instruction diff in {changes:?}");
+		};
+		assert!(path.ends_with(".Count"));
+		assert_eq!(
+			lines,
+			&[
+				Line::Same("RETURN ::count_var".into()),
+				Line::Removed("RETURN 2".into()),
+				Line::Added("RETURN 3".into()),
+			]
+		);
+	}
+}
@@ -0,0 +1,69 @@
+use crate::pex::{Function, Instruction, Pex, VariableData};
+
+/// Renders an operand with identifiers resolved and string literals quoted.
+pub fn variable_data(pex: &Pex, value: &VariableData) -> String {
+	match value {
+		VariableData::Null => "None".to_owned(),
+		VariableData::Ident(i) => pex.string(*i).to_owned(),
+		VariableData::String(s) => format!("{:?}", pex.string(*s)),
+		VariableData::Int(i) => i.to_string(),
+		VariableData::Float(f) => format!("{f:?}"),
+		VariableData::Bool(b) => b.to_string(),
+	}
+}
+
+/// Renders an instruction as `MNEMONIC a, b, [args..]`.
+pub fn instruction(pex: &Pex, instr: &Instruction) -> String {
+	let (operands, varargs) = instr.operands();
+
+	let mut parts = operands
+		.iter()
+		.map(|op| variable_data(pex, op))
+		.collect::<Vec<_>>();
+
+	if matches!(
+		instr,
+		Instruction::CALLMETHOD(..) | Instruction::CALLPARENT(..) | Instruction::CALLSTATIC(..)
+	) {
+		let args = varargs
+			.iter()
+			.map(|op| variable_data(pex, op))
+			.collect::<Vec<_>>();
+		parts.push(format!("[{}]", args.join(", ")));
+	}
+
+	if parts.is_empty() {
+		instr.mnemonic().to_owned()
+	} else {
+		format!("{} {}", instr.mnemonic(), parts.join(", "))
+	}
+}
+
+/// [`signature`] followed by the function's flags and user flags as Papyrus writes them,
+/// e.g. `Actor GetPlayer() Global Native`.
+pub fn declaration(pex: &Pex, name: &str, function: &Function) -> String {
+	let mut out = signature(pex, name, function);
+	let user_flags = pex.user_flag_names(function.user_flags);
+	for flag in function.flags.names().into_iter().chain(user_flags) {
+		let mut chars = flag.chars();
+		out.push(' ');
+		out.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+		out.extend(chars);
+	}
+	out
+}
+
+/// Renders a function's signature as `ReturnType Name(Type param, ..)`.
+pub fn signature(pex: &Pex, name: &str, function: &Function) -> String {
+	let params = function
+		.params
+		.iter()
+		.map(|p| format!("{} {}", pex.string(p.type_idx), pex.string(p.name_idx)))
+		.collect::<Vec<_>>();
+
+	format!(
+		"{} {name}({})",
+		pex.string(function.return_type_idx),
+		params.join(", ")
+	)
+}
@@ -0,0 +1,337 @@
+//! Editing function bodies without breaking control flow or debug line tables.
+
+use crate::pex::{
+	DebugFunction, FN_TYPE_GETTER, FN_TYPE_METHOD, FN_TYPE_SETTER, Function, Instruction, Pex,
+	function_path,
+};
+use std::ops::Range;
+
+/// A function being edited, along with its debug line table if the script has one.
+/// Change instructions through the editor's methods, or jumps and lines can get out of sync.
+pub struct Editor<'a> {
+	pub function: &'a mut Function,
+	pub lines: Option<&'a mut Vec<u16>>,
+}
+
+impl<'a> Editor<'a> {
+	pub fn new(function: &'a mut Function, debug: Option<&'a mut DebugFunction>) -> Self {
+		Self {
+			function,
+			lines: debug.map(|d| &mut d.instructions),
+		}
+	}
+
+	/// Replaces `range` with `replacement`, returning the removed instructions.
+	///
+	/// Jumps outside of the range keep their targets, and jumps into it land on the start of the replacement.
+	/// Jumps within `replacement` are relative to it and left alone.
+	/// New instructions take the line of the first one replaced, or the one before it when inserting.
+	///
+	/// Panics if the range is out of bounds, like [`Vec::splice`].
+	pub fn splice(
+		&mut self,
+		range: Range<usize>,
+		replacement: Vec<Instruction>,
+	) -> Vec<Instruction> {
+		let Range { start, end } = range;
+		let delta = replacement.len() as isize - (end - start) as isize;
+
+		// Where a jump lands. Landing on `start` runs the replacement first.
+		let target = |pc: usize| -> usize {
+			if pc <= start {
+				pc
+			} else if pc < end {
+				start
+			} else {
+				pc.saturating_add_signed(delta)
+			}
+		};
+		// Where a kept instruction ends up. One at `start` when inserting moves past the inserted ones.
+		let position = |pc: usize| -> usize {
+			if pc >= end {
+				pc.saturating_add_signed(delta)
+			} else {
+				pc
+			}
+		};
+
+		for (pc, instruction) in self.function.instructions.iter_mut().enumerate() {
+			if range.contains(&pc) {
+				continue;
+			}
+
+			if let Some(to) = instruction.jump_target(pc) {
+				instruction.set_jump_target(position(pc), target(to));
+			}
+		}
+
+		if let Some(lines) = self.lines.as_deref_mut() {
+			let line = lines
+				.get(start)
+				.or_else(|| start.checked_sub(1).and_then(|i| lines.get(i)))
+				.copied()
+				.unwrap_or_default();
+
+			// Line tables of hand edited scripts can be short, so don't trust them to cover the range.
+			let len = lines.len();
+			lines.splice(
+				start.min(len)..end.min(len),
+				std::iter::repeat_n(line, replacement.len()),
+			);
+		}
+
+		self.function
+			.instructions
+			.splice(range, replacement)
+			.collect()
+	}
+
+	/// Inserts instructions before `at`. Jumps to `at` now run the inserted instructions first.
+	pub fn insert(&mut self, at: usize, instructions: Vec<Instruction>) {
+		self.splice(at..at, instructions);
+	}
+
+	/// Removes a range of instructions. Jumps into it land on whatever follows.
+	pub fn remove(&mut self, range: Range<usize>) -> Vec<Instruction> {
+		self.splice(range, vec![])
+	}
+
+	/// Replaces a single instruction, keeping its line.
+	pub fn replace(&mut self, at: usize, instruction: Instruction) -> Instruction {
+		self.splice(at..at + 1, vec![instruction]).remove(0)
+	}
+
+	/// Replaces the whole function, e.g. after it was edited as text. Its jumps are taken as they are.
+	/// Instructions found unchanged keep their lines, and new ones take the line of the one before them.
+	pub fn set_function(&mut self, function: Function) {
+		if let Some(lines) = self.lines.as_deref_mut() {
+			*lines = realign_lines(&self.function.instructions, &function.instructions, lines);
+		}
+		*self.function = function;
+	}
+}
+
+/// Line table for `new`, carried over from `lines` of `old` through the longest common subsequence.
+pub(crate) fn realign_lines(old: &[Instruction], new: &[Instruction], lines: &[u16]) -> Vec<u16> {
+	let (n, m) = (old.len(), new.len());
+
+	// lcs[i][j] = length of the LCS of old[i..] and new[j..]
+	let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+	for i in (0..n).rev() {
+		for j in (0..m).rev() {
+			lcs[i][j] = if old[i] == new[j] {
+				lcs[i + 1][j + 1] + 1
+			} else {
+				lcs[i + 1][j].max(lcs[i][j + 1])
+			};
+		}
+	}
+
+	let line = |i: usize| lines.get(i).or(lines.last()).copied().unwrap_or_default();
+
+	let mut out = Vec::with_capacity(m);
+	let (mut i, mut j) = (0, 0);
+	while j < m {
+		if i < n && old[i] == new[j] {
+			out.push(line(i));
+			i += 1;
+			j += 1;
+		} else if i < n && lcs[i + 1][j] >= lcs[i][j + 1] {
+			i += 1;
+		} else {
+			// Inserted before anything that stayed, so there's no line before it yet.
+			out.push(out.last().copied().unwrap_or_else(|| line(i)));
+			j += 1;
+		}
+	}
+	out
+}
+
+/// An editor for every function, with the same paths and order as [`Pex::function_paths`].
+pub fn editors(pex: &mut Pex) -> Vec<(String, Editor<'_>)> {
+	let Pex {
+		stringtable,
+		objects,
+		debuginfo,
+		..
+	} = pex;
+
+	let string = |idx: u16| {
+		stringtable
+			.get(idx as usize)
+			.map(String::as_str)
+			.unwrap_or_default()
+	};
+
+	let mut debug = debuginfo
+		.iter_mut()
+		.flat_map(|d| &mut d.functions)
+		.map(Some)
+		.collect::<Vec<_>>();
+
+	// Property handlers are named after their property, and aren't tied to a state.
+	let mut take_debug = |object: &str, state: &str, name: &str, fn_type: u8| {
+		debug
+			.iter_mut()
+			.find(|d| {
+				d.as_ref().is_some_and(|d| {
+					d.fn_type == fn_type
+						&& string(d.obj_name_idx).eq_ignore_ascii_case(object)
+						&& string(d.fn_name_idx).eq_ignore_ascii_case(name)
+						&& (fn_type != FN_TYPE_METHOD
+							|| string(d.state_name_idx).eq_ignore_ascii_case(state))
+				})
+			})
+			.and_then(Option::take)
+	};
+
+	let mut out = vec![];
+
+	for (obj_name, obj) in objects {
+		let obj_name = string(*obj_name);
+
+		for state in &mut obj.states {
+			let state_name = string(state.name_idx);
+
+			for (name_idx, function) in &mut state.functions {
+				let name = string(*name_idx);
+				let debug = take_debug(obj_name, state_name, name, FN_TYPE_METHOD);
+				out.push((
+					function_path(obj_name, state_name, name),
+					Editor::new(function, debug),
+				));
+			}
+		}
+
+		for property in &mut obj.properties {
+			let prop_name = string(property.name_idx);
+			let handlers = [
+				("Get", FN_TYPE_GETTER, &mut property.read_handler),
+				("Set", FN_TYPE_SETTER, &mut property.write_handler),
+			];
+
+			for (kind, fn_type, handler) in handlers {
+				if let Some(function) = handler {
+					let debug = take_debug(obj_name, "", prop_name, fn_type);
+					out.push((
+						format!("{obj_name}.{prop_name}.{kind}"),
+						Editor::new(function, debug),
+					));
+				}
+			}
+		}
+	}
+
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture::builder},
+		model::VariableData::{Bool, Int},
+	};
+
+	/// `0: JMP +3`, two `NOP`s, `3: RETURN 1` and `4: JMPT true, -4`, on lines 1 to 5.
+	fn script() -> Pex {
+		builder()
+			.debug_info()
+			.function(
+				"Run",
+				FunctionBuilder::new()
+					.returns("Int")
+					.line(1)
+					.op("JMP", vec![Int(3)])
+					.line(2)
+					.op("NOP", vec![])
+					.line(3)
+					.op("NOP", vec![])
+					.line(4)
+					.op("RETURN", vec![Int(1)])
+					.line(5)
+					.op("JMPT", vec![Bool(true), Int(-4)]),
+			)
+			.build()
+			.unwrap()
+	}
+
+	fn with_editor(pex: &mut Pex, f: impl FnOnce(&mut Editor)) {
+		let (_, mut editor) = editors(pex).into_iter().next().unwrap();
+		f(&mut editor);
+	}
+
+	fn targets(editor: &Editor) -> Vec<Option<usize>> {
+		(editor.function.instructions.iter().enumerate())
+			.map(|(pc, i)| i.jump_target(pc))
+			.collect()
+	}
+
+	#[test]
+	fn insert_keeps_jumps() {
+		let mut pex = script();
+		with_editor(&mut pex, |editor| {
+			editor.insert(2, vec![Instruction::NOP]);
+
+			assert_eq!(targets(editor), [Some(4), None, None, None, None, Some(0)]);
+			assert_eq!(**editor.lines.as_ref().unwrap(), [1, 2, 3, 3, 4, 5]);
+		});
+	}
+
+	#[test]
+	fn insert_at_jump_target() {
+		let mut pex = script();
+		with_editor(&mut pex, |editor| {
+			editor.insert(3, vec![Instruction::NOP, Instruction::NOP]);
+
+			// Jumps to the old instruction 3 run the inserted ones first.
+			assert_eq!(targets(editor)[0], Some(3));
+			assert_eq!(targets(editor)[6], Some(0));
+			assert_eq!(**editor.lines.as_ref().unwrap(), [1, 2, 3, 4, 4, 4, 5]);
+		});
+	}
+
+	#[test]
+	fn insert_at_jump() {
+		let mut pex = script();
+		with_editor(&mut pex, |editor| {
+			editor.insert(4, vec![Instruction::NOP]);
+			editor.insert(0, vec![Instruction::NOP]);
+
+			// Both jumps moved and still reach `RETURN 1`, and the start of the function,
+			// which now runs the inserted NOP first.
+			assert_eq!(
+				targets(editor),
+				[None, Some(4), None, None, None, None, Some(0)]
+			);
+			assert_eq!(**editor.lines.as_ref().unwrap(), [1, 1, 2, 3, 4, 5, 5]);
+		});
+	}
+
+	#[test]
+	fn remove_jump_target() {
+		let mut pex = script();
+		with_editor(&mut pex, |editor| {
+			editor.remove(0..1);
+
+			// The backward jump lost its target, so it lands on what follows.
+			assert_eq!(targets(editor), [None, None, None, Some(0)]);
+			assert_eq!(**editor.lines.as_ref().unwrap(), [2, 3, 4, 5]);
+		});
+	}
+
+	#[test]
+	fn set_function_realigns_lines() {
+		let mut pex = script();
+		with_editor(&mut pex, |editor| {
+			let mut function = editor.function.clone();
+			function
+				.instructions
+				.insert(2, Instruction::RETURN(crate::pex::VariableData::Int(2)));
+			function.instructions.remove(1);
+			editor.set_function(function);
+
+			assert_eq!(**editor.lines.as_ref().unwrap(), [1, 1, 3, 4, 5]);
+		});
+	}
+}
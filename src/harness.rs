@@ -0,0 +1,392 @@
+//! Declarative unit tests for compiled scripts, run with [`crate::interp`].
+//!
+//! ```ron
+//! (
+//!     script: "MyQuest.pex",
+//!     stubs: { "Game.GetPlayer": "None" },
+//!     cases: [
+//!         (
+//!             name: "activating advances the quest",
+//!             call: "OnActivate",
+//!             args: ["None"],
+//!             calls: [(name: "SetStage", args: ["20"])],
+//!         ),
+//!     ],
+//! )
+//! ```
+//!
+//! Values are literals like `5`, `1.5`, `true`, `None` or text. Calls that aren't stubbed return `None`.
+//! Optional fields are written without `Some(..)`, e.g. `returns: "5"`.
+
+use crate::{
+	coverage::Coverage,
+	interp::{InterpResult, Interpreter, Value},
+	pex::Pex,
+};
+use nanoserde::DeRon;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+#[derive(Debug, Clone, DeRon)]
+pub struct Suite {
+	/// Path of the script, relative to the suite file.
+	pub script: String,
+	/// Stub return values shared by every case, by the names [`Interpreter::register`] uses.
+	#[nserde(default)]
+	pub stubs: HashMap<String, String>,
+	pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Clone, DeRon)]
+pub struct Case {
+	pub name: String,
+	/// Function to call, in the script's auto state unless `state` is given.
+	pub call: String,
+	#[nserde(default)]
+	pub args: Vec<String>,
+	// A plain `default` gives an `else { None }` that clippy wants written with `?`.
+	#[nserde(default_with = "Option::default")]
+	pub state: Option<String>,
+	/// Overrides the suite's stubs.
+	#[nserde(default)]
+	pub stubs: HashMap<String, String>,
+	/// Script variables to set before the call.
+	#[nserde(default)]
+	pub variables: HashMap<String, String>,
+
+	#[nserde(default_with = "Option::default")]
+	pub returns: Option<String>,
+	/// Calls that have to happen in this order. Other calls can happen in between.
+	#[nserde(default)]
+	pub calls: Vec<ExpectedCall>,
+	/// Script variables after the call.
+	#[nserde(default)]
+	pub expect_variables: HashMap<String, String>,
+	#[nserde(default_with = "Option::default")]
+	pub expect_state: Option<String>,
+}
+
+#[derive(Debug, Clone, DeRon)]
+pub struct ExpectedCall {
+	pub name: String,
+	/// Any arguments match when left out.
+	#[nserde(default_with = "Option::default")]
+	pub args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+	pub name: String,
+	pub failures: Vec<String>,
+}
+
+impl Outcome {
+	pub fn passed(&self) -> bool {
+		self.failures.is_empty()
+	}
+}
+
+pub fn parse(src: &str) -> Result<Suite, nanoserde::DeRonErr> {
+	Suite::deserialize_ron(src)
+}
+
+/// Shows a value the way it'd be written in a suite, with strings quoted.
+fn show(value: &Value) -> String {
+	match value {
+		Value::String(s) => format!("{s:?}"),
+		v => v.to_string(),
+	}
+}
+
+fn show_call((name, args): &(String, Vec<Value>)) -> String {
+	let args = args.iter().map(show).collect::<Vec<_>>();
+	format!("{name}({})", args.join(", "))
+}
+
+/// Runs every case of a suite against a fresh interpreter.
+/// When given `coverage`, what the cases ran is added to it.
+pub fn run(pex: &Pex, suite: &Suite, mut coverage: Option<&mut Coverage>) -> Vec<Outcome> {
+	suite
+		.cases
+		.iter()
+		.map(|case| Outcome {
+			name: case.name.clone(),
+			failures: match run_case(pex, suite, case, coverage.as_deref_mut()) {
+				Ok(failures) => failures,
+				Err(e) => vec![e.to_string()],
+			},
+		})
+		.collect()
+}
+
+fn run_case(
+	pex: &Pex,
+	suite: &Suite,
+	case: &Case,
+	coverage: Option<&mut Coverage>,
+) -> InterpResult<Vec<String>> {
+	let mut interp = Interpreter::new(pex)?;
+	if coverage.is_some() {
+		interp.record_coverage();
+	}
+
+	let stubs = suite
+		.stubs
+		.iter()
+		.chain(&case.stubs)
+		.map(|(name, value)| (name.to_lowercase(), Value::parse(value)))
+		.collect::<HashMap<_, _>>();
+
+	let calls = Rc::new(RefCell::new(vec![]));
+	let recorded = calls.clone();
+	interp.set_fallback(move |name, _, args| {
+		recorded.borrow_mut().push((name.to_owned(), args.to_vec()));
+		Ok(stubs.get(&name.to_lowercase()).cloned().unwrap_or_default())
+	});
+
+	if let Some(state) = &case.state {
+		interp.state = state.clone();
+	}
+	for (name, value) in &case.variables {
+		interp.set_variable(name, Value::parse(value))?;
+	}
+
+	let args = case.args.iter().map(|a| Value::parse(a)).collect();
+	let result = interp.call(&case.call, args);
+
+	// Failing cases still ran something.
+	if let Some(coverage) = coverage {
+		coverage.merge(interp.take_coverage());
+	}
+	let result = result?;
+
+	let mut failures = vec![];
+
+	if let Some(expected) = &case.returns
+		&& Value::parse(expected) != result
+	{
+		failures.push(format!("returned {}, expected {expected}", show(&result)));
+	}
+
+	let calls = calls.borrow();
+	let mut actual = calls.iter();
+	for expected in &case.calls {
+		let args = expected
+			.args
+			.as_ref()
+			.map(|args| args.iter().map(|a| Value::parse(a)).collect::<Vec<_>>());
+
+		let found = actual.any(|(name, actual_args)| {
+			name.eq_ignore_ascii_case(&expected.name)
+				&& args.as_ref().is_none_or(|args| args == actual_args)
+		});
+
+		if !found {
+			let wanted = match &args {
+				Some(args) => show_call(&(expected.name.clone(), args.clone())),
+				None => format!("{}(..)", expected.name),
+			};
+			let made = calls.iter().map(show_call).collect::<Vec<_>>();
+			failures.push(format!(
+				"expected a call to {wanted} in order, calls made: {}",
+				made.join(", ")
+			));
+			break;
+		}
+	}
+
+	for (name, expected) in &case.expect_variables {
+		match interp.variable(name) {
+			Some(value) if *value == Value::parse(expected) => {}
+			Some(value) => failures.push(format!("{name} is {}, expected {expected}", show(value))),
+			None => failures.push(format!("no variable {name}")),
+		}
+	}
+
+	if let Some(expected) = &case.expect_state
+		&& !interp.state.eq_ignore_ascii_case(expected)
+	{
+		failures.push(format!(
+			"in state {:?}, expected {expected:?}",
+			interp.state
+		));
+	}
+
+	Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture, ident, string},
+		model::VariableData::Int,
+	};
+
+	/// Adds `Game.GetValue()` to `::count_var`, traces "a" and "b", goes to `Done` and returns the count.
+	fn script() -> Pex {
+		let trace = |text| {
+			(
+				vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+				vec![string(text)],
+			)
+		};
+		let (trace_a, trace_a_args) = trace("a");
+		let (trace_b, trace_b_args) = trace("b");
+
+		let run = FunctionBuilder::new()
+			.returns("Int")
+			.local("::temp0", "Int")
+			.call(
+				"CALLSTATIC",
+				vec![ident("Game"), ident("GetValue"), ident("::temp0")],
+				vec![],
+			)
+			.call("CALLSTATIC", trace_a, trace_a_args)
+			.call("CALLSTATIC", trace_b, trace_b_args)
+			.op(
+				"IADD",
+				vec![ident("::count_var"), ident("::count_var"), ident("::temp0")],
+			)
+			.call(
+				"CALLMETHOD",
+				vec![ident("GoToState"), ident("self"), ident("::NoneVar")],
+				vec![string("Done")],
+			)
+			.op("RETURN", vec![ident("::count_var")]);
+
+		fixture::builder()
+			.variable("::count_var", "Int", Int(0), &[])
+			.function("Run", run)
+			.build()
+			.unwrap()
+	}
+
+	/// Runs one case of a suite that stubs `Game.GetValue` to 1.
+	fn run_case(case: &str) -> Vec<String> {
+		let suite = parse(&format!(
+			r#"(script: "TestScript.pex", stubs: {{ "Game.GetValue": "1" }}, cases: [{case}])"#
+		))
+		.unwrap();
+		let mut outcomes = run(&script(), &suite, None);
+		assert_eq!(outcomes.len(), 1);
+		outcomes.remove(0).failures
+	}
+
+	#[test]
+	fn parse_optional_fields() {
+		let suite = parse(
+			r#"(
+				script: "TestScript.pex",
+				cases: [
+					(name: "bare", call: "Run"),
+					(
+						name: "full",
+						call: "Run",
+						state: "Busy",
+						returns: "5",
+						calls: [(name: "Debug.Trace"), (name: "Debug.Trace", args: ["b"])],
+						expect_state: "Done",
+					),
+				],
+			)"#,
+		)
+		.unwrap();
+		assert!(suite.stubs.is_empty());
+
+		let [bare, full] = &suite.cases[..] else {
+			panic!("expected two cases");
+		};
+		assert_eq!(bare.state, None);
+		assert_eq!(bare.returns, None);
+		assert_eq!(bare.expect_state, None);
+		assert!(bare.args.is_empty() && bare.calls.is_empty());
+
+		assert_eq!(full.state.as_deref(), Some("Busy"));
+		assert_eq!(full.returns.as_deref(), Some("5"));
+		assert_eq!(full.expect_state.as_deref(), Some("Done"));
+		assert_eq!(full.calls[0].args, None);
+		assert_eq!(full.calls[1].args, Some(vec!["b".to_owned()]));
+	}
+
+	#[test]
+	fn passing_case() {
+		let failures = run_case(
+			r#"(
+				name: "passes",
+				call: "Run",
+				variables: { "::count_var": "2" },
+				returns: "3",
+				calls: [(name: "game.getvalue"), (name: "Debug.Trace", args: ["b"])],
+				expect_variables: { "::count_var": "3" },
+				expect_state: "done",
+			)"#,
+		);
+		assert_eq!(failures, Vec::<String>::new());
+	}
+
+	#[test]
+	fn failing_case() {
+		let mut failures = run_case(
+			r#"(
+				name: "fails",
+				call: "Run",
+				returns: "2",
+				expect_variables: { "::count_var": "2", "::missing_var": "0" },
+				expect_state: "Busy",
+			)"#,
+		);
+		failures.sort();
+		assert_eq!(
+			failures,
+			[
+				"::count_var is 1, expected 2",
+				"in state \"Done\", expected \"Busy\"",
+				"no variable ::missing_var",
+				"returned 1, expected 2",
+			]
+		);
+
+		let failures = run_case(r#"(name: "errors", call: "Missing")"#);
+		assert_eq!(failures.len(), 1);
+	}
+
+	#[test]
+	fn calls_in_order() {
+		let expect = |calls: &str| {
+			run_case(&format!(
+				r#"(name: "calls", call: "Run", calls: [{calls}])"#
+			))
+		};
+
+		// Other calls can happen in between.
+		assert!(
+			expect(r#"(name: "Game.GetValue"), (name: "Debug.Trace", args: ["b"])"#).is_empty()
+		);
+		assert!(expect(r#"(name: "Debug.Trace"), (name: "Debug.Trace")"#).is_empty());
+
+		let failures =
+			expect(r#"(name: "Debug.Trace", args: ["b"]), (name: "Debug.Trace", args: ["a"])"#);
+		assert_eq!(
+			failures,
+			[
+				"expected a call to Debug.Trace(\"a\") in order, calls made: \
+				Game.GetValue(), Debug.Trace(\"a\"), Debug.Trace(\"b\")"
+			]
+		);
+		assert_eq!(
+			expect(r#"(name: "Debug.Trace"), (name: "Debug.Trace"), (name: "Debug.Trace")"#).len(),
+			1
+		);
+	}
+
+	#[test]
+	fn case_stubs_override_suite_stubs() {
+		let returns = |stubs| {
+			run_case(&format!(
+				r#"(name: "stubs", call: "Run", stubs: {{ {stubs} }}, returns: "10")"#
+			))
+		};
+		assert!(returns(r#""game.getvalue": "10""#).is_empty());
+		assert_eq!(returns(""), ["returned 1, expected 10"]);
+	}
+}
@@ -0,0 +1,917 @@
+//! Runs Papyrus bytecode offline, with native and external calls handled by Rust stubs.
+
+use crate::{
+	coverage::Coverage,
+	pex::{Function, Instruction, ObjectData, Pex, VariableData},
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+#[derive(Debug, thiserror::Error)]
+pub enum InterpError {
+	#[error("No function {0}")]
+	UnknownFunction(String),
+
+	#[error("No variable {0}")]
+	UnknownVariable(String),
+
+	#[error("No stub registered for {0}")]
+	MissingStub(String),
+
+	#[error("{function} takes {expected} arguments but was given {got}")]
+	ArgumentCount {
+		function: String,
+		expected: usize,
+		got: usize,
+	},
+
+	#[error("Division by zero")]
+	DivideByZero,
+
+	#[error("Index {0} out of range for an array of length {1}")]
+	IndexOutOfRange(i32, usize),
+
+	#[error("Expected an array, got {0}")]
+	NotAnArray(String),
+
+	#[error("Gave up after {0} instructions")]
+	StepLimit(usize),
+
+	#[error("Call stack deeper than {0}")]
+	StackOverflow(usize),
+
+	#[error("Arrays can hold at most {MAX_ARRAY_LEN} elements, not {0}")]
+	ArraySize(u32),
+
+	/// Raised by stubs.
+	#[error("{0}")]
+	Stub(String),
+}
+
+pub type InterpResult<T> = Result<T, InterpError>;
+
+#[derive(Debug, Clone, Default)]
+pub enum Value {
+	#[default]
+	None,
+	Int(i32),
+	Float(f32),
+	Bool(bool),
+	String(String),
+	/// Opaque handle to a game object. The script being run is `Object(0)` unless told otherwise.
+	Object(u32),
+	/// Arrays are shared by reference, like in game.
+	Array(Rc<RefCell<Vec<Value>>>),
+}
+
+impl Value {
+	pub fn array(values: Vec<Value>) -> Self {
+		Value::Array(Rc::new(RefCell::new(values)))
+	}
+
+	/// Parses a literal like `5`, `1.5`, `true` or `None`. Anything else is taken as a string,
+	/// with surrounding quotes removed. Papyrus has no literal for infinity or NaN, so those are strings too.
+	pub fn parse(s: &str) -> Self {
+		if let Ok(i) = s.parse() {
+			Value::Int(i)
+		} else if let Some(f) = s.parse::<f32>().ok().filter(|f| f.is_finite()) {
+			Value::Float(f)
+		} else if s.eq_ignore_ascii_case("true") || s.eq_ignore_ascii_case("false") {
+			Value::Bool(s.eq_ignore_ascii_case("true"))
+		} else if s.eq_ignore_ascii_case("none") {
+			Value::None
+		} else {
+			Value::String(s.trim_matches('"').to_owned())
+		}
+	}
+
+	/// Default value of a variable of the given type.
+	fn default_for(type_name: &str) -> Self {
+		match type_name.to_lowercase().as_str() {
+			"int" => Value::Int(0),
+			"float" => Value::Float(0.0),
+			"bool" => Value::Bool(false),
+			"string" => Value::String(String::new()),
+			_ => Value::None,
+		}
+	}
+
+	pub fn as_bool(&self) -> bool {
+		match self {
+			Value::None => false,
+			Value::Int(i) => *i != 0,
+			Value::Float(f) => *f != 0.0,
+			Value::Bool(b) => *b,
+			Value::String(s) => !s.is_empty(),
+			Value::Object(_) => true,
+			Value::Array(a) => !a.borrow().is_empty(),
+		}
+	}
+
+	pub fn as_int(&self) -> i32 {
+		match self {
+			Value::Int(i) => *i,
+			Value::Float(f) => *f as i32,
+			Value::Bool(b) => *b as i32,
+			Value::String(s) => parse_leading(s).map_or(0, |f| f as i32),
+			_ => 0,
+		}
+	}
+
+	pub fn as_float(&self) -> f32 {
+		match self {
+			Value::Int(i) => *i as f32,
+			Value::Float(f) => *f,
+			Value::Bool(b) => *b as i32 as f32,
+			Value::String(s) => parse_leading(s).unwrap_or_default(),
+			_ => 0.0,
+		}
+	}
+
+	/// Casts a value to a variable's declared type. Object and array types are left as is.
+	fn cast(&self, type_name: &str) -> Value {
+		match type_name.to_lowercase().as_str() {
+			"int" => Value::Int(self.as_int()),
+			"float" => Value::Float(self.as_float()),
+			"bool" => Value::Bool(self.as_bool()),
+			"string" => Value::String(self.to_string()),
+			_ => self.clone(),
+		}
+	}
+}
+
+/// Numeric prefix of a string, the way the game reads "12abc" as 12.
+fn parse_leading(s: &str) -> Option<f32> {
+	let s = s.trim_start();
+	(1..=s.len())
+		.rev()
+		.filter(|&end| s.is_char_boundary(end))
+		.find_map(|end| s[..end].parse().ok())
+}
+
+impl std::fmt::Display for Value {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Value::None => f.write_str("None"),
+			Value::Int(i) => write!(f, "{i}"),
+			Value::Float(x) => write!(f, "{x:.6}"),
+			Value::Bool(true) => f.write_str("True"),
+			Value::Bool(false) => f.write_str("False"),
+			Value::String(s) => f.write_str(s),
+			Value::Object(id) => write!(f, "[Object {id}]"),
+			Value::Array(a) => {
+				let items = a.borrow().iter().map(|v| v.to_string()).collect::<Vec<_>>();
+				write!(f, "[{}]", items.join(", "))
+			}
+		}
+	}
+}
+
+/// Papyrus compares strings case insensitively, and numbers across types.
+impl PartialEq for Value {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(Value::None, Value::None) => true,
+			(Value::None, _) | (_, Value::None) => false,
+			(Value::Bool(a), b) | (b, Value::Bool(a)) => *a == b.as_bool(),
+			(Value::Int(a), Value::Int(b)) => a == b,
+			(Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+				self.as_float() == other.as_float()
+			}
+			(Value::String(a), Value::String(b)) => a.eq_ignore_ascii_case(b),
+			(Value::Object(a), Value::Object(b)) => a == b,
+			(Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+			_ => false,
+		}
+	}
+}
+
+impl PartialOrd for Value {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		match (self, other) {
+			(Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+			(Value::String(a), Value::String(b)) => a.to_lowercase().partial_cmp(&b.to_lowercase()),
+			_ => self.as_float().partial_cmp(&other.as_float()),
+		}
+	}
+}
+
+/// A Rust stand-in for a native or external function. Gets the object it was called on
+/// (`None` for static calls) and the arguments.
+pub type Stub<'a> = Box<dyn FnMut(&Value, &[Value]) -> InterpResult<Value> + 'a>;
+
+/// Like a [`Stub`], but for any call without one. Also gets the name the stub would've been registered as.
+pub type Fallback<'a> = Box<dyn FnMut(&str, &Value, &[Value]) -> InterpResult<Value> + 'a>;
+
+#[derive(Default)]
+struct Frame {
+	/// Parameters and locals by lowercase name, with their declared types.
+	variables: HashMap<String, (String, Value)>,
+}
+
+/// Instructions run before giving up, so infinite loops don't hang tests.
+pub const DEFAULT_MAX_STEPS: usize = 1_000_000;
+/// Nested script calls allowed before giving up.
+pub const MAX_DEPTH: usize = 256;
+/// Largest array the game lets scripts create.
+pub const MAX_ARRAY_LEN: u32 = 128;
+
+/// Runs functions of one object in a script, keeping its variables and state between calls.
+pub struct Interpreter<'a> {
+	pex: &'a Pex,
+	name: &'a str,
+	object: &'a ObjectData,
+	/// Script variables by lowercase name, with their declared types.
+	variables: HashMap<String, (String, Value)>,
+	/// Stubs by lowercase name, see [`Interpreter::register`].
+	stubs: HashMap<String, Stub<'a>>,
+	/// Handles calls no stub was registered for, see [`Interpreter::set_fallback`].
+	fallback: Option<Fallback<'a>>,
+	/// Paths of the script's functions by address, and what ran of them so far.
+	coverage: Option<(HashMap<*const Function, String>, Coverage)>,
+	pub state: String,
+	/// What `self` evaluates to. Calls on this value run the script's own functions.
+	pub this: Value,
+	pub max_steps: usize,
+	steps: usize,
+	depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+	/// Sets up the first object of a script, with its variables at their initial values.
+	pub fn new(pex: &'a Pex) -> InterpResult<Self> {
+		let Some((name_idx, object)) = pex.objects.first() else {
+			return Err(InterpError::UnknownFunction("(no objects)".into()));
+		};
+
+		let mut interp = Self {
+			pex,
+			name: pex.string(*name_idx),
+			object,
+			variables: HashMap::new(),
+			stubs: HashMap::new(),
+			fallback: None,
+			coverage: None,
+			state: pex.string(object.auto_state_name_idx).to_owned(),
+			this: Value::Object(0),
+			max_steps: DEFAULT_MAX_STEPS,
+			steps: 0,
+			depth: 0,
+		};
+
+		for (name_idx, type_idx, _, data) in &object.variables {
+			let type_name = pex.string(*type_idx).to_owned();
+			let value = match data {
+				VariableData::Null => Value::default_for(&type_name),
+				data => interp.literal(data),
+			};
+			interp
+				.variables
+				.insert(pex.string(*name_idx).to_lowercase(), (type_name, value));
+		}
+
+		Ok(interp)
+	}
+
+	/// Registers a stub, looked up case insensitively by:
+	/// - `Object.Function` for static calls, e.g. `Debug.Trace`
+	/// - `Function` for method calls on anything but `self`, and native methods of the script itself
+	/// - `Parent.Function` for calls to the parent script
+	/// - `Property.Get`/`Property.Set` for properties of other objects
+	pub fn register(
+		&mut self,
+		name: &str,
+		stub: impl FnMut(&Value, &[Value]) -> InterpResult<Value> + 'a,
+	) {
+		self.stubs.insert(name.to_lowercase(), Box::new(stub));
+	}
+
+	/// Handles every call that has no stub registered, instead of failing.
+	pub fn set_fallback(
+		&mut self,
+		fallback: impl FnMut(&str, &Value, &[Value]) -> InterpResult<Value> + 'a,
+	) {
+		self.fallback = Some(Box::new(fallback));
+	}
+
+	/// Starts recording which instructions run, see [`Interpreter::take_coverage`].
+	pub fn record_coverage(&mut self) {
+		let paths = self
+			.pex
+			.function_paths()
+			.into_iter()
+			.map(|(path, f)| (f as *const Function, path))
+			.collect();
+		self.coverage = Some((paths, Coverage::default()));
+	}
+
+	/// What ran of the script's own functions since coverage started being recorded, or was last taken.
+	pub fn take_coverage(&mut self) -> Coverage {
+		self.coverage
+			.as_mut()
+			.map(|(_, coverage)| std::mem::take(coverage))
+			.unwrap_or_default()
+	}
+
+	/// A script variable's current value, by name.
+	pub fn variable(&self, name: &str) -> Option<&Value> {
+		self.variables.get(&name.to_lowercase()).map(|(_, v)| v)
+	}
+
+	/// Sets a script variable, casting the value to the variable's type.
+	pub fn set_variable(&mut self, name: &str, value: Value) -> InterpResult<()> {
+		match self.variables.get_mut(&name.to_lowercase()) {
+			Some((type_name, v)) => {
+				*v = value.cast(type_name);
+				Ok(())
+			}
+			None => Err(InterpError::UnknownVariable(name.to_owned())),
+		}
+	}
+
+	/// Calls a function of the script by name, in the current state.
+	pub fn call(&mut self, function: &str, args: Vec<Value>) -> InterpResult<Value> {
+		if self.find(function).is_none() && !self.stubs.contains_key(&function.to_lowercase()) {
+			return Err(InterpError::UnknownFunction(format!(
+				"{}.{function}",
+				self.name
+			)));
+		}
+
+		self.steps = 0;
+		self.call_method(function, args)
+	}
+
+	/// Runs a function with the given arguments. It doesn't have to belong to the script,
+	/// but any names it uses are resolved through the script's string table.
+	pub fn run(&mut self, function: &Function, args: Vec<Value>) -> InterpResult<Value> {
+		self.steps = 0;
+		self.execute("(function)", function, args)
+	}
+
+	/// Finds a function in the current state, falling back to the empty state.
+	fn find(&self, name: &str) -> Option<&'a Function> {
+		let object = self.object;
+		let pex = self.pex;
+		[self.state.as_str(), ""]
+			.into_iter()
+			.filter_map(|state| {
+				object
+					.states
+					.iter()
+					.find(|s| pex.string(s.name_idx).eq_ignore_ascii_case(state))
+			})
+			.flat_map(|s| &s.functions)
+			.find(|(name_idx, _)| pex.string(*name_idx).eq_ignore_ascii_case(name))
+			.map(|(_, f)| f)
+	}
+
+	fn stub(&mut self, name: &str, this: &Value, args: &[Value]) -> InterpResult<Value> {
+		match (self.stubs.get_mut(&name.to_lowercase()), &mut self.fallback) {
+			(Some(stub), _) => stub(this, args),
+			(None, Some(fallback)) => fallback(name, this, args),
+			(None, None) => Err(InterpError::MissingStub(name.to_owned())),
+		}
+	}
+
+	fn call_method(&mut self, name: &str, args: Vec<Value>) -> InterpResult<Value> {
+		match name.to_lowercase().as_str() {
+			"gotostate" => {
+				let state = args.first().map(Value::to_string).unwrap_or_default();
+				let old = self.state.clone();
+				self.state_event("OnEndState", &state)?;
+				self.state = state;
+				self.state_event("OnBeginState", &old)?;
+				return Ok(Value::None);
+			}
+			"getstate" => return Ok(Value::String(self.state.clone())),
+			_ => {}
+		}
+
+		match self.find(name) {
+			Some(function) if !function.is_native() => self.execute(name, function, args),
+			// Native, or inherited from a parent script we don't have.
+			_ => {
+				let this = self.this.clone();
+				self.stub(name, &this, &args)
+			}
+		}
+	}
+
+	/// Runs `OnBeginState` or `OnEndState` if the current state has it, like `GoToState` does in game.
+	/// Fallout 4 passes them the other state's name, Skyrim passes nothing.
+	fn state_event(&mut self, event: &str, other_state: &str) -> InterpResult<()> {
+		let Some(function) = self.find(event).filter(|f| !f.is_native()) else {
+			return Ok(());
+		};
+
+		let args = match function.params.len() {
+			0 => vec![],
+			_ => vec![Value::String(other_state.to_owned())],
+		};
+		self.execute(event, function, args).map(drop)
+	}
+
+	fn execute(
+		&mut self,
+		name: &str,
+		function: &Function,
+		args: Vec<Value>,
+	) -> InterpResult<Value> {
+		if function.params.len() != args.len() {
+			return Err(InterpError::ArgumentCount {
+				function: name.to_owned(),
+				expected: function.params.len(),
+				got: args.len(),
+			});
+		}
+
+		if self.depth >= MAX_DEPTH {
+			return Err(InterpError::StackOverflow(MAX_DEPTH));
+		}
+
+		let pex = self.pex;
+		let mut frame = Frame::default();
+		for (param, arg) in function.params.iter().zip(args) {
+			let type_name = pex.string(param.type_idx);
+			frame.variables.insert(
+				pex.string(param.name_idx).to_lowercase(),
+				(type_name.to_owned(), arg.cast(type_name)),
+			);
+		}
+		for local in &function.locals {
+			let type_name = pex.string(local.type_idx);
+			frame.variables.insert(
+				pex.string(local.name_idx).to_lowercase(),
+				(type_name.to_owned(), Value::default_for(type_name)),
+			);
+		}
+
+		self.depth += 1;
+		let result = self.execute_frame(function, &mut frame);
+		self.depth -= 1;
+		result
+	}
+
+	fn execute_frame(&mut self, function: &Function, frame: &mut Frame) -> InterpResult<Value> {
+		let mut pc = 0;
+
+		// Functions that aren't part of the script have nothing to map coverage to.
+		let path = self
+			.coverage
+			.as_ref()
+			.and_then(|(paths, _)| paths.get(&(function as *const Function)))
+			.cloned();
+		if let (Some(path), Some((_, coverage))) = (&path, &mut self.coverage) {
+			coverage.call(path);
+		}
+
+		while let Some(instruction) = function.instructions.get(pc) {
+			self.steps += 1;
+			if self.steps > self.max_steps {
+				return Err(InterpError::StepLimit(self.max_steps));
+			}
+
+			if let (Some(path), Some((_, coverage))) = (&path, &mut self.coverage) {
+				coverage.hit(path, function, pc);
+			}
+
+			if let Some(target) = instruction.jump_target(pc) {
+				let jump = match instruction {
+					Instruction::JMPT(cond, _) => self.value(frame, cond)?.as_bool(),
+					Instruction::JMPF(cond, _) => !self.value(frame, cond)?.as_bool(),
+					_ => true,
+				};
+
+				if let (Some(path), Some((_, coverage))) = (&path, &mut self.coverage)
+					&& !matches!(instruction, Instruction::JMP(..))
+				{
+					coverage.branch(path, pc, jump);
+				}
+
+				pc = if jump { target } else { pc + 1 };
+				continue;
+			}
+
+			if let Instruction::RETURN(value) = instruction {
+				return self.value(frame, value);
+			}
+
+			self.step(frame, instruction)?;
+			pc += 1;
+		}
+
+		Ok(Value::None)
+	}
+
+	/// Runs any instruction other than jumps and returns.
+	fn step(&mut self, frame: &mut Frame, instruction: &Instruction) -> InterpResult<()> {
+		use Instruction as I;
+
+		let pex = self.pex;
+		let result = match instruction {
+			I::NOP => return Ok(()),
+			I::IADD(_, a, b)
+			| I::ISUB(_, a, b)
+			| I::IMUL(_, a, b)
+			| I::IDIV(_, a, b)
+			| I::IMOD(_, a, b) => {
+				let (a, b) = (
+					self.value(frame, a)?.as_int(),
+					self.value(frame, b)?.as_int(),
+				);
+				Value::Int(match instruction {
+					I::IADD(..) => a.wrapping_add(b),
+					I::ISUB(..) => a.wrapping_sub(b),
+					I::IMUL(..) => a.wrapping_mul(b),
+					_ if b == 0 => return Err(InterpError::DivideByZero),
+					I::IDIV(..) => a.wrapping_div(b),
+					_ => a.wrapping_rem(b),
+				})
+			}
+			I::FADD(_, a, b) | I::FSUB(_, a, b) | I::FMUL(_, a, b) | I::FDIV(_, a, b) => {
+				let (a, b) = (
+					self.value(frame, a)?.as_float(),
+					self.value(frame, b)?.as_float(),
+				);
+				Value::Float(match instruction {
+					I::FADD(..) => a + b,
+					I::FSUB(..) => a - b,
+					I::FMUL(..) => a * b,
+					_ if b == 0.0 => return Err(InterpError::DivideByZero),
+					_ => a / b,
+				})
+			}
+			I::NOT(_, a) => Value::Bool(!self.value(frame, a)?.as_bool()),
+			I::INEG(_, a) => Value::Int(self.value(frame, a)?.as_int().wrapping_neg()),
+			I::FNEG(_, a) => Value::Float(-self.value(frame, a)?.as_float()),
+			I::ASSIGN(_, a) => self.value(frame, a)?,
+			I::CAST(dest, a) => {
+				let type_name = self.type_of(frame, *dest)?;
+				self.value(frame, a)?.cast(&type_name)
+			}
+			I::CMP_EQ(_, a, b)
+			| I::CMP_LT(_, a, b)
+			| I::CMP_LE(_, a, b)
+			| I::CMP_GT(_, a, b)
+			| I::CMP_GE(_, a, b) => {
+				let (a, b) = (self.value(frame, a)?, self.value(frame, b)?);
+				Value::Bool(match instruction {
+					I::CMP_EQ(..) => a == b,
+					I::CMP_LT(..) => a < b,
+					I::CMP_LE(..) => a <= b,
+					I::CMP_GT(..) => a > b,
+					_ => a >= b,
+				})
+			}
+			I::CALLMETHOD(name, target, _, args) => {
+				let target = self.value(frame, target)?;
+				let args = self.values(frame, args)?;
+				let name = pex.string(*name);
+
+				if target == self.this {
+					self.call_method(name, args)?
+				} else {
+					self.stub(name, &target, &args)?
+				}
+			}
+			I::CALLPARENT(name, _, args) => {
+				let args = self.values(frame, args)?;
+				let parent = pex.string(self.object.parent_name_idx);
+				let this = self.this.clone();
+				self.stub(&format!("{parent}.{}", pex.string(*name)), &this, &args)?
+			}
+			I::CALLSTATIC(object, name, _, args) => {
+				let args = self.values(frame, args)?;
+				let (object, name) = (pex.string(*object), pex.string(*name));
+
+				match self.find(name) {
+					Some(function)
+						if object.eq_ignore_ascii_case(self.name) && !function.is_native() =>
+					{
+						self.execute(name, function, args)?
+					}
+					_ => self.stub(&format!("{object}.{name}"), &Value::None, &args)?,
+				}
+			}
+			I::STRCAT(_, a, b) => Value::String(format!(
+				"{}{}",
+				self.value(frame, a)?,
+				self.value(frame, b)?
+			)),
+			I::PROPGET(name, target, _) => {
+				let target = self.read(frame, *target)?;
+				self.property(pex.string(*name), &target, None)?
+			}
+			I::PROPSET(name, target, value) => {
+				let target = self.read(frame, *target)?;
+				let value = self.value(frame, value)?;
+				self.property(pex.string(*name), &target, Some(value))?;
+				return Ok(());
+			}
+			I::ARRAY_CREATE(_, size) if *size > MAX_ARRAY_LEN => {
+				return Err(InterpError::ArraySize(*size));
+			}
+			I::ARRAY_CREATE(dest, size) => {
+				let type_name = self.type_of(frame, *dest)?;
+				let element = Value::default_for(type_name.trim_end_matches("[]"));
+				Value::array(vec![element; *size as usize])
+			}
+			I::ARRAY_LENGTH(_, array) => {
+				let array = self.array(frame, *array)?;
+				Value::Int(array.borrow().len() as i32)
+			}
+			I::ARRAY_GETELEMENT(_, array, index) => {
+				let array = self.array(frame, *array)?;
+				let index = self.value(frame, index)?.as_int();
+				let array = array.borrow();
+				match usize::try_from(index).ok().and_then(|i| array.get(i)) {
+					Some(v) => v.clone(),
+					None => return Err(InterpError::IndexOutOfRange(index, array.len())),
+				}
+			}
+			I::ARRAY_SETELEMENT(array, index, value) => {
+				let array = self.array(frame, *array)?;
+				let index = self.value(frame, index)?.as_int();
+				let value = self.value(frame, value)?;
+				let mut array = array.borrow_mut();
+				let len = array.len();
+				match usize::try_from(index).ok().and_then(|i| array.get_mut(i)) {
+					Some(v) => *v = value,
+					None => return Err(InterpError::IndexOutOfRange(index, len)),
+				}
+				return Ok(());
+			}
+			I::ARRAY_FINDELEMENT(array, _, value, start)
+			| I::ARRAY_RFINDELEMENT(array, _, value, start) => {
+				let array = self.array(frame, *array)?;
+				let value = self.value(frame, value)?;
+				let array = array.borrow();
+				let len = array.len() as i32;
+
+				// RFind counts down from the start index, with a negative one meaning the end.
+				let found = if matches!(instruction, I::ARRAY_FINDELEMENT(..)) {
+					(*start.max(&0)..len).find(|&i| array[i as usize] == value)
+				} else {
+					let start = if *start < 0 {
+						len - 1
+					} else {
+						(*start).min(len - 1)
+					};
+					(0..=start).rev().find(|&i| array[i as usize] == value)
+				};
+				Value::Int(found.unwrap_or(-1))
+			}
+			I::JMP(..) | I::JMPT(..) | I::JMPF(..) | I::RETURN(..) => unreachable!(),
+		};
+
+		if let Some(dest) = instruction.destination() {
+			self.assign(frame, dest, result)?;
+		}
+
+		Ok(())
+	}
+
+	fn literal(&self, data: &VariableData) -> Value {
+		match data {
+			VariableData::Null | VariableData::Ident(_) => Value::None,
+			VariableData::String(idx) => Value::String(self.pex.string(*idx).to_owned()),
+			VariableData::Int(i) => Value::Int(*i),
+			VariableData::Float(f) => Value::Float(*f),
+			VariableData::Bool(b) => Value::Bool(*b),
+		}
+	}
+
+	fn value(&self, frame: &Frame, data: &VariableData) -> InterpResult<Value> {
+		match data {
+			VariableData::Ident(idx) => self.read(frame, *idx),
+			data => Ok(self.literal(data)),
+		}
+	}
+
+	fn values(&self, frame: &Frame, data: &[VariableData]) -> InterpResult<Vec<Value>> {
+		data.iter().map(|d| self.value(frame, d)).collect()
+	}
+
+	fn read(&self, frame: &Frame, idx: u16) -> InterpResult<Value> {
+		let name = self.pex.string(idx).to_lowercase();
+		if let Some((_, v)) = frame.variables.get(&name).or(self.variables.get(&name)) {
+			return Ok(v.clone());
+		}
+
+		match name.as_str() {
+			"self" => Ok(self.this.clone()),
+			"::nonevar" => Ok(Value::None),
+			_ => Err(InterpError::UnknownVariable(name)),
+		}
+	}
+
+	fn type_of(&self, frame: &Frame, idx: u16) -> InterpResult<String> {
+		let name = self.pex.string(idx).to_lowercase();
+		match frame.variables.get(&name).or(self.variables.get(&name)) {
+			Some((type_name, _)) => Ok(type_name.clone()),
+			None => Err(InterpError::UnknownVariable(name)),
+		}
+	}
+
+	fn array(&self, frame: &Frame, idx: u16) -> InterpResult<Rc<RefCell<Vec<Value>>>> {
+		match self.read(frame, idx)? {
+			Value::Array(a) => Ok(a),
+			other => Err(InterpError::NotAnArray(other.to_string())),
+		}
+	}
+
+	fn assign(&mut self, frame: &mut Frame, idx: u16, value: Value) -> InterpResult<()> {
+		let name = self.pex.string(idx).to_lowercase();
+		if name == "::nonevar" {
+			return Ok(());
+		}
+
+		match frame.variables.get_mut(&name) {
+			Some((_, v)) => *v = value,
+			None => match self.variables.get_mut(&name) {
+				Some((_, v)) => *v = value,
+				None => return Err(InterpError::UnknownVariable(name)),
+			},
+		}
+
+		Ok(())
+	}
+
+	/// Reads a property, or writes it when given a value.
+	fn property(
+		&mut self,
+		name: &str,
+		target: &Value,
+		value: Option<Value>,
+	) -> InterpResult<Value> {
+		if *target != self.this {
+			let kind = if value.is_some() { "Set" } else { "Get" };
+			let args = value.into_iter().collect::<Vec<_>>();
+			return self.stub(&format!("{name}.{kind}"), target, &args);
+		}
+
+		let pex = self.pex;
+		let Some(property) = self
+			.object
+			.properties
+			.iter()
+			.find(|p| pex.string(p.name_idx).eq_ignore_ascii_case(name))
+		else {
+			return Err(InterpError::UnknownVariable(format!(
+				"{}.{name}",
+				self.name
+			)));
+		};
+
+		if let Some(var) = property.auto_var_name {
+			let var = pex.string(var);
+			return match value {
+				Some(value) => self.set_variable(var, value).map(|_| Value::None),
+				None => self
+					.variable(var)
+					.cloned()
+					.ok_or_else(|| InterpError::UnknownVariable(var.to_owned())),
+			};
+		}
+
+		let (kind, handler) = match value {
+			Some(_) => ("Set", &property.write_handler),
+			None => ("Get", &property.read_handler),
+		};
+		match handler {
+			Some(handler) => self.execute(name, handler, value.into_iter().collect()),
+			None => Err(InterpError::UnknownFunction(format!(
+				"{}.{name}.{kind}",
+				self.name
+			))),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, ScriptBuilder, fixture, ident, string},
+		model::VariableData::Int,
+	};
+
+	/// The fixture script with an `Int` variable `::count_var` starting at 2.
+	fn counting() -> ScriptBuilder {
+		fixture::builder().variable("::count_var", "Int", Int(2), &[])
+	}
+
+	fn script(function: FunctionBuilder) -> Pex {
+		counting().function("Run", function).build().unwrap()
+	}
+
+	#[test]
+	fn parse_values() {
+		assert!(matches!(Value::parse("5"), Value::Int(5)));
+		assert!(matches!(Value::parse("1.5"), Value::Float(f) if f == 1.5));
+		assert!(matches!(Value::parse("TRUE"), Value::Bool(true)));
+		assert!(matches!(Value::parse("none"), Value::None));
+		assert!(matches!(Value::parse("\"hi\""), Value::String(s) if s == "hi"));
+
+		for s in ["inf", "-infinity", "NaN"] {
+			assert!(matches!(Value::parse(s), Value::String(_)), "{s}");
+		}
+	}
+
+	#[test]
+	fn compare_values() {
+		assert_eq!(Value::Int(1), Value::Float(1.0));
+		assert_eq!(Value::Bool(true), Value::Int(5));
+		assert_eq!(Value::String("abc".into()), Value::String("ABC".into()));
+		assert_eq!(Value::None, Value::None);
+		assert_ne!(Value::None, Value::Bool(false));
+		assert_ne!(Value::Bool(false), Value::None);
+		assert_ne!(Value::None, Value::Int(0));
+	}
+
+	#[test]
+	fn run_with_stubs() {
+		let pex = script(
+			FunctionBuilder::new()
+				.returns("Int")
+				.local("::temp0", "Int")
+				.op("IMUL", vec![ident("::temp0"), ident("::count_var"), Int(3)])
+				.call(
+					"CALLSTATIC",
+					vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+					vec![string("traced")],
+				)
+				.op("RETURN", vec![ident("::temp0")]),
+		);
+
+		let traced = RefCell::new(vec![]);
+		let mut interp = Interpreter::new(&pex).unwrap();
+		interp.register("Debug.Trace", |_, args| {
+			traced.borrow_mut().push(args[0].to_string());
+			Ok(Value::None)
+		});
+
+		assert_eq!(interp.call("Run", vec![]).unwrap(), Value::Int(6));
+		interp.set_variable("::count_var", Value::Int(5)).unwrap();
+		assert_eq!(interp.call("Run", vec![]).unwrap(), Value::Int(15));
+		drop(interp);
+		assert_eq!(traced.into_inner(), ["traced", "traced"]);
+	}
+
+	#[test]
+	fn array_size_limit() {
+		let create = |size| {
+			script(
+				FunctionBuilder::new()
+					.returns("Int")
+					.local("::temp0", "Int[]")
+					.local("::temp1", "Int")
+					.op("ARRAY_CREATE", vec![ident("::temp0"), Int(size)])
+					.op("ARRAY_LENGTH", vec![ident("::temp1"), ident("::temp0")])
+					.op("RETURN", vec![ident("::temp1")]),
+			)
+		};
+
+		let pex = create(128);
+		let mut interp = Interpreter::new(&pex).unwrap();
+		assert_eq!(interp.call("Run", vec![]).unwrap(), Value::Int(128));
+
+		let pex = create(129);
+		let mut interp = Interpreter::new(&pex).unwrap();
+		assert!(matches!(
+			interp.call("Run", vec![]),
+			Err(InterpError::ArraySize(129))
+		));
+	}
+
+	#[test]
+	fn state_events() {
+		let go_to_busy = FunctionBuilder::new().call(
+			"CALLMETHOD",
+			vec![ident("GoToState"), ident("self"), ident("::NoneVar")],
+			vec![string("Busy")],
+		);
+		let pex = counting()
+			.function("Run", go_to_busy)
+			.function(
+				"OnEndState",
+				FunctionBuilder::new().op("ASSIGN", vec![ident("::count_var"), Int(5)]),
+			)
+			.state_function(
+				"Busy",
+				"OnBeginState",
+				FunctionBuilder::new().param("asOldState", "String").op(
+					"IMUL",
+					vec![ident("::count_var"), ident("::count_var"), Int(3)],
+				),
+			)
+			.build()
+			.unwrap();
+
+		// The old state ends before the new one begins.
+		let mut interp = Interpreter::new(&pex).unwrap();
+		interp.call("Run", vec![]).unwrap();
+		assert_eq!(interp.state, "Busy");
+		assert_eq!(interp.variable("::count_var"), Some(&Value::Int(15)));
+	}
+}
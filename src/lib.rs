@@ -0,0 +1,17 @@
+pub mod bsa;
+pub mod builder;
+pub mod cfg;
+pub mod coverage;
+pub mod diff;
+pub mod disasm;
+pub mod edit;
+pub mod harness;
+pub mod interp;
+pub mod merge;
+pub mod model;
+pub mod optimize;
+pub mod pattern;
+pub mod pex;
+pub mod release;
+pub mod search;
+pub mod source;
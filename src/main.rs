@@ -1,43 +1,336 @@
 // use pex::Instruction;
 
-mod pex;
+mod cli;
+
+use pexspy::{bsa, disasm, edit, merge, pex, search, source};
 
 use iced::{
 	Alignment::Center,
 	Border, Color,
 	Length::{self, Fill},
-	Task, Theme, color,
-	widget::{
-		button, center, column, container, row, scrollable, text, text_editor,
-	},
+	Task, color,
+	widget::{button, center, column, container, row, scrollable, text, text_editor, text_input},
 };
-use nanoserde::SerRon;
+use nanoserde::{DeRon, SerRon};
 struct EditorState {
 	content: iced::widget::text_editor::Content,
 	visible: bool,
 }
 
+#[derive(Debug, Clone, Copy)]
+enum EditorSlot {
+	// object_idx, state_idx, func_idx
+	Function(usize, usize, usize),
+	// object_idx, prop_idx, 0 for the read handler or 1 for the write handler
+	Handler(usize, usize, usize),
+}
+
 struct Tab {
 	path: std::path::PathBuf,
-	content: Vec<u8>,
-
-	rons: Vec<Vec<Vec<String>>>,
 
 	// object_idx -> state_idx -> function_idx -> (Content, visibility)
 	editors: Vec<Vec<Vec<EditorState>>>,
+	// object_idx -> property_idx -> [read handler, write handler]
+	handler_editors: Vec<Vec<[Option<EditorState>; 2]>>,
 
 	// Todo: Make this lazily parsed, so Option<Pex>
 	pex: pex::Pex,
+
+	// Left over from a three-way merge, resolved in the Merge section.
+	conflicts: Vec<merge::Conflict>,
+	overlapping: Vec<String>,
+	revived: Vec<String>,
+
+	// Lines of the .psc the script was compiled from, if it could be found.
+	source: Option<Vec<String>>,
+	lines: source::LineMap,
+}
+
+impl Tab {
+	fn new(path: std::path::PathBuf, pex: pex::Pex, source_dirs: &[std::path::PathBuf]) -> Self {
+		let mut tab = Self {
+			path,
+			editors: vec![],
+			handler_editors: vec![],
+			pex,
+			conflicts: vec![],
+			overlapping: vec![],
+			revived: vec![],
+			source: None,
+			lines: Default::default(),
+		};
+		tab.rebuild_editors();
+		tab.load_source(source_dirs);
+		tab
+	}
+
+	fn load_source(&mut self, source_dirs: &[std::path::PathBuf]) {
+		self.source = source::find_source(&self.pex, &self.path, source_dirs)
+			.and_then(|path| std::fs::read(path).ok())
+			.map(|bytes| {
+				String::from_utf8_lossy(&bytes)
+					.lines()
+					.map(str::to_owned)
+					.collect()
+			});
+	}
+
+	fn rebuild_editors(&mut self) {
+		self.lines = source::LineMap::new(&self.pex);
+		(self.editors, self.handler_editors) = self
+			.pex
+			.with_user_flag_names(|| (self.new_editors(), self.new_handler_editors()));
+	}
+
+	/// An editor per state function, holding its RON. Call inside [`pex::Pex::with_user_flag_names`].
+	fn new_editors(&self) -> Vec<Vec<Vec<EditorState>>> {
+		self.pex
+			.objects
+			.iter()
+			.map(|(_, o)| {
+				o.states
+					.iter()
+					.map(|s| {
+						s.functions
+							.iter()
+							.map(|(_, f)| EditorState {
+								content: iced::widget::text_editor::Content::with_text(
+									&f.serialize_ron(),
+								),
+								visible: false,
+							})
+							.collect::<Vec<_>>()
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>()
+	}
+
+	/// An editor per property handler, like [`Tab::new_editors`].
+	fn new_handler_editors(&self) -> Vec<Vec<[Option<EditorState>; 2]>> {
+		self.pex
+			.objects
+			.iter()
+			.map(|(_, o)| {
+				o.properties
+					.iter()
+					.map(|p| {
+						[&p.read_handler, &p.write_handler].map(|handler| {
+							handler.as_ref().map(|f| EditorState {
+								content: iced::widget::text_editor::Content::with_text(
+									&f.serialize_ron(),
+								),
+								visible: false,
+							})
+						})
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>()
+	}
+
+	/// Where each editor is, by the path of its function.
+	fn editor_slots(&self) -> Vec<(String, EditorSlot)> {
+		let mut out = vec![];
+		for (obj_idx, (obj_name_idx, obj)) in self.pex.objects.iter().enumerate() {
+			let obj_name = self.pex.string(*obj_name_idx);
+
+			for (state_idx, state) in obj.states.iter().enumerate() {
+				let state_name = self.pex.string(state.name_idx);
+				for (func_idx, (name_idx, _)) in state.functions.iter().enumerate() {
+					let path = pex::function_path(obj_name, state_name, self.pex.string(*name_idx));
+					out.push((path, EditorSlot::Function(obj_idx, state_idx, func_idx)));
+				}
+			}
+
+			for (prop_idx, property) in obj.properties.iter().enumerate() {
+				let prop_name = self.pex.string(property.name_idx);
+				for (kind, name) in ["Get", "Set"].into_iter().enumerate() {
+					let path = format!("{obj_name}.{prop_name}.{name}");
+					out.push((path, EditorSlot::Handler(obj_idx, prop_idx, kind)));
+				}
+			}
+		}
+		out
+	}
+
+	fn editor_mut(&mut self, slot: EditorSlot) -> Option<&mut EditorState> {
+		match slot {
+			EditorSlot::Function(obj, state, func) => {
+				self.editors.get_mut(obj)?.get_mut(state)?.get_mut(func)
+			}
+			EditorSlot::Handler(obj, prop, kind) => {
+				self.handler_editors.get_mut(obj)?.get_mut(prop)?[kind].as_mut()
+			}
+		}
+	}
+
+	/// The text and visibility of every editor by path, unsaved edits and all.
+	fn editor_texts(&mut self) -> Vec<(String, String, bool)> {
+		self.editor_slots()
+			.into_iter()
+			.filter_map(|(path, slot)| {
+				let editor = self.editor_mut(slot)?;
+				Some((path, editor.content.text(), editor.visible))
+			})
+			.collect()
+	}
+
+	/// Puts back what [`Tab::editor_texts`] saved after a rebuild, except for the editor of `path`.
+	fn restore_editor_texts(&mut self, texts: Vec<(String, String, bool)>, except: &str) {
+		for (path, slot) in self.editor_slots() {
+			let Some((_, text, visible)) = texts.iter().find(|(p, ..)| *p == path) else {
+				continue;
+			};
+
+			if let Some(editor) = self.editor_mut(slot) {
+				editor.visible = *visible;
+				if !path.eq_ignore_ascii_case(except) {
+					editor.content = iced::widget::text_editor::Content::with_text(text);
+				}
+			}
+		}
+	}
+
+	/// Parses the editors back into their functions, carrying debug lines over to the new instructions.
+	fn apply_editors(&mut self) -> Result<(), String> {
+		let mut parsed = self.pex.with_user_flag_names(|| self.parse_editors())?;
+
+		let mut changed = false;
+		for (path, mut editor) in edit::editors(&mut self.pex) {
+			let Some(idx) = parsed.iter().position(|(p, _)| *p == path) else {
+				continue;
+			};
+
+			let (_, func) = parsed.swap_remove(idx);
+			if *editor.function != func {
+				editor.set_function(func);
+				changed = true;
+			}
+		}
+
+		if changed && let Some(debug) = &mut self.pex.debuginfo {
+			debug.touch();
+		}
+		self.lines = source::LineMap::new(&self.pex);
+
+		Ok(())
+	}
+
+	/// Every editor's function by path, inside [`pex::Pex::with_user_flag_names`].
+	fn parse_editors(&self) -> Result<Vec<(String, pex::Function)>, String> {
+		let mut parsed = vec![];
+		for (obj_idx, (obj_name_idx, obj)) in self.pex.objects.iter().enumerate() {
+			let obj_name = self.pex.string(*obj_name_idx);
+
+			for (state_idx, state) in obj.states.iter().enumerate() {
+				let state_name = self.pex.string(state.name_idx);
+
+				for (func_idx, (name_idx, _)) in state.functions.iter().enumerate() {
+					let name = self.pex.string(*name_idx);
+					let text = self.editors[obj_idx][state_idx][func_idx].content.text();
+					let func = pex::Function::deserialize_ron(&text)
+						.map_err(|e| format!("Failed to parse {name}: {e}"))?;
+					parsed.push((pex::function_path(obj_name, state_name, name), func));
+				}
+			}
+
+			for (prop_idx, property) in obj.properties.iter().enumerate() {
+				let prop_name = self.pex.string(property.name_idx);
+				let handlers = self.handler_editors[obj_idx][prop_idx].iter();
+
+				for (kind, editor) in ["Get", "Set"].into_iter().zip(handlers) {
+					let Some(editor) = editor else {
+						continue;
+					};
+
+					let path = format!("{obj_name}.{prop_name}.{kind}");
+					let func = pex::Function::deserialize_ron(&editor.content.text())
+						.map_err(|e| format!("Failed to parse {path}: {e}"))?;
+					parsed.push((path, func));
+				}
+			}
+		}
+
+		Ok(parsed)
+	}
+}
+
+fn read_pex(path: &std::path::Path) -> Result<pex::Pex, String> {
+	let bytes =
+		std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+	pex::parse(&bytes).map_err(|e| format!("Failed to parse {}: {e}", path.display()))
+}
+
+/// Fields of the Main section, edited as text and checked when submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderField {
+	Major,
+	Minor,
+	GameId,
+	CompTime,
+	Src,
+	Username,
+	Machine,
+	// Of the active object
+	Parent,
+	AutoState,
+	DocString,
 }
 
 #[derive(Default)]
 struct App {
-	theme: Theme,
-
 	active: usize,
 	active_section: usize,
 	active_object: usize,
 	tabs: Vec<Tab>,
+
+	search_query: String,
+	// (tab_idx, hit)
+	search_results: Vec<(usize, search::Hit)>,
+
+	status: Option<String>,
+
+	// Extra folders to look for .psc files in.
+	source_dirs: Vec<std::path::PathBuf>,
+	// (state_idx, func_idx, line) highlighted in the source view.
+	selected_line: Option<(usize, usize, u16)>,
+
+	// Typed into the Strings section, added with Message::AddString.
+	new_string: String,
+	// A string that couldn't be removed, with everything that uses it.
+	blocked_removal: Option<(u16, Vec<search::Reference>)>,
+	// (idx, text) of a string being edited, set with Message::EditString.
+	string_input: Option<(u16, String)>,
+	// A string being edited that's used both as a name and as text, with everything that uses it.
+	shared_string: Option<(u16, Vec<search::Reference>)>,
+
+	// (var_idx, text) of the initial value being typed, set with Message::SetVariable.
+	variable_input: Option<(usize, String)>,
+	// Name and type typed in for Message::AddVariable.
+	new_variable: (String, String),
+	// A variable and everything that uses it.
+	variable_refs: Option<(String, Vec<search::Reference>)>,
+
+	// A header field being typed, set with Message::SetHeaderField.
+	header_input: Option<(HeaderField, String)>,
+
+	archives: Vec<bsa::Bsa>,
+	// (archive_idx, folder) expanded in the Archives section.
+	open_folders: Vec<(usize, String)>,
+	archive_filter: String,
+}
+
+/// What a user flag toggle sets the flag on, within the active object.
+#[derive(Debug, Clone, Copy)]
+enum FlagTarget {
+	Object,
+	// var_idx
+	Variable(usize),
+	// prop_idx
+	Property(usize),
+	// state_idx, func_idx
+	Function(usize, usize),
 }
 
 #[derive(Debug, Clone)]
@@ -48,13 +341,50 @@ enum Message {
 	SwitchSection(usize),
 	SwitchObject(usize),
 	ToggleEditor(usize, usize),
+	Merge,
+	Resolve(usize, merge::Side),
+	Save,
+	SearchInput(String),
+	Search,
+	OpenHit(usize, search::Location),
+	AddSourceDir,
+	SelectLine(usize, usize, u16),
+	// Whether to sort as well
+	CompactStrings(bool),
+	RemoveString(u16),
+	StringInput(u16, String),
+	EditString(u16),
+	// Whether to only change the string where it's used as text
+	ApplyString(bool),
+	CancelString,
+	NewStringInput(String),
+	AddString,
+	CloseBlockedRemoval,
+	ToggleUserFlag(FlagTarget, u8),
+	// prop_idx, 0 for the read handler or 1 for the write handler
+	HandlerEditor(usize, usize, iced::widget::text_editor::Action),
+	ToggleHandler(usize, usize),
+	VariableInput(usize, String),
+	SetVariable(usize),
+	RemoveVariable(usize),
+	FindVariable(usize),
+	NewVariableName(String),
+	NewVariableType(String),
+	AddVariable,
+	CloseVariableRefs,
+	HeaderInput(HeaderField, String),
+	SetHeaderField(HeaderField),
+	OpenArchive,
+	ToggleFolder(usize, String),
+	ArchiveFilter(String),
+	// archive_idx, entry_idx
+	OpenArchived(usize, usize),
 }
 
 impl App {
 	const BG_DARKER: Color = color!(20, 20, 20);
 	const BG: Color = color!(30, 30, 30);
 	const BG_GLOW: Color = color!(50, 50, 50);
-	const BG_GLOW_BRIGHT: Color = color!(70, 70, 70);
 
 	const TEXT: Color = Color::WHITE;
 
@@ -78,14 +408,58 @@ impl App {
 		}
 	}
 
-	pub fn view(&self) -> iced::Element<Message> {
+	fn with_files(paths: &[String]) -> Self {
+		let mut app = Self::default();
+		for path in paths.iter().map(std::path::PathBuf::from) {
+			let archive = path
+				.extension()
+				.is_some_and(|e| e.eq_ignore_ascii_case("bsa") || e.eq_ignore_ascii_case("ba2"));
+
+			let result = if archive {
+				bsa::Bsa::open(&path)
+					.map(|a| app.archives.push(a))
+					.map_err(|e| format!("Failed to read {}: {e}", path.display()))
+			} else {
+				read_pex(&path).map(|pex| app.tabs.push(Tab::new(path, pex, &app.source_dirs)))
+			};
+
+			if let Err(e) = result {
+				app.status = Some(e);
+			}
+		}
+
+		app
+	}
+
+	/// Forgets search hits into a tab whose functions or strings moved around.
+	fn drop_search_results(&mut self, tab_idx: usize) {
+		self.search_results.retain(|(t, _)| *t != tab_idx);
+	}
+
+	pub fn view(&self) -> iced::Element<'_, Message> {
 		if self.tabs.is_empty() {
 			return column![center(
-				button("Open a file..")
-					.style(|_, _| Self::style_button(false))
-					.on_press(Message::Open)
-					.padding(8)
+				column![
+					button("Open a file..")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::Open)
+						.padding(8),
+					button("Merge three files..")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::Merge)
+						.padding(8),
+					button("Browse an archive..")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::OpenArchive)
+						.padding(8),
+				]
+				.spacing(8)
+				.align_x(Center)
 			)]
+			.push_maybe(
+				(!self.archives.is_empty()).then(|| scrollable(self.view_archives()).height(Fill)),
+			)
+			.push_maybe(self.status.as_ref().map(text))
 			.width(Fill)
 			.height(Fill)
 			.into();
@@ -147,21 +521,65 @@ impl App {
 				.on_press(Message::Open),
 		);
 
+		let tabs = tabs.push(
+			button("Merge..")
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::Merge),
+		);
+
+		let tabs = tabs.push(
+			button("Archive..")
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::OpenArchive),
+		);
+
+		let tabs = tabs.push(
+			button("Sources..")
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::AddSourceDir),
+		);
+
+		let tabs = tabs.push(
+			button("Save")
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::Save),
+		);
+
 		let tab = &self.tabs[self.active];
 
 		let overview = column![].width(Fill);
 		let mut overview = overview.push(
-			scrollable(row![
-				button("Objects")
-					.on_press(Message::SwitchSection(0))
-					.style(|_, _| Self::style_button(self.active_section == 0)),
-				button("Strings")
-					.on_press(Message::SwitchSection(1))
-					.style(|_, _| Self::style_button(self.active_section == 1)),
-				button("Main")
-					.on_press(Message::SwitchSection(2))
-					.style(|_, _| Self::style_button(self.active_section == 2)),
-			])
+			scrollable(
+				row![
+					button("Objects")
+						.on_press(Message::SwitchSection(0))
+						.style(|_, _| Self::style_button(self.active_section == 0)),
+					button("Strings")
+						.on_press(Message::SwitchSection(1))
+						.style(|_, _| Self::style_button(self.active_section == 1)),
+					button("Main")
+						.on_press(Message::SwitchSection(2))
+						.style(|_, _| Self::style_button(self.active_section == 2)),
+					button("Search")
+						.on_press(Message::SwitchSection(4))
+						.style(|_, _| Self::style_button(self.active_section == 4)),
+				]
+				.push_maybe((!self.archives.is_empty()).then(|| {
+					button("Archives")
+						.on_press(Message::SwitchSection(5))
+						.style(|_, _| Self::style_button(self.active_section == 5))
+				}))
+				.push_maybe(
+					(!tab.conflicts.is_empty()
+						|| !tab.overlapping.is_empty()
+						|| !tab.revived.is_empty())
+					.then(|| {
+						button("Merge")
+							.on_press(Message::SwitchSection(3))
+							.style(|_, _| Self::style_button(self.active_section == 3))
+					}),
+				),
+			)
 			.direction(iced::widget::scrollable::Direction::Horizontal(
 				iced::widget::scrollable::Scrollbar::new(),
 			))
@@ -210,47 +628,69 @@ impl App {
 						.collect::<Vec<_>>(),
 				);
 
-				let (_, obj) = &tab.pex.objects[self.active_object];
+				let (obj_name_idx, obj) = &tab.pex.objects[self.active_object];
+				let obj_name = tab.pex.string(*obj_name_idx);
 				let state =
 					obj.states.iter().enumerate().map(|(state_idx, state)| {
 						let state_name = &tab.pex.stringtable[state.name_idx as usize];
 
 						let functions = state.functions.iter().enumerate().map(
-							|(func_idx, (name_idx, _func))| {
+							|(func_idx, (name_idx, func))| {
 								let name = &tab.pex.stringtable[*name_idx as usize];
 								let editor_state =
 									&tab.editors[self.active_object][state_idx][func_idx];
 
-								let mut col = column![
+								// Natives have no instructions to edit, so they only show their declaration.
+								let header = if func.is_native() {
+									row![
+										text(disasm::declaration(&tab.pex, name, func)).size(20),
+										container(text("native").size(12))
+											.style(|_| iced::widget::container::Style {
+												border: Self::BORDER,
+												..Default::default()
+											})
+											.padding([2, 6]),
+									]
+								} else {
 									row![
 										text(name).size(20),
 										button(if editor_state.visible { "<" } else { ">" })
 											.on_press(Message::ToggleEditor(state_idx, func_idx))
 									]
-									.align_y(Center)
-									.spacing(8)
+								};
+
+								let mut col = column![
+									header
+										.extend(Self::user_flag_toggles(
+											&tab.pex,
+											func.user_flags,
+											FlagTarget::Function(state_idx, func_idx),
+										))
+										.align_y(Center)
+										.spacing(8)
 								];
 
-								if editor_state.visible {
-									col = col.push(container(
-										text_editor(&editor_state.content)
-											.style(|_, _| iced::widget::text_editor::Style {
-												background: Self::BG.into(),
-												border: Self::BORDER,
-												selection: Self::BG_GLOW,
-												value: Self::TEXT,
-												icon: Self::TEXT,
-												placeholder: Self::TEXT,
-											})
-											.highlight(
-												"rust",
-												iced::highlighter::Theme::InspiredGitHub,
-											)
-											.on_action(move |a| {
+								if editor_state.visible && !func.is_native() {
+									let path = pex::function_path(obj_name, state_name, name);
+									let side =
+										tab.source.as_ref().zip(tab.lines.function(&path)).map(
+											|(source, lines)| {
+												self.view_source(
+													tab, state_idx, func_idx, func, source, lines,
+												)
+											},
+										);
+
+									col = col.push(
+										row![
+											Self::view_editor(&editor_state.content, move |a| {
 												Message::Editor(state_idx, func_idx, a)
 											})
-											.padding(8),
-									));
+											.width(Length::FillPortion(3))
+										]
+										.push_maybe(side)
+										.spacing(8),
+									);
 								}
 
 								col.spacing(5).into()
@@ -265,15 +705,114 @@ impl App {
 						.into()
 					});
 
-				editor = scrollable(column(state).padding(8));
+				let object_flags = row![text("User flags")]
+					.extend(Self::user_flag_toggles(
+						&tab.pex,
+						obj.user_flags,
+						FlagTarget::Object,
+					))
+					.align_y(Center)
+					.spacing(8);
+
+				editor = scrollable(
+					column![object_flags, self.view_variables(tab)]
+						.extend(state)
+						.push(self.view_properties(tab))
+						.spacing(20)
+						.padding(8),
+				);
 			}
 			1 => {
-				editor = scrollable(column(
-					tab.pex
-						.stringtable
-						.iter()
-						.enumerate()
-						.map(|(i, s)| {
+				let actions = row![
+					button("Compact")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::CompactStrings(false)),
+					button("Compact and sort")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::CompactStrings(true)),
+				]
+				.spacing(8)
+				.padding(4);
+
+				let mut strings = column![actions];
+				if let Some((idx, refs)) = &self.blocked_removal {
+					let places = refs.iter().map(|r| {
+						let kind = if r.literal { "text" } else { "name" };
+						text(format!("{} ({kind})", r.place)).size(12).into()
+					});
+
+					strings = strings.push(
+						container(
+							column![text(format!(
+								"String {idx} is used in {} places, so it can't be removed:",
+								refs.len()
+							))]
+							.extend(places)
+							.push(
+								button("Close")
+									.style(|_, _| Self::style_button(false))
+									.on_press(Message::CloseBlockedRemoval),
+							)
+							.spacing(4),
+						)
+						.style(|_| iced::widget::container::Style {
+							border: Self::BORDER,
+							..Default::default()
+						})
+						.padding(8),
+					);
+				}
+
+				if let Some((idx, refs)) = &self.shared_string {
+					let places = refs.iter().map(|r| {
+						let kind = if r.literal { "text" } else { "name" };
+						text(format!("{} ({kind})", r.place)).size(12).into()
+					});
+
+					strings = strings.push(
+						container(
+							column![text(format!(
+								"String {idx} is used both as a name and as text:"
+							))]
+							.extend(places)
+							.push(
+								row![
+									button("Change both")
+										.style(|_, _| Self::style_button(false))
+										.on_press(Message::ApplyString(false)),
+									button("Only change the text")
+										.style(|_, _| Self::style_button(false))
+										.on_press(Message::ApplyString(true)),
+									button("Cancel")
+										.style(|_, _| Self::style_button(false))
+										.on_press(Message::CancelString),
+								]
+								.spacing(8),
+							)
+							.spacing(4),
+						)
+						.style(|_| iced::widget::container::Style {
+							border: Self::BORDER,
+							..Default::default()
+						})
+						.padding(8),
+					);
+				}
+
+				let add = row![
+					text_input("New string", &self.new_string)
+						.on_input(Message::NewStringInput)
+						.on_submit(Message::AddString),
+					button("Add")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::AddString),
+				]
+				.spacing(8)
+				.padding(4);
+
+				editor = scrollable(
+					strings
+						.extend(tab.pex.stringtable.iter().enumerate().map(|(i, s)| {
 							row![
 								container(text(format!("{}", i)))
 									.style(|_| {
@@ -284,30 +823,53 @@ impl App {
 									})
 									.width(Fill)
 									.height(Fill),
-								container(text(s))
-									.style(|_| iced::widget::container::Style {
-										border: Self::BORDER,
-										..Default::default()
-									})
-									.width(Fill)
-									.height(Fill),
-								container(button("x").style(|_, _| Self::style_button(false)))
-									.style(|_| iced::widget::container::Style {
-										border: Self::BORDER,
-										..Default::default()
-									})
-									.width(Fill)
-									.height(Fill)
+								container(
+									text_input(
+										"",
+										match &self.string_input {
+											Some((idx, value)) if *idx as usize == i => value,
+											_ => s,
+										}
+									)
+									.on_input(move |value| Message::StringInput(i as u16, value))
+									.on_submit(Message::EditString(i as u16)),
+								)
+								.style(|_| iced::widget::container::Style {
+									border: Self::BORDER,
+									..Default::default()
+								})
+								.width(Fill)
+								.height(Fill),
+								container(
+									button("x")
+										.style(|_, _| Self::style_button(false))
+										.on_press(Message::RemoveString(i as u16)),
+								)
+								.style(|_| iced::widget::container::Style {
+									border: Self::BORDER,
+									..Default::default()
+								})
+								.width(Fill)
+								.height(Fill)
 							]
 							.padding(4)
 							.height(40)
 							.into()
-						})
-						.collect::<Vec<_>>(),
-				));
+						}))
+						.push(add),
+				);
 			}
 			2 => {
-				editor = scrollable(column![]);
+				editor = scrollable(self.view_main(tab));
+			}
+			3 => {
+				editor = scrollable(self.view_merge(tab));
+			}
+			4 => {
+				editor = scrollable(self.view_search());
+			}
+			5 => {
+				editor = scrollable(self.view_archives());
 			}
 			_ => unreachable!(),
 		}
@@ -345,7 +907,7 @@ impl App {
 
 		let body = row![tree, editor];
 
-		container(column![tabs, body])
+		container(column![tabs, body].push_maybe(self.status.as_ref().map(text)))
 			.style(|_| iced::widget::container::Style {
 				background: Some(Self::BG.into()),
 				..Default::default()
@@ -353,6 +915,555 @@ impl App {
 			.into()
 	}
 
+	/// The header, the active object's metadata and some numbers about the script.
+	fn view_main<'a>(&'a self, tab: &'a Tab) -> iced::Element<'a, Message> {
+		use HeaderField::*;
+
+		let pex = &tab.pex;
+		let (obj_name_idx, obj) = &pex.objects[self.active_object];
+
+		let field = |label: &'a str, field: HeaderField, value: String| {
+			let value = match &self.header_input {
+				Some((f, input)) if *f == field => input.clone(),
+				_ => value,
+			};
+
+			row![
+				text(label).width(Length::FillPortion(1)),
+				text_input("", &value)
+					.on_input(move |v| Message::HeaderInput(field, v))
+					.on_submit(Message::SetHeaderField(field))
+					.width(Length::FillPortion(3)),
+			]
+			.align_y(Center)
+			.spacing(8)
+			.into()
+		};
+
+		let header = [
+			field("Major version", Major, pex.major.to_string()),
+			field("Minor version", Minor, pex.minor.to_string()),
+			field("Game id", GameId, pex.gameid.to_string()),
+			field(
+				"Compiled (UTC)",
+				CompTime,
+				pex::format_timestamp(pex.comptime),
+			),
+			field("Source", Src, pex.src.clone()),
+			field("User", Username, pex.username.clone()),
+			field("Machine", Machine, pex.machine.clone()),
+		];
+
+		let object = [
+			field("Parent", Parent, pex.string(obj.parent_name_idx).to_owned()),
+			field(
+				"Auto state",
+				AutoState,
+				pex.string(obj.auto_state_name_idx).to_owned(),
+			),
+			field(
+				"Doc string",
+				DocString,
+				pex.string(obj.doc_string_idx).to_owned(),
+			),
+		];
+
+		let functions = pex.function_paths();
+		let stats = [
+			format!("Objects: {}", pex.objects.len()),
+			format!(
+				"Functions: {} ({} native)",
+				functions.len(),
+				functions.iter().filter(|(_, f)| f.is_native()).count()
+			),
+			format!(
+				"Instructions: {}",
+				functions
+					.iter()
+					.map(|(_, f)| f.instructions.len())
+					.sum::<usize>()
+			),
+			format!(
+				"Strings: {} ({} bytes)",
+				pex.stringtable.len(),
+				pex.stringtable.iter().map(|s| s.len() + 2).sum::<usize>()
+			),
+			match &pex.debuginfo {
+				Some(debug) => format!(
+					"Debug info: {} functions, modified {}",
+					debug.functions.len(),
+					pex::format_timestamp(debug.modtime)
+				),
+				None => "Debug info: none".to_owned(),
+			},
+		];
+
+		column![text("Header").size(20)]
+			.extend(header)
+			.push(text(format!("Object: {}", pex.string(*obj_name_idx))).size(20))
+			.extend(object)
+			.push(text("Summary").size(20))
+			.extend(stats.into_iter().map(|s| text(s).into()))
+			.spacing(8)
+			.padding(8)
+			.into()
+	}
+
+	fn view_editor<'a>(
+		content: &'a iced::widget::text_editor::Content,
+		on_action: impl Fn(iced::widget::text_editor::Action) -> Message + 'a,
+	) -> iced::widget::Container<'a, Message> {
+		container(
+			text_editor(content)
+				.style(|_, _| iced::widget::text_editor::Style {
+					background: Self::BG.into(),
+					border: Self::BORDER,
+					selection: Self::BG_GLOW,
+					value: Self::TEXT,
+					icon: Self::TEXT,
+					placeholder: Self::TEXT,
+				})
+				.highlight("rust", iced::highlighter::Theme::InspiredGitHub)
+				.on_action(on_action)
+				.padding(8),
+		)
+	}
+
+	/// A toggle per user flag the script declares, lit for the ones set in `flags`.
+	fn user_flag_toggles<'a>(
+		pex: &'a pex::Pex,
+		flags: pex::UserFlags,
+		target: FlagTarget,
+	) -> impl Iterator<Item = iced::Element<'a, Message>> {
+		pex.userflags
+			.iter()
+			.filter(|(_, bit)| *bit < 32)
+			.map(move |(flag_idx, bit)| {
+				let set = flags.contains(*bit);
+				button(text(pex.string(*flag_idx)).size(12))
+					.style(move |_, _| Self::style_button(set))
+					.on_press(Message::ToggleUserFlag(target, *bit))
+					.into()
+			})
+	}
+
+	/// The active object's variables, with their initial values editable in place.
+	fn view_variables<'a>(&'a self, tab: &'a Tab) -> iced::Element<'a, Message> {
+		let (_, obj) = &tab.pex.objects[self.active_object];
+
+		let cell = |content: iced::Element<'a, Message>| {
+			container(content)
+				.style(|_| iced::widget::container::Style {
+					border: Self::BORDER,
+					..Default::default()
+				})
+				.padding(4)
+				.width(Fill)
+		};
+
+		let variables = obj.variables.iter().enumerate().map(
+			|(var_idx, (name_idx, type_idx, user_flags, data))| {
+				let value = match &self.variable_input {
+					Some((idx, input)) if *idx == var_idx => input.clone(),
+					_ => disasm::variable_data(&tab.pex, data),
+				};
+
+				row![
+					cell(text(tab.pex.string(*name_idx)).into()),
+					cell(text(tab.pex.string(*type_idx)).into()),
+					cell(
+						text_input("None", &value)
+							.on_input(move |v| Message::VariableInput(var_idx, v))
+							.on_submit(Message::SetVariable(var_idx))
+							.into()
+					),
+					cell(
+						row(Self::user_flag_toggles(
+							&tab.pex,
+							*user_flags,
+							FlagTarget::Variable(var_idx),
+						))
+						.spacing(4)
+						.into()
+					),
+					button("Uses")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::FindVariable(var_idx)),
+					button("x")
+						.style(|_, _| Self::style_button(false))
+						.on_press(Message::RemoveVariable(var_idx)),
+				]
+				.align_y(Center)
+				.into()
+			},
+		);
+
+		let add = row![
+			text_input("Name", &self.new_variable.0).on_input(Message::NewVariableName),
+			text_input("Type", &self.new_variable.1)
+				.on_input(Message::NewVariableType)
+				.on_submit(Message::AddVariable),
+			button("Add")
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::AddVariable),
+		]
+		.spacing(8);
+
+		let refs = self.variable_refs.as_ref().map(|(name, refs)| {
+			container(
+				column![text(format!("{name} is used in {} places:", refs.len()))]
+					.extend(refs.iter().map(|r| text(&r.place).size(12).into()))
+					.push(
+						button("Close")
+							.style(|_, _| Self::style_button(false))
+							.on_press(Message::CloseVariableRefs),
+					)
+					.spacing(4),
+			)
+			.style(|_| iced::widget::container::Style {
+				border: Self::BORDER,
+				..Default::default()
+			})
+			.padding(8)
+		});
+
+		column![text("Variables")]
+			.push_maybe(refs)
+			.extend(variables)
+			.push(add)
+			.spacing(4)
+			.into()
+	}
+
+	/// The active object's properties, with editors for their handler functions.
+	fn view_properties<'a>(&'a self, tab: &'a Tab) -> iced::Element<'a, Message> {
+		let (_, obj) = &tab.pex.objects[self.active_object];
+
+		let properties = obj
+			.properties
+			.iter()
+			.enumerate()
+			.map(|(prop_idx, property)| {
+				let editors = &tab.handler_editors[self.active_object][prop_idx];
+
+				let mut header = row![
+					text(tab.pex.string(property.name_idx)).size(20),
+					text(tab.pex.string(property.type_idx)),
+					text(property.flags.to_string()).size(12),
+				];
+				if let Some(var) = property.auto_var_name {
+					header = header.push(text(format!("auto: {}", tab.pex.string(var))).size(12));
+				}
+				header = header.extend(Self::user_flag_toggles(
+					&tab.pex,
+					property.user_flags,
+					FlagTarget::Property(prop_idx),
+				));
+
+				for (kind, (label, editor)) in ["Get", "Set"].iter().zip(editors).enumerate() {
+					if let Some(editor) = editor {
+						header = header.push(
+							button(text(*label).size(12))
+								.style(move |_, _| Self::style_button(editor.visible))
+								.on_press(Message::ToggleHandler(prop_idx, kind)),
+						);
+					}
+				}
+
+				let mut col = column![header.align_y(Center).spacing(8)];
+
+				let doc = tab.pex.string(property.doc_string_idx);
+				if !doc.is_empty() {
+					col = col.push(text(doc).size(12));
+				}
+
+				for (kind, editor) in editors.iter().enumerate() {
+					if let Some(editor) = editor.as_ref().filter(|e| e.visible) {
+						col = col.push(Self::view_editor(&editor.content, move |a| {
+							Message::HandlerEditor(prop_idx, kind, a)
+						}));
+					}
+				}
+
+				col.spacing(5).into()
+			});
+
+		column![text("Properties")]
+			.push_maybe(obj.properties.is_empty().then(|| text("(none)").size(12)))
+			.extend(properties)
+			.spacing(10)
+			.into()
+	}
+
+	fn view_merge<'a>(&'a self, tab: &'a Tab) -> iced::Element<'a, Message> {
+		let listing = |function: &Option<pex::Function>| {
+			let lines = match function {
+				Some(f) => f
+					.instructions
+					.iter()
+					.map(|i| disasm::instruction(&tab.pex, i))
+					.collect::<Vec<_>>()
+					.join("\n"),
+				None => "(removed)".to_owned(),
+			};
+
+			container(text(lines).font(iced::Font::MONOSPACE))
+				.style(|_| iced::widget::container::Style {
+					border: Self::BORDER,
+					..Default::default()
+				})
+				.padding(4)
+				.width(Fill)
+		};
+
+		let conflicts = tab.conflicts.iter().enumerate().map(|(i, conflict)| {
+			column![
+				row![
+					text(conflict.path()).size(20),
+					button("Ours")
+						.style(move |_, _| {
+							Self::style_button(conflict.resolution == merge::Side::Ours)
+						})
+						.on_press(Message::Resolve(i, merge::Side::Ours)),
+					button("Theirs")
+						.style(move |_, _| {
+							Self::style_button(conflict.resolution == merge::Side::Theirs)
+						})
+						.on_press(Message::Resolve(i, merge::Side::Theirs)),
+				]
+				.align_y(Center)
+				.spacing(8),
+				row![listing(&conflict.ours), listing(&conflict.theirs)].spacing(8)
+			]
+			.spacing(5)
+			.into()
+		});
+
+		let overlapping = tab
+			.overlapping
+			.iter()
+			.map(|path| text(format!("Changed on both sides, kept ours: {path}")).into());
+
+		let revived = tab.revived.iter().map(|name| {
+			text(format!(
+				"Deleted on one side and changed on the other, kept the changed object: {name}"
+			))
+			.into()
+		});
+
+		column(conflicts.chain(overlapping).chain(revived))
+			.spacing(20)
+			.padding(8)
+			.into()
+	}
+
+	/// A function's instructions next to the source lines they came from.
+	/// Clicking either side highlights the line in both.
+	fn view_source<'a>(
+		&'a self,
+		tab: &'a Tab,
+		state_idx: usize,
+		func_idx: usize,
+		func: &'a pex::Function,
+		source: &'a [String],
+		lines: &'a [u16],
+	) -> iced::Element<'a, Message> {
+		// Lines of surrounding source shown above and below the function.
+		const CONTEXT: u16 = 2;
+
+		// Debug info can't point past line 65535, so a longer file can't be what this was compiled from.
+		let Ok(source_len) = u16::try_from(source.len()) else {
+			return text(format!(
+				"Source has {} lines, more than debug info can refer to",
+				source.len()
+			))
+			.width(Length::FillPortion(4))
+			.into();
+		};
+
+		let selected = move |line: u16| self.selected_line == Some((state_idx, func_idx, line));
+
+		let row_button = move |label: String, line: u16| {
+			button(text(label).font(iced::Font::MONOSPACE).size(12))
+				.style(move |_, _| Self::style_button(selected(line)))
+				.on_press(Message::SelectLine(state_idx, func_idx, line))
+				.padding(2)
+				.width(Fill)
+				.into()
+		};
+
+		let instructions = func.instructions.iter().enumerate().map(|(pc, i)| {
+			let line = lines.get(pc).copied().unwrap_or_default();
+			row_button(
+				format!("{line:>4}  {}", disasm::instruction(&tab.pex, i)),
+				line,
+			)
+		});
+
+		// Debug info lines are 1-based.
+		let first = lines
+			.iter()
+			.min()
+			.copied()
+			.unwrap_or(1)
+			.saturating_sub(CONTEXT)
+			.max(1);
+		let last = lines
+			.iter()
+			.max()
+			.copied()
+			.unwrap_or(0)
+			.saturating_add(CONTEXT)
+			.min(source_len);
+		let source = (first..=last)
+			.map(|line| row_button(format!("{line:>4}  {}", source[line as usize - 1]), line));
+
+		row![column(instructions).width(Fill), column(source).width(Fill)]
+			.spacing(4)
+			.width(Length::FillPortion(4))
+			.into()
+	}
+
+	/// Scripts in the opened archives by folder, opening as tabs when clicked.
+	fn view_archives(&self) -> iced::Element<'_, Message> {
+		// Vanilla archives have thousands of scripts in one folder.
+		const MAX_ENTRIES: usize = 500;
+
+		let filter = self.archive_filter.to_lowercase();
+
+		let input = text_input("Filter scripts..", &self.archive_filter)
+			.on_input(Message::ArchiveFilter)
+			.padding(8);
+
+		let archives = self
+			.archives
+			.iter()
+			.enumerate()
+			.map(|(archive_idx, archive)| {
+				let scripts = archive
+					.entries
+					.iter()
+					.enumerate()
+					.filter(|(_, e)| e.is_script() && e.path.contains(&filter))
+					.collect::<Vec<_>>();
+
+				let mut folders = scripts.iter().map(|(_, e)| e.folder()).collect::<Vec<_>>();
+				folders.sort_unstable();
+				folders.dedup();
+
+				let folders = folders.into_iter().map(|folder| {
+					let open = self
+						.open_folders
+						.iter()
+						.any(|(i, f)| *i == archive_idx && f == folder);
+					let entries = scripts.iter().filter(|(_, e)| e.folder() == folder);
+					let count = entries.clone().count();
+
+					let mut col = column![
+						button(text(format!(
+							"{} {folder} ({count})",
+							if open { "v" } else { ">" }
+						)))
+						.style(move |_, _| Self::style_button(open))
+						.on_press(Message::ToggleFolder(archive_idx, folder.to_owned()))
+						.width(Fill)
+					];
+
+					if open {
+						col = col.extend(entries.take(MAX_ENTRIES).map(|(entry_idx, e)| {
+							button(text(e.file_name()).font(iced::Font::MONOSPACE).size(12))
+								.style(|_, _| Self::style_button(false))
+								.on_press(Message::OpenArchived(archive_idx, *entry_idx))
+								.padding([2, 24])
+								.width(Fill)
+								.into()
+						}));
+						if count > MAX_ENTRIES {
+							col = col.push(
+								text(format!(
+									"{} more, filter to narrow them down",
+									count - MAX_ENTRIES
+								))
+								.size(12),
+							);
+						}
+					}
+
+					col.spacing(2).into()
+				});
+
+				column![
+					text(format!(
+						"{} (v{}, {} scripts)",
+						archive
+							.path
+							.file_name()
+							.unwrap_or_default()
+							.to_string_lossy(),
+						archive.version,
+						scripts.len()
+					))
+					.size(20)
+				]
+				.extend(folders)
+				.spacing(4)
+				.into()
+			});
+
+		column![input]
+			.extend(archives)
+			.spacing(12)
+			.padding(8)
+			.into()
+	}
+
+	fn view_search(&self) -> iced::Element<'_, Message> {
+		// Rendering thousands of rows makes the view sluggish.
+		const MAX_RESULTS: usize = 500;
+
+		let input = text_input(
+			"CALLSTATIC Debug Trace, \"MQ101\", PROPSET..",
+			&self.search_query,
+		)
+		.on_input(Message::SearchInput)
+		.on_submit(Message::Search)
+		.padding(8);
+
+		// Hits into scripts that changed since are left out rather than pointing at the wrong place.
+		let results = self
+			.search_results
+			.iter()
+			.filter_map(|(tab_idx, hit)| {
+				let tab = self.tabs.get(*tab_idx)?;
+				let location = hit.location.describe(&tab.pex)?;
+				Some((tab_idx, hit, tab, location))
+			})
+			.take(MAX_RESULTS)
+			.map(|(tab_idx, hit, tab, location)| {
+				let file = tab.path.file_name().unwrap().to_string_lossy();
+				let location = format!("{file} › {location}");
+
+				button(column![
+					text(location).size(12),
+					text(&hit.text).font(iced::Font::MONOSPACE)
+				])
+				.style(|_, _| Self::style_button(false))
+				.on_press(Message::OpenHit(*tab_idx, hit.location))
+				.width(Fill)
+				.into()
+			});
+
+		let summary = match self.search_results.len() {
+			n if n > MAX_RESULTS => format!("{n} results, showing the first {MAX_RESULTS}"),
+			n => format!("{n} results"),
+		};
+
+		column![input, text(summary), column(results).spacing(4)]
+			.spacing(8)
+			.padding(8)
+			.into()
+	}
+
 	pub fn update(&mut self, message: Message) -> Task<Message> {
 		match message {
 			Message::Open => 'blk: {
@@ -365,57 +1476,22 @@ impl App {
 				};
 
 				for path in paths {
-					let bytes = std::fs::read(&path).expect("Failed to read file");
-					let pex = pex::parse(&bytes).expect("This isn't a .pex file");
-
-					let rons = pex
-						.objects
-						.iter()
-						.map(|(_, o)| {
-							o.states
-								.iter()
-								.map(|s| {
-									s.functions
-										.iter()
-										.map(|(_, f)| f.serialize_ron())
-										.collect::<Vec<_>>()
-								})
-								.collect::<Vec<_>>()
-						})
-						.collect::<Vec<_>>();
-
-					let editors = rons
-						.iter()
-						.map(|object_ron| {
-							object_ron
-								.iter()
-								.map(|state_ron| {
-									state_ron
-										.iter()
-										.map(|function_ron| EditorState {
-											content: iced::widget::text_editor::Content::with_text(
-												&function_ron,
-											),
-											visible: false,
-										})
-										.collect::<Vec<_>>()
-								})
-								.collect::<Vec<_>>()
-						})
-						.collect::<Vec<_>>();
-
-					self.tabs.push(Tab {
-						path,
-						content: bytes,
-						pex,
-						rons,
-						editors,
-					})
+					match read_pex(&path) {
+						Ok(pex) => self.tabs.push(Tab::new(path, pex, &self.source_dirs)),
+						Err(e) => self.status = Some(e),
+					}
 				}
 			}
 
 			Message::SwitchTab(i) => {
 				self.active = i;
+				self.selected_line = None;
+				self.blocked_removal = None;
+				self.string_input = None;
+				self.shared_string = None;
+				self.variable_input = None;
+				self.variable_refs = None;
+				self.header_input = None;
 			}
 
 			Message::SwitchSection(i) => {
@@ -424,6 +1500,179 @@ impl App {
 
 			Message::SwitchObject(i) => {
 				self.active_object = i;
+				self.selected_line = None;
+				self.variable_input = None;
+				self.variable_refs = None;
+				self.header_input = None;
+			}
+
+			Message::SelectLine(state_idx, func_idx, line) => {
+				self.selected_line = Some((state_idx, func_idx, line));
+			}
+
+			Message::CompactStrings(sort) => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				// Both sides of a conflict point into the string table, so it can't be renumbered under them.
+				if !tab.conflicts.is_empty() {
+					self.status = Some("Strings can't be removed while merging".into());
+					break 'blk;
+				}
+
+				// Editors hold string indices, so they have to be in the pex before renumbering.
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let dropped = tab.pex.compact_strings(sort);
+				tab.rebuild_editors();
+				self.drop_search_results(self.active);
+				self.string_input = None;
+				self.shared_string = None;
+				self.status = Some(format!("Dropped {dropped} unused strings"));
+			}
+
+			Message::RemoveString(idx) => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				if !tab.conflicts.is_empty() {
+					self.status = Some("Strings can't be removed while merging".into());
+					break 'blk;
+				}
+
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				if tab.pex.remove_string(idx) {
+					tab.rebuild_editors();
+					self.drop_search_results(self.active);
+					self.blocked_removal = None;
+					self.string_input = None;
+					self.shared_string = None;
+				} else {
+					self.blocked_removal = Some((idx, search::references(&tab.pex, idx)));
+				}
+			}
+
+			Message::StringInput(idx, value) => {
+				self.string_input = Some((idx, value));
+				self.shared_string = None;
+			}
+
+			Message::EditString(idx) => 'blk: {
+				if self.string_input.as_ref().is_none_or(|(i, _)| *i != idx) {
+					break 'blk;
+				}
+
+				let tab = &self.tabs[self.active];
+				let refs = search::references(&tab.pex, idx);
+				if refs.iter().any(|r| r.literal) && refs.iter().any(|r| !r.literal) {
+					self.shared_string = Some((idx, refs));
+				} else {
+					return self.update(Message::ApplyString(false));
+				}
+			}
+
+			Message::ApplyString(split) => 'blk: {
+				self.shared_string = None;
+				let Some((idx, value)) = self.string_input.take() else {
+					break 'blk;
+				};
+
+				let tab = &mut self.tabs[self.active];
+				if !split {
+					if let Some(s) = tab.pex.stringtable.get_mut(idx as usize) {
+						*s = value;
+					}
+					break 'blk;
+				}
+
+				// Splitting points instructions at another string, so editors have to be in the pex first.
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let new = tab.pex.split_string(idx, &value);
+				tab.rebuild_editors();
+				self.drop_search_results(self.active);
+				self.status = Some(format!("Text uses of string {idx} now use string {new}"));
+			}
+
+			Message::CancelString => {
+				self.string_input = None;
+				self.shared_string = None;
+			}
+
+			Message::NewStringInput(value) => {
+				self.new_string = value;
+			}
+
+			Message::AddString => {
+				let tab = &mut self.tabs[self.active];
+				let value = std::mem::take(&mut self.new_string);
+				self.status = Some(match tab.pex.stringtable.iter().position(|s| *s == value) {
+					Some(idx) => format!("Already have that as string {idx}"),
+					None if tab.pex.stringtable.len() >= pex::MAX_STRINGS => {
+						format!("Scripts can't have more than {} strings", pex::MAX_STRINGS)
+					}
+					None => {
+						tab.pex.stringtable.push(value);
+						format!("Added string {}", tab.pex.stringtable.len() - 1)
+					}
+				});
+			}
+
+			Message::ToggleUserFlag(target, bit) => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let obj = &mut tab.pex.objects[self.active_object].1;
+				let flags = match target {
+					FlagTarget::Object => &mut obj.user_flags,
+					FlagTarget::Variable(var_idx) => &mut obj.variables[var_idx].2,
+					FlagTarget::Property(prop_idx) => &mut obj.properties[prop_idx].user_flags,
+					FlagTarget::Function(state_idx, func_idx) => {
+						&mut obj.states[state_idx].functions[func_idx].1.user_flags
+					}
+				};
+				flags.set(bit, !flags.contains(bit));
+
+				if let FlagTarget::Function(state_idx, func_idx) = target {
+					let (_, func) = &tab.pex.objects[self.active_object].1.states[state_idx]
+						.functions[func_idx];
+					let ron = tab.pex.with_user_flag_names(|| func.serialize_ron());
+					tab.editors[self.active_object][state_idx][func_idx].content =
+						iced::widget::text_editor::Content::with_text(&ron);
+				}
+			}
+
+			Message::CloseBlockedRemoval => {
+				self.blocked_removal = None;
+			}
+
+			Message::AddSourceDir => 'blk: {
+				let Some(dir) = rfd::FileDialog::new()
+					.set_title("Select a folder with .psc sources")
+					.pick_folder()
+				else {
+					break 'blk;
+				};
+
+				self.source_dirs.push(dir);
+				for tab in &mut self.tabs {
+					tab.load_source(&self.source_dirs);
+				}
+
+				let found = self.tabs.iter().filter(|t| t.source.is_some()).count();
+				self.status = Some(format!(
+					"Found sources for {found} of {} open scripts",
+					self.tabs.len()
+				));
 			}
 
 			Message::Editor(state_idx, func_idx, action) => {
@@ -436,6 +1685,412 @@ impl App {
 				self.tabs[self.active].editors[self.active_object][state_idx][func_idx].visible ^=
 					true;
 			}
+
+			Message::VariableInput(var_idx, value) => {
+				self.variable_input = Some((var_idx, value));
+			}
+
+			Message::SetVariable(var_idx) => 'blk: {
+				let Some((idx, input)) = self.variable_input.take() else {
+					break 'blk;
+				};
+				if idx != var_idx {
+					break 'blk;
+				}
+
+				let tab = &mut self.tabs[self.active];
+				let (name_idx, type_idx, ..) = tab.pex.objects[self.active_object].1.variables[idx];
+				let type_name = tab.pex.string(type_idx).to_owned();
+
+				match tab.pex.parse_value(&type_name, &input) {
+					Some(value) => {
+						tab.pex.objects[self.active_object].1.variables[idx].3 = value;
+						self.status = None;
+					}
+					None => {
+						self.status = Some(format!(
+							"{input:?} isn't a valid {type_name} for {}",
+							tab.pex.string(name_idx)
+						));
+						self.variable_input = Some((idx, input));
+					}
+				}
+			}
+
+			Message::FindVariable(var_idx) => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let (name_idx, ..) = tab.pex.objects[self.active_object].1.variables[var_idx];
+				let name = tab.pex.string(name_idx);
+				let refs = search::variable_references(&tab.pex, self.active_object, name);
+				self.variable_refs = Some((name.to_owned(), refs));
+			}
+
+			Message::RemoveVariable(var_idx) => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let (name_idx, ..) = tab.pex.objects[self.active_object].1.variables[var_idx];
+				let name = tab.pex.string(name_idx).to_owned();
+				let refs = search::variable_references(&tab.pex, self.active_object, &name);
+				if !refs.is_empty() {
+					self.status = Some(format!("{name} is still used, so it can't be removed"));
+					self.variable_refs = Some((name, refs));
+					break 'blk;
+				}
+
+				tab.pex.objects[self.active_object]
+					.1
+					.variables
+					.remove(var_idx);
+				self.variable_input = None;
+				self.variable_refs = None;
+			}
+
+			Message::NewVariableName(name) => {
+				self.new_variable.0 = name;
+			}
+
+			Message::NewVariableType(type_name) => {
+				self.new_variable.1 = type_name;
+			}
+
+			Message::AddVariable => 'blk: {
+				let (name, type_name) = &self.new_variable;
+				let (name, type_name) = (name.trim(), type_name.trim());
+				if name.is_empty() || type_name.is_empty() {
+					self.status = Some("Variables need a name and a type".into());
+					break 'blk;
+				}
+
+				let tab = &mut self.tabs[self.active];
+				let exists = tab.pex.objects[self.active_object]
+					.1
+					.variables
+					.iter()
+					.any(|(n, ..)| tab.pex.string(*n).eq_ignore_ascii_case(name));
+				if exists {
+					self.status = Some(format!("There's already a variable named {name}"));
+					break 'blk;
+				}
+
+				let name_idx = tab.pex.intern(name);
+				let type_idx = tab.pex.intern(type_name);
+				tab.pex.objects[self.active_object].1.variables.push((
+					name_idx,
+					type_idx,
+					pex::UserFlags::default(),
+					pex::VariableData::Null,
+				));
+				self.new_variable = Default::default();
+			}
+
+			Message::HeaderInput(field, value) => {
+				self.header_input = Some((field, value));
+			}
+
+			Message::SetHeaderField(field) => 'blk: {
+				let Some((f, input)) = self.header_input.take() else {
+					break 'blk;
+				};
+				if f != field {
+					break 'blk;
+				}
+
+				let tab = &mut self.tabs[self.active];
+				let pex = &mut tab.pex;
+				let object = self.active_object;
+
+				let valid = match field {
+					HeaderField::Major => input.trim().parse().map(|v| pex.major = v).is_ok(),
+					HeaderField::Minor => input.trim().parse().map(|v| pex.minor = v).is_ok(),
+					HeaderField::GameId => input.trim().parse().map(|v| pex.gameid = v).is_ok(),
+					HeaderField::CompTime => pex::parse_timestamp(&input)
+						.map(|v| pex.comptime = v)
+						.is_some(),
+					HeaderField::Src => {
+						pex.src = input.clone();
+						true
+					}
+					HeaderField::Username => {
+						pex.username = input.clone();
+						true
+					}
+					HeaderField::Machine => {
+						pex.machine = input.clone();
+						true
+					}
+					HeaderField::Parent => {
+						pex.objects[object].1.parent_name_idx = pex.intern(input.trim());
+						true
+					}
+					// Has to be one of the object's states, or none.
+					HeaderField::AutoState => {
+						let name = input.trim();
+						let state = pex.objects[object]
+							.1
+							.states
+							.iter()
+							.map(|s| s.name_idx)
+							.find(|i| pex.string(*i).eq_ignore_ascii_case(name));
+						let idx = state.or_else(|| name.is_empty().then(|| pex.intern("")));
+						idx.map(|idx| pex.objects[object].1.auto_state_name_idx = idx)
+							.is_some()
+					}
+					HeaderField::DocString => {
+						pex.objects[object].1.doc_string_idx = pex.intern(&input);
+						true
+					}
+				};
+
+				if valid {
+					self.status = None;
+				} else {
+					self.status = Some(format!("{input:?} isn't a valid {field:?}"));
+					self.header_input = Some((field, input));
+				}
+			}
+
+			Message::OpenArchive => 'blk: {
+				let Some(path) = rfd::FileDialog::new()
+					.set_title("Open an archive")
+					.add_filter("Archives", &["bsa", "ba2"])
+					.pick_file()
+				else {
+					break 'blk;
+				};
+
+				match bsa::Bsa::open(&path) {
+					Ok(archive) => {
+						self.status = Some(format!(
+							"{} has {} scripts",
+							path.display(),
+							archive.scripts().count()
+						));
+						self.archives.push(archive);
+						if !self.tabs.is_empty() {
+							self.active_section = 5;
+						}
+					}
+					Err(e) => self.status = Some(format!("Failed to read {}: {e}", path.display())),
+				}
+			}
+
+			Message::ToggleFolder(archive_idx, folder) => {
+				let len = self.open_folders.len();
+				self.open_folders
+					.retain(|(i, f)| !(*i == archive_idx && *f == folder));
+				if self.open_folders.len() == len {
+					self.open_folders.push((archive_idx, folder));
+				}
+			}
+
+			Message::ArchiveFilter(filter) => {
+				self.archive_filter = filter;
+			}
+
+			Message::OpenArchived(archive_idx, entry_idx) => 'blk: {
+				let archive = &self.archives[archive_idx];
+				let entry = &archive.entries[entry_idx];
+				// Not a real file, but names the tab and where it came from.
+				let path = archive.path.join(entry.path.replace('\\', "/"));
+
+				if let Some(i) = self.tabs.iter().position(|t| t.path == path) {
+					self.active = i;
+				} else {
+					match archive.read_pex(entry) {
+						Ok(pex) => {
+							self.tabs.push(Tab::new(path, pex, &self.source_dirs));
+							self.active = self.tabs.len() - 1;
+							self.active_object = 0;
+						}
+						Err(e) => {
+							self.status = Some(e.to_string());
+							break 'blk;
+						}
+					}
+				}
+
+				self.active_section = 0;
+			}
+
+			Message::CloseVariableRefs => {
+				self.variable_refs = None;
+			}
+
+			Message::HandlerEditor(prop_idx, kind, action) => {
+				if let Some(editor) =
+					&mut self.tabs[self.active].handler_editors[self.active_object][prop_idx][kind]
+				{
+					editor.content.perform(action);
+				}
+			}
+
+			Message::ToggleHandler(prop_idx, kind) => {
+				if let Some(editor) =
+					&mut self.tabs[self.active].handler_editors[self.active_object][prop_idx][kind]
+				{
+					editor.visible ^= true;
+				}
+			}
+
+			Message::Merge => 'blk: {
+				let pick = |title: &str| {
+					rfd::FileDialog::new()
+						.set_title(title)
+						.add_filter("PEX Files", &["pex"])
+						.pick_file()
+				};
+
+				let Some(base) = pick("Select the original script") else {
+					break 'blk;
+				};
+				let Some(ours) = pick("Select our edit of the script") else {
+					break 'blk;
+				};
+				let Some(theirs) = pick("Select their edit of the script") else {
+					break 'blk;
+				};
+
+				let (base_pex, ours_pex, theirs_pex) =
+					match (read_pex(&base), read_pex(&ours), read_pex(&theirs)) {
+						(Ok(b), Ok(o), Ok(t)) => (b, o, t),
+						(Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+							self.status = Some(e);
+							break 'blk;
+						}
+					};
+
+				let merge = merge::merge(&base_pex, &ours_pex, &theirs_pex);
+				self.status = Some(format!(
+					"Merged with {} conflicting functions",
+					merge.conflicts.len()
+				));
+
+				let mut tab = Tab::new(ours, merge.pex, &self.source_dirs);
+				tab.conflicts = merge.conflicts;
+				tab.overlapping = merge.overlapping;
+				tab.revived = merge.revived;
+
+				self.tabs.push(tab);
+				self.active = self.tabs.len() - 1;
+				self.active_object = 0;
+				self.active_section = 3;
+			}
+
+			Message::Resolve(i, side) => {
+				let tab = &mut self.tabs[self.active];
+				let path = tab.conflicts[i].path();
+				let kept = tab.editor_texts();
+				tab.conflicts[i].resolve(&mut tab.pex, side);
+				tab.rebuild_editors();
+				tab.restore_editor_texts(kept, &path);
+				self.drop_search_results(self.active);
+			}
+
+			Message::SearchInput(query) => {
+				self.search_query = query;
+			}
+
+			Message::Search => {
+				self.search_results = self
+					.tabs
+					.iter()
+					.enumerate()
+					.flat_map(|(i, tab)| {
+						search::search(&tab.pex, &self.search_query)
+							.into_iter()
+							.map(move |hit| (i, hit))
+					})
+					.collect();
+			}
+
+			Message::OpenHit(tab_idx, location) => 'blk: {
+				let Some(tab) = self.tabs.get_mut(tab_idx) else {
+					break 'blk;
+				};
+				if location.describe(&tab.pex).is_none() {
+					self.status = Some("That result is out of date, search again".into());
+					break 'blk;
+				}
+
+				self.active = tab_idx;
+				let editor = match location {
+					search::Location::String(_) => {
+						self.active_section = 1;
+						break 'blk;
+					}
+					search::Location::Function {
+						object,
+						state,
+						function,
+					}
+					| search::Location::Instruction {
+						object,
+						state,
+						function,
+						..
+					} => {
+						self.active_object = object;
+						tab.editor_mut(EditorSlot::Function(object, state, function))
+					}
+					search::Location::Handler {
+						object,
+						property,
+						handler,
+					}
+					| search::Location::HandlerInstruction {
+						object,
+						property,
+						handler,
+						..
+					} => {
+						self.active_object = object;
+						tab.editor_mut(EditorSlot::Handler(object, property, handler))
+					}
+				};
+
+				self.active_section = 0;
+				if let Some(editor) = editor {
+					editor.visible = true;
+				}
+			}
+
+			Message::Save => 'blk: {
+				let tab = &mut self.tabs[self.active];
+				if let Err(e) = tab.apply_editors() {
+					self.status = Some(e);
+					break 'blk;
+				}
+
+				let Some(path) = rfd::FileDialog::new()
+					.set_title("Save file")
+					.add_filter("PEX Files", &["pex"])
+					.set_file_name(tab.path.file_name().unwrap().to_string_lossy())
+					.save_file()
+				else {
+					break 'blk;
+				};
+
+				let result = pex::assemble(&tab.pex)
+					.map_err(|e| e.to_string())
+					.and_then(|bytes| std::fs::write(&path, bytes).map_err(|e| e.to_string()));
+
+				match result {
+					Ok(()) => {
+						self.status = Some(format!("Saved {}", path.display()));
+						tab.path = path;
+					}
+					Err(e) => self.status = Some(format!("Failed to save {}: {e}", path.display())),
+				}
+			}
 		};
 
 		Task::none()
@@ -443,9 +2098,15 @@ impl App {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+	if args.first().is_some_and(|a| cli::is_command(a)) {
+		std::process::exit(cli::run(&args));
+	}
+
+	// Anything else is files to open, like when launched through a file association.
 	iced::application("pexSpy", App::update, App::view)
 		.window_size((768.0, 512.0))
-		.run()?;
+		.run_with(move || (App::with_files(&args), Task::none()))?;
 
 	Ok(())
 }
@@ -0,0 +1,943 @@
+use crate::{
+	disasm,
+	pex::{
+		DebugFunction, FN_TYPE_GETTER, FN_TYPE_METHOD, FN_TYPE_SETTER, Function, ObjectData, Pex,
+		Property, State, UserFlags, function_path,
+	},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+	Ours,
+	Theirs,
+}
+
+/// A function changed differently on both sides.
+/// Both versions are stored against the merged string table, so either can be put back in.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+	pub object: String,
+	pub state: String,
+	pub function: String,
+	pub ours: Option<Function>,
+	pub theirs: Option<Function>,
+	pub ours_debug: Option<DebugFunction>,
+	pub theirs_debug: Option<DebugFunction>,
+	pub resolution: Side,
+}
+
+impl Conflict {
+	/// Same path format as [`crate::diff`].
+	pub fn path(&self) -> String {
+		function_path(&self.object, &self.state, &self.function)
+	}
+
+	pub fn resolve(&mut self, pex: &mut Pex, side: Side) {
+		let (function, debug) = match side {
+			Side::Ours => (&self.ours, &self.ours_debug),
+			Side::Theirs => (&self.theirs, &self.theirs_debug),
+		};
+
+		put_function(
+			pex,
+			&self.object,
+			&self.state,
+			&self.function,
+			function.clone(),
+		);
+		put_debug(
+			pex,
+			&self.object,
+			Some(&self.state),
+			Some(&self.function),
+			&[FN_TYPE_METHOD],
+			debug.iter().cloned().collect(),
+		);
+
+		self.resolution = side;
+	}
+}
+
+#[derive(Debug)]
+pub struct Merge {
+	/// Merged script. Conflicting functions start out resolved to our side.
+	pub pex: Pex,
+	pub conflicts: Vec<Conflict>,
+	/// Variables, properties and user flags changed differently on both sides. Our version is kept for these.
+	pub overlapping: Vec<String>,
+	/// Objects deleted on one side and changed on the other. The changed version is kept in full.
+	pub revived: Vec<String>,
+}
+
+/// Three-way merges `ours` and `theirs` against their common ancestor `base`,
+/// by object, state and function name. The merged string table extends ours.
+pub fn merge(base: &Pex, ours: &Pex, theirs: &Pex) -> Merge {
+	let mut merger = Merger {
+		base,
+		ours,
+		theirs,
+		merged: ours.clone(),
+		flag_bits: vec![],
+		conflicts: vec![],
+		overlapping: vec![],
+		revived: vec![],
+	};
+
+	for &(name_idx, bit) in &theirs.userflags {
+		let name = theirs.string(name_idx);
+		match merger.user_flag_bit(name) {
+			Some(merged_bit) => merger.flag_bits.push((bit, merged_bit)),
+			// Their uses of it are dropped, as if we never had it.
+			None => merger.overlapping.push(format!("user flag {name}")),
+		}
+	}
+
+	let objects = names([base, ours, theirs].map(|pex| {
+		pex.objects
+			.iter()
+			.map(|(name_idx, _)| pex.string(*name_idx))
+			.collect::<Vec<_>>()
+	}));
+
+	for name in objects {
+		merger.object(&name);
+	}
+
+	Merge {
+		pex: merger.merged,
+		conflicts: merger.conflicts,
+		overlapping: merger.overlapping,
+		revived: merger.revived,
+	}
+}
+
+#[derive(Debug, PartialEq)]
+enum Pick {
+	Ours,
+	Theirs,
+	Conflict,
+}
+
+/// Compares fingerprints of the same item on each side. `None` means the item doesn't exist there.
+fn pick(base: Option<String>, ours: Option<String>, theirs: Option<String>) -> Pick {
+	if ours == theirs || theirs == base {
+		Pick::Ours
+	} else if ours == base {
+		Pick::Theirs
+	} else {
+		Pick::Conflict
+	}
+}
+
+/// Case insensitive union of names, in order of first appearance.
+fn names(sides: [Vec<&str>; 3]) -> Vec<String> {
+	let mut out: Vec<String> = vec![];
+	for name in sides.into_iter().flatten() {
+		if !out.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+			out.push(name.to_owned());
+		}
+	}
+	out
+}
+
+fn find_object<'p>(pex: &'p Pex, name: &str) -> Option<&'p ObjectData> {
+	pex.objects
+		.iter()
+		.find(|(name_idx, _)| pex.string(*name_idx).eq_ignore_ascii_case(name))
+		.map(|(_, o)| o)
+}
+
+fn find_state<'p>(pex: &'p Pex, object: Option<&'p ObjectData>, name: &str) -> Option<&'p State> {
+	object?
+		.states
+		.iter()
+		.find(|s| pex.string(s.name_idx).eq_ignore_ascii_case(name))
+}
+
+fn find_function<'p>(pex: &'p Pex, state: Option<&'p State>, name: &str) -> Option<&'p Function> {
+	state?
+		.functions
+		.iter()
+		.find(|(name_idx, _)| pex.string(*name_idx).eq_ignore_ascii_case(name))
+		.map(|(_, f)| f)
+}
+
+type Variable = (u16, u16, UserFlags, crate::pex::VariableData);
+
+fn find_variable<'p>(
+	pex: &'p Pex,
+	object: Option<&'p ObjectData>,
+	name: &str,
+) -> Option<&'p Variable> {
+	object?
+		.variables
+		.iter()
+		.find(|v| pex.string(v.0).eq_ignore_ascii_case(name))
+}
+
+fn find_property<'p>(
+	pex: &'p Pex,
+	object: Option<&'p ObjectData>,
+	name: &str,
+) -> Option<&'p Property> {
+	object?
+		.properties
+		.iter()
+		.find(|p| pex.string(p.name_idx).eq_ignore_ascii_case(name))
+}
+
+fn function_print(pex: &Pex, function: &Function) -> String {
+	let mut out = format!(
+		"{} {} {:?} {:?}\n",
+		disasm::signature(pex, "", function),
+		function.flags,
+		pex.user_flag_names(function.user_flags),
+		pex.string(function.doc_string_idx),
+	);
+
+	for local in &function.locals {
+		out += &format!(
+			"{} {}\n",
+			pex.string(local.type_idx),
+			pex.string(local.name_idx)
+		);
+	}
+
+	for instruction in &function.instructions {
+		out += &disasm::instruction(pex, instruction);
+		out.push('\n');
+	}
+
+	out
+}
+
+fn variable_print(pex: &Pex, variable: &Variable) -> String {
+	format!(
+		"{} {:?} {}",
+		pex.string(variable.1),
+		pex.user_flag_names(variable.2),
+		disasm::variable_data(pex, &variable.3)
+	)
+}
+
+fn property_print(pex: &Pex, property: &Property) -> String {
+	let handler = |f: &Option<Function>| {
+		f.as_ref()
+			.map(|f| function_print(pex, f))
+			.unwrap_or_default()
+	};
+
+	format!(
+		"{} {:?} {} {:?} {:?}\n{}\n{}",
+		pex.string(property.type_idx),
+		pex.string(property.doc_string_idx),
+		property.flags,
+		pex.user_flag_names(property.user_flags),
+		property.auto_var_name.map(|i| pex.string(i)),
+		handler(&property.read_handler),
+		handler(&property.write_handler),
+	)
+}
+
+fn state_print(pex: &Pex, state: &State) -> String {
+	state
+		.functions
+		.iter()
+		.map(|(name_idx, f)| format!("{}\n{}", pex.string(*name_idx), function_print(pex, f)))
+		.collect()
+}
+
+fn object_print(pex: &Pex, object: &ObjectData) -> String {
+	let mut out = format!(
+		"{} {:?} {:?} {}\n",
+		pex.string(object.parent_name_idx),
+		pex.string(object.doc_string_idx),
+		pex.user_flag_names(object.user_flags),
+		pex.string(object.auto_state_name_idx),
+	);
+
+	for variable in &object.variables {
+		out += &format!(
+			"{} {}\n",
+			pex.string(variable.0),
+			variable_print(pex, variable)
+		);
+	}
+
+	for property in &object.properties {
+		out += &format!(
+			"{} {}\n",
+			pex.string(property.name_idx),
+			property_print(pex, property)
+		);
+	}
+
+	for state in &object.states {
+		out += &format!(
+			"{}\n{}",
+			pex.string(state.name_idx),
+			state_print(pex, state)
+		);
+	}
+
+	out
+}
+
+fn object_idx(pex: &Pex, name: &str) -> Option<usize> {
+	pex.objects
+		.iter()
+		.position(|(name_idx, _)| pex.string(*name_idx).eq_ignore_ascii_case(name))
+}
+
+/// Replaces, inserts or removes (`None`) a function of the merged script, creating its state if needed.
+fn put_function(pex: &mut Pex, object: &str, state: &str, name: &str, function: Option<Function>) {
+	let Some(obj_idx) = object_idx(pex, object) else {
+		return;
+	};
+
+	let state_idx = {
+		let states = &pex.objects[obj_idx].1.states;
+		match states
+			.iter()
+			.position(|s| pex.string(s.name_idx).eq_ignore_ascii_case(state))
+		{
+			Some(idx) => idx,
+			None if function.is_none() => return,
+			None => {
+				let name_idx = pex.intern(state);
+				let states = &mut pex.objects[obj_idx].1.states;
+				states.push(State {
+					name_idx,
+					functions: vec![],
+				});
+				states.len() - 1
+			}
+		}
+	};
+
+	let func_idx = pex.objects[obj_idx].1.states[state_idx]
+		.functions
+		.iter()
+		.position(|(name_idx, _)| pex.string(*name_idx).eq_ignore_ascii_case(name));
+
+	match (func_idx, function) {
+		(Some(idx), Some(function)) => {
+			pex.objects[obj_idx].1.states[state_idx].functions[idx].1 = function;
+		}
+		(Some(idx), None) => {
+			pex.objects[obj_idx].1.states[state_idx]
+				.functions
+				.remove(idx);
+		}
+		(None, Some(function)) => {
+			let name_idx = pex.intern(name);
+			pex.objects[obj_idx].1.states[state_idx]
+				.functions
+				.push((name_idx, function));
+		}
+		(None, None) => {}
+	}
+}
+
+/// Replaces the debug info entries matching an object, optional state and function name, and function types.
+fn put_debug(
+	pex: &mut Pex,
+	object: &str,
+	state: Option<&str>,
+	name: Option<&str>,
+	fn_types: &[u8],
+	entries: Vec<DebugFunction>,
+) {
+	let Some(mut debug) = pex.debuginfo.take() else {
+		return;
+	};
+
+	debug.functions.retain(|f| {
+		!(pex.string(f.obj_name_idx).eq_ignore_ascii_case(object)
+			&& state.is_none_or(|s| pex.string(f.state_name_idx).eq_ignore_ascii_case(s))
+			&& name.is_none_or(|n| pex.string(f.fn_name_idx).eq_ignore_ascii_case(n))
+			&& fn_types.contains(&f.fn_type))
+	});
+	debug.functions.extend(entries);
+
+	pex.debuginfo = Some(debug);
+}
+
+fn find_debug<'p>(
+	pex: &'p Pex,
+	object: &str,
+	state: Option<&str>,
+	name: Option<&str>,
+	fn_types: &[u8],
+) -> Vec<&'p DebugFunction> {
+	pex.debuginfo
+		.iter()
+		.flat_map(|d| &d.functions)
+		.filter(|f| {
+			pex.string(f.obj_name_idx).eq_ignore_ascii_case(object)
+				&& state.is_none_or(|s| pex.string(f.state_name_idx).eq_ignore_ascii_case(s))
+				&& name.is_none_or(|n| pex.string(f.fn_name_idx).eq_ignore_ascii_case(n))
+				&& fn_types.contains(&f.fn_type)
+		})
+		.collect()
+}
+
+struct Merger<'a> {
+	base: &'a Pex,
+	ours: &'a Pex,
+	theirs: &'a Pex,
+	merged: Pex,
+	/// Their user flag bits -> merged user flag bits
+	flag_bits: Vec<(u8, u8)>,
+	conflicts: Vec<Conflict>,
+	overlapping: Vec<String>,
+	revived: Vec<String>,
+}
+
+impl Merger<'_> {
+	/// The merged bit of a user flag, adding it if there's a bit free. `None` if all 32 are taken.
+	fn user_flag_bit(&mut self, name: &str) -> Option<u8> {
+		if let Some((_, bit)) = self
+			.merged
+			.userflags
+			.iter()
+			.find(|(name_idx, _)| self.merged.string(*name_idx).eq_ignore_ascii_case(name))
+		{
+			return Some(*bit);
+		}
+
+		let bit = (0..32).find(|b| !self.merged.userflags.iter().any(|(_, bit)| bit == b))?;
+		let name_idx = self.merged.intern(name);
+		self.merged.userflags.push((name_idx, bit));
+		Some(bit)
+	}
+
+	fn import_user_flags(&self, flags: UserFlags) -> UserFlags {
+		self.flag_bits
+			.iter()
+			.filter(|(from, _)| flags.contains(*from))
+			.fold(UserFlags::default(), |mut acc, (_, to)| {
+				acc.set(*to, true);
+				acc
+			})
+	}
+
+	fn import_string(&mut self, idx: u16) -> u16 {
+		self.merged.intern(self.theirs.string(idx))
+	}
+
+	fn import_function(&mut self, function: &Function) -> Function {
+		let (theirs, merged) = (self.theirs, &mut self.merged);
+		let mut function = function.clone();
+		function.for_each_string_mut(&mut |idx| *idx = merged.intern(theirs.string(*idx)));
+		function.user_flags = self.import_user_flags(function.user_flags);
+		function
+	}
+
+	fn import_property(&mut self, property: &Property) -> Property {
+		let (theirs, merged) = (self.theirs, &mut self.merged);
+		let mut property = property.clone();
+		property.for_each_string_mut(&mut |idx| *idx = merged.intern(theirs.string(*idx)));
+		property.user_flags = self.import_user_flags(property.user_flags);
+
+		for handler in [&mut property.read_handler, &mut property.write_handler]
+			.into_iter()
+			.flatten()
+		{
+			handler.user_flags = self.import_user_flags(handler.user_flags);
+		}
+
+		property
+	}
+
+	fn import_state(&mut self, state: &State) -> State {
+		let (theirs, merged) = (self.theirs, &mut self.merged);
+		let mut state = state.clone();
+		state.for_each_string_mut(&mut |idx| *idx = merged.intern(theirs.string(*idx)));
+
+		for (_, function) in &mut state.functions {
+			function.user_flags = self.import_user_flags(function.user_flags);
+		}
+
+		state
+	}
+
+	fn import_variable(&mut self, variable: &Variable) -> Variable {
+		let (theirs, merged) = (self.theirs, &mut self.merged);
+		let (name_idx, type_idx, user_flags, mut data) = variable.clone();
+		data.for_each_string_mut(&mut |idx| *idx = merged.intern(theirs.string(*idx)));
+
+		(
+			self.import_string(name_idx),
+			self.import_string(type_idx),
+			self.import_user_flags(user_flags),
+			data,
+		)
+	}
+
+	fn import_object(&mut self, object: &ObjectData) -> ObjectData {
+		ObjectData {
+			parent_name_idx: self.import_string(object.parent_name_idx),
+			doc_string_idx: self.import_string(object.doc_string_idx),
+			user_flags: self.import_user_flags(object.user_flags),
+			auto_state_name_idx: self.import_string(object.auto_state_name_idx),
+			variables: object
+				.variables
+				.iter()
+				.map(|v| self.import_variable(v))
+				.collect(),
+			properties: object
+				.properties
+				.iter()
+				.map(|p| self.import_property(p))
+				.collect(),
+			states: object.states.iter().map(|s| self.import_state(s)).collect(),
+		}
+	}
+
+	fn import_debug(&mut self, entries: Vec<&DebugFunction>) -> Vec<DebugFunction> {
+		let (theirs, merged) = (self.theirs, &mut self.merged);
+		entries
+			.into_iter()
+			.map(|entry| {
+				let mut entry = entry.clone();
+				entry.for_each_string_mut(&mut |idx| *idx = merged.intern(theirs.string(*idx)));
+				entry
+			})
+			.collect()
+	}
+
+	fn object(&mut self, name: &str) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let (b, o, t) = (
+			find_object(base, name),
+			find_object(ours, name),
+			find_object(theirs, name),
+		);
+
+		match pick(
+			b.map(|x| object_print(base, x)),
+			o.map(|x| object_print(ours, x)),
+			t.map(|x| object_print(theirs, x)),
+		) {
+			Pick::Ours => {}
+			Pick::Theirs => self.take_object(name, t),
+			Pick::Conflict => {
+				// Merging the parts of a deleted object would only keep the parts the other side changed.
+				let (Some(o), Some(t)) = (o, t) else {
+					self.revived.push(name.to_owned());
+					if o.is_none() {
+						self.take_object(name, t);
+					}
+					return;
+				};
+				self.object_header(name, b, o, t);
+
+				let variables = names([(base, b), (ours, Some(o)), (theirs, Some(t))].map(
+					|(pex, obj)| {
+						obj.iter()
+							.flat_map(|o| &o.variables)
+							.map(|v| pex.string(v.0))
+							.collect()
+					},
+				));
+				for variable in variables {
+					self.variable(name, &variable);
+				}
+
+				let properties = names([(base, b), (ours, Some(o)), (theirs, Some(t))].map(
+					|(pex, obj)| {
+						obj.iter()
+							.flat_map(|o| &o.properties)
+							.map(|p| pex.string(p.name_idx))
+							.collect()
+					},
+				));
+				for property in properties {
+					self.property(name, &property);
+				}
+
+				let states = names([(base, b), (ours, Some(o)), (theirs, Some(t))].map(
+					|(pex, obj)| {
+						obj.iter()
+							.flat_map(|o| &o.states)
+							.map(|s| pex.string(s.name_idx))
+							.collect()
+					},
+				));
+				for state in states {
+					self.state(name, &state);
+				}
+			}
+		}
+	}
+
+	/// Replaces, inserts or removes (`None`) an object along with its debug info, as it is on their side.
+	fn take_object(&mut self, name: &str, t: Option<&ObjectData>) {
+		let object = t.map(|t| self.import_object(t));
+		let idx = object_idx(&self.merged, name);
+		match (idx, object) {
+			(Some(idx), Some(object)) => self.merged.objects[idx].1 = object,
+			(Some(idx), None) => {
+				self.merged.objects.remove(idx);
+			}
+			(None, Some(object)) => {
+				let name_idx = self.merged.intern(name);
+				self.merged.objects.push((name_idx, object));
+			}
+			(None, None) => {}
+		}
+
+		let debug = self.import_debug(find_debug(
+			self.theirs,
+			name,
+			None,
+			None,
+			&[FN_TYPE_METHOD, FN_TYPE_GETTER, FN_TYPE_SETTER],
+		));
+		put_debug(
+			&mut self.merged,
+			name,
+			None,
+			None,
+			&[FN_TYPE_METHOD, FN_TYPE_GETTER, FN_TYPE_SETTER],
+			debug,
+		);
+	}
+
+	/// Takes their parent, auto state, doc string and user flags where only they changed them.
+	fn object_header(
+		&mut self,
+		name: &str,
+		b: Option<&ObjectData>,
+		o: &ObjectData,
+		t: &ObjectData,
+	) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let Some(idx) = object_idx(&self.merged, name) else {
+			return;
+		};
+
+		let take = |field: fn(&ObjectData) -> u16| {
+			pick(
+				b.map(|b| base.string(field(b)).to_owned()),
+				Some(ours.string(field(o)).to_owned()),
+				Some(theirs.string(field(t)).to_owned()),
+			) == Pick::Theirs
+		};
+
+		let parent = take(|o| o.parent_name_idx).then(|| self.import_string(t.parent_name_idx));
+		let auto_state =
+			take(|o| o.auto_state_name_idx).then(|| self.import_string(t.auto_state_name_idx));
+		let doc_string = take(|o| o.doc_string_idx).then(|| self.import_string(t.doc_string_idx));
+
+		let object = &mut self.merged.objects[idx].1;
+		object.parent_name_idx = parent.unwrap_or(object.parent_name_idx);
+		object.auto_state_name_idx = auto_state.unwrap_or(object.auto_state_name_idx);
+		object.doc_string_idx = doc_string.unwrap_or(object.doc_string_idx);
+
+		let take = pick(
+			b.map(|b| format!("{:?}", base.user_flag_names(b.user_flags))),
+			Some(format!("{:?}", ours.user_flag_names(o.user_flags))),
+			Some(format!("{:?}", theirs.user_flag_names(t.user_flags))),
+		) == Pick::Theirs;
+
+		if take {
+			self.merged.objects[idx].1.user_flags = self.import_user_flags(t.user_flags);
+		}
+	}
+
+	fn variable(&mut self, object: &str, name: &str) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let (b, o, t) = (
+			find_variable(base, find_object(base, object), name),
+			find_variable(ours, find_object(ours, object), name),
+			find_variable(theirs, find_object(theirs, object), name),
+		);
+
+		match pick(
+			b.map(|x| variable_print(base, x)),
+			o.map(|x| variable_print(ours, x)),
+			t.map(|x| variable_print(theirs, x)),
+		) {
+			Pick::Ours => {}
+			Pick::Conflict => self.overlapping.push(format!("{object}.{name}")),
+			Pick::Theirs => {
+				let variable = t.map(|t| self.import_variable(t));
+				let Some(idx) = object_idx(&self.merged, object) else {
+					return;
+				};
+
+				let merged = &self.merged;
+				let existing = merged.objects[idx]
+					.1
+					.variables
+					.iter()
+					.position(|v| merged.string(v.0).eq_ignore_ascii_case(name));
+
+				let variables = &mut self.merged.objects[idx].1.variables;
+				match (existing, variable) {
+					(Some(i), Some(variable)) => variables[i] = variable,
+					(Some(i), None) => {
+						variables.remove(i);
+					}
+					(None, Some(variable)) => variables.push(variable),
+					(None, None) => {}
+				}
+			}
+		}
+	}
+
+	fn property(&mut self, object: &str, name: &str) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let (b, o, t) = (
+			find_property(base, find_object(base, object), name),
+			find_property(ours, find_object(ours, object), name),
+			find_property(theirs, find_object(theirs, object), name),
+		);
+
+		match pick(
+			b.map(|x| property_print(base, x)),
+			o.map(|x| property_print(ours, x)),
+			t.map(|x| property_print(theirs, x)),
+		) {
+			Pick::Ours => {}
+			Pick::Conflict => self.overlapping.push(format!("{object}.{name}")),
+			Pick::Theirs => {
+				let property = t.map(|t| self.import_property(t));
+				let Some(idx) = object_idx(&self.merged, object) else {
+					return;
+				};
+
+				let merged = &self.merged;
+				let existing = merged.objects[idx]
+					.1
+					.properties
+					.iter()
+					.position(|p| merged.string(p.name_idx).eq_ignore_ascii_case(name));
+
+				let properties = &mut self.merged.objects[idx].1.properties;
+				match (existing, property) {
+					(Some(i), Some(property)) => properties[i] = property,
+					(Some(i), None) => {
+						properties.remove(i);
+					}
+					(None, Some(property)) => properties.push(property),
+					(None, None) => {}
+				}
+
+				let types = [FN_TYPE_GETTER, FN_TYPE_SETTER];
+				let debug = self.import_debug(find_debug(theirs, object, None, Some(name), &types));
+				put_debug(&mut self.merged, object, None, Some(name), &types, debug);
+			}
+		}
+	}
+
+	fn state(&mut self, object: &str, name: &str) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let (b, o, t) = (
+			find_state(base, find_object(base, object), name),
+			find_state(ours, find_object(ours, object), name),
+			find_state(theirs, find_object(theirs, object), name),
+		);
+
+		match pick(
+			b.map(|x| state_print(base, x)),
+			o.map(|x| state_print(ours, x)),
+			t.map(|x| state_print(theirs, x)),
+		) {
+			Pick::Ours => {}
+			Pick::Theirs => {
+				let state = t.map(|t| self.import_state(t));
+				let Some(idx) = object_idx(&self.merged, object) else {
+					return;
+				};
+
+				let merged = &self.merged;
+				let existing = merged.objects[idx]
+					.1
+					.states
+					.iter()
+					.position(|s| merged.string(s.name_idx).eq_ignore_ascii_case(name));
+
+				let states = &mut self.merged.objects[idx].1.states;
+				match (existing, state) {
+					(Some(i), Some(state)) => states[i] = state,
+					(Some(i), None) => {
+						states.remove(i);
+					}
+					(None, Some(state)) => states.push(state),
+					(None, None) => {}
+				}
+
+				let types = [FN_TYPE_METHOD];
+				let debug = self.import_debug(find_debug(theirs, object, Some(name), None, &types));
+				put_debug(&mut self.merged, object, Some(name), None, &types, debug);
+			}
+			Pick::Conflict => {
+				let functions = names([(base, b), (ours, o), (theirs, t)].map(|(pex, state)| {
+					state
+						.iter()
+						.flat_map(|s| &s.functions)
+						.map(|(name_idx, _)| pex.string(*name_idx))
+						.collect()
+				}));
+
+				for function in functions {
+					self.function(object, name, &function, (b, o, t));
+				}
+			}
+		}
+	}
+
+	fn function(
+		&mut self,
+		object: &str,
+		state: &str,
+		name: &str,
+		(b, o, t): (Option<&State>, Option<&State>, Option<&State>),
+	) {
+		let (base, ours, theirs) = (self.base, self.ours, self.theirs);
+		let (b, o, t) = (
+			find_function(base, b, name),
+			find_function(ours, o, name),
+			find_function(theirs, t, name),
+		);
+
+		let pick = pick(
+			b.map(|x| function_print(base, x)),
+			o.map(|x| function_print(ours, x)),
+			t.map(|x| function_print(theirs, x)),
+		);
+
+		if pick == Pick::Ours {
+			return;
+		}
+
+		let types = [FN_TYPE_METHOD];
+		let function = t.map(|t| self.import_function(t));
+		let debug = self.import_debug(find_debug(theirs, object, Some(state), Some(name), &types));
+
+		if pick == Pick::Theirs {
+			put_function(&mut self.merged, object, state, name, function);
+			put_debug(
+				&mut self.merged,
+				object,
+				Some(state),
+				Some(name),
+				&types,
+				debug,
+			);
+			return;
+		}
+
+		// Merged string table extends ours, so our indices are still valid.
+		self.conflicts.push(Conflict {
+			object: object.to_owned(),
+			state: state.to_owned(),
+			function: name.to_owned(),
+			ours: o.cloned(),
+			theirs: function,
+			ours_debug: find_debug(ours, object, Some(state), Some(name), &types)
+				.into_iter()
+				.next()
+				.cloned(),
+			theirs_debug: debug.into_iter().next(),
+			resolution: Side::Ours,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{
+			FunctionBuilder,
+			fixture::{self, listing},
+		},
+		model::VariableData::Int,
+	};
+
+	fn script(count: i32, other: i32) -> Pex {
+		let returns = |value| {
+			FunctionBuilder::new()
+				.returns("Int")
+				.op("RETURN", vec![Int(value)])
+		};
+		fixture::script([("Count", returns(count)), ("Other", returns(other))])
+	}
+
+	#[test]
+	fn takes_changes_made_on_one_side() {
+		let merge = merge(&script(1, 1), &script(1, 2), &script(3, 1));
+
+		assert!(merge.conflicts.is_empty());
+		assert_eq!(listing(&merge.pex, "Count"), ["RETURN 3"]);
+		assert_eq!(listing(&merge.pex, "Other"), ["RETURN 2"]);
+	}
+
+	#[test]
+	fn conflicts_on_changes_made_on_both_sides() {
+		let merge = merge(&script(1, 1), &script(2, 1), &script(3, 1));
+
+		assert_eq!(merge.conflicts.len(), 1);
+		assert_eq!(merge.conflicts[0].path(), "TestScript.Count");
+		assert_eq!(listing(&merge.pex, "Count"), ["RETURN 2"]);
+	}
+
+	#[test]
+	fn keeps_whole_object_deleted_on_our_side_and_changed_on_theirs() {
+		let mut ours = script(1, 1);
+		ours.objects.clear();
+		let merge = merge(&script(1, 1), &ours, &script(3, 1));
+
+		assert_eq!(merge.revived, ["TestScript"]);
+		assert_eq!(listing(&merge.pex, "Count"), ["RETURN 3"]);
+		assert_eq!(listing(&merge.pex, "Other"), ["RETURN 1"]);
+	}
+
+	#[test]
+	fn keeps_whole_object_changed_on_our_side_and_deleted_on_theirs() {
+		let mut theirs = script(1, 1);
+		theirs.objects.clear();
+		let merge = merge(&script(1, 1), &script(2, 1), &theirs);
+
+		assert_eq!(merge.revived, ["TestScript"]);
+		assert_eq!(listing(&merge.pex, "Count"), ["RETURN 2"]);
+		assert_eq!(listing(&merge.pex, "Other"), ["RETURN 1"]);
+	}
+
+	#[test]
+	fn reports_user_flags_that_dont_fit() {
+		let mut ours = script(1, 1);
+		ours.userflags = (0..32)
+			.map(|bit| (ours.intern(&format!("flag{bit}")), bit))
+			.collect();
+		let mut theirs = script(1, 1);
+		let name_idx = theirs.intern("extra");
+		theirs.userflags.push((name_idx, 5));
+
+		let merge = merge(&script(1, 1), &ours, &theirs);
+
+		assert_eq!(merge.pex.userflags.len(), 32);
+		assert!(merge.overlapping.contains(&"user flag extra".to_owned()));
+	}
+
+	#[test]
+	fn matches_user_flags_ignoring_case() {
+		let mut theirs = script(1, 1);
+		let name_idx = theirs.intern("HIDDEN");
+		let hidden = theirs
+			.userflags
+			.iter()
+			.position(|(_, bit)| *bit == 0)
+			.unwrap();
+		theirs.userflags[hidden].0 = name_idx;
+
+		let merge = merge(&script(1, 1), &script(1, 1), &theirs);
+
+		assert_eq!(merge.pex.userflags.len(), 2);
+		assert!(merge.overlapping.is_empty());
+	}
+}
@@ -0,0 +1,502 @@
+//! An owned model of a script where names and literals are strings rather than string table indices.
+//!
+//! It mirrors [`crate::pex`] type for type. Convert with [`Script::from`] and lower with [`Script::to_pex`],
+//! which builds a fresh string table, so scripts can be edited without ever touching an index.
+
+use crate::pex::{self, FunctionFlags, MNEMONICS, Pex, PexError, PexResult, PropertyFlags};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+	pub major: u8,
+	pub minor: u8,
+	pub gameid: u16,
+	pub comptime: u64,
+	pub src: String,
+	pub username: String,
+	pub machine: String,
+	pub debuginfo: Option<DebugInfo>,
+	/// User flag names and the bits they're stored in.
+	pub userflags: Vec<(String, u8)>,
+	pub objects: Vec<Object>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+	pub modtime: u64,
+	pub functions: Vec<DebugFunction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugFunction {
+	pub object: String,
+	pub state: String,
+	pub function: String,
+	/// One of [`pex::FN_TYPE_METHOD`], [`pex::FN_TYPE_GETTER`] or [`pex::FN_TYPE_SETTER`].
+	pub fn_type: u8,
+	/// Source line of each instruction.
+	pub lines: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+	pub name: String,
+	pub parent: String,
+	pub doc_string: String,
+	/// Names from [`Script::userflags`], or `bitN` for bits it doesn't name.
+	pub user_flags: Vec<String>,
+	pub auto_state: String,
+	pub variables: Vec<Variable>,
+	pub properties: Vec<Property>,
+	pub states: Vec<State>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+	pub name: String,
+	pub type_name: String,
+	pub user_flags: Vec<String>,
+	pub value: VariableData,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+	pub name: String,
+	pub type_name: String,
+	pub doc_string: String,
+	pub user_flags: Vec<String>,
+	pub flags: PropertyFlags,
+	pub auto_var_name: Option<String>,
+	pub read_handler: Option<Function>,
+	pub write_handler: Option<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+	pub name: String,
+	pub functions: Vec<(String, Function)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+	pub return_type: String,
+	pub doc_string: String,
+	pub user_flags: Vec<String>,
+	pub flags: FunctionFlags,
+	pub params: Vec<VariableType>,
+	pub locals: Vec<VariableType>,
+	pub instructions: Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableType {
+	pub name: String,
+	pub type_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableData {
+	Null,
+	Ident(String),
+	String(String),
+	Int(i32),
+	Float(f32),
+	Bool(bool),
+}
+
+/// An instruction as its opcode and operands, in the layout of [`pex::Instruction::operands`].
+/// Whether the operands fit the opcode is checked when lowering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+	pub opcode: u8,
+	pub operands: Vec<VariableData>,
+	/// Arguments of calls.
+	pub args: Vec<VariableData>,
+}
+
+impl Function {
+	/// Native functions are implemented by the game, and have no instructions.
+	pub fn is_native(&self) -> bool {
+		self.flags.contains(FunctionFlags::NATIVE)
+	}
+}
+
+impl Instruction {
+	/// Looks up the opcode by mnemonic, e.g. `"CALLMETHOD"`. Returns `None` for unknown ones.
+	pub fn new(
+		mnemonic: &str,
+		operands: Vec<VariableData>,
+		args: Vec<VariableData>,
+	) -> Option<Self> {
+		let opcode = MNEMONICS
+			.iter()
+			.position(|m| m.eq_ignore_ascii_case(mnemonic))?;
+
+		Some(Self {
+			opcode: opcode as u8,
+			operands,
+			args,
+		})
+	}
+
+	pub fn mnemonic(&self) -> &'static str {
+		MNEMONICS
+			.get(self.opcode as usize)
+			.copied()
+			.unwrap_or("???")
+	}
+}
+
+impl From<&Pex> for Script {
+	fn from(pex: &Pex) -> Self {
+		let s = |idx: u16| pex.string(idx).to_owned();
+
+		let data = |data: &pex::VariableData| match data {
+			pex::VariableData::Null => VariableData::Null,
+			pex::VariableData::Ident(i) => VariableData::Ident(s(*i)),
+			pex::VariableData::String(i) => VariableData::String(s(*i)),
+			pex::VariableData::Int(i) => VariableData::Int(*i),
+			pex::VariableData::Float(f) => VariableData::Float(*f),
+			pex::VariableData::Bool(b) => VariableData::Bool(*b),
+		};
+
+		let variable_types = |vars: &[pex::VariableType]| {
+			vars.iter()
+				.map(|v| VariableType {
+					name: s(v.name_idx),
+					type_name: s(v.type_idx),
+				})
+				.collect()
+		};
+
+		let function = |f: &pex::Function| Function {
+			return_type: s(f.return_type_idx),
+			doc_string: s(f.doc_string_idx),
+			user_flags: pex.user_flag_names(f.user_flags),
+			flags: f.flags,
+			params: variable_types(&f.params),
+			locals: variable_types(&f.locals),
+			instructions: f
+				.instructions
+				.iter()
+				.map(|i| {
+					let (operands, args) = i.operands();
+					Instruction {
+						opcode: i.opcode(),
+						operands: operands.iter().map(data).collect(),
+						args: args.iter().map(data).collect(),
+					}
+				})
+				.collect(),
+		};
+
+		let object = |(name_idx, o): &(u16, pex::ObjectData)| Object {
+			name: s(*name_idx),
+			parent: s(o.parent_name_idx),
+			doc_string: s(o.doc_string_idx),
+			user_flags: pex.user_flag_names(o.user_flags),
+			auto_state: s(o.auto_state_name_idx),
+			variables: o
+				.variables
+				.iter()
+				.map(|(name_idx, type_idx, user_flags, value)| Variable {
+					name: s(*name_idx),
+					type_name: s(*type_idx),
+					user_flags: pex.user_flag_names(*user_flags),
+					value: data(value),
+				})
+				.collect(),
+			properties: o
+				.properties
+				.iter()
+				.map(|p| Property {
+					name: s(p.name_idx),
+					type_name: s(p.type_idx),
+					doc_string: s(p.doc_string_idx),
+					user_flags: pex.user_flag_names(p.user_flags),
+					flags: p.flags,
+					auto_var_name: p.auto_var_name.map(s),
+					read_handler: p.read_handler.as_ref().map(function),
+					write_handler: p.write_handler.as_ref().map(function),
+				})
+				.collect(),
+			states: o
+				.states
+				.iter()
+				.map(|st| State {
+					name: s(st.name_idx),
+					functions: st
+						.functions
+						.iter()
+						.map(|(name_idx, f)| (s(*name_idx), function(f)))
+						.collect(),
+				})
+				.collect(),
+		};
+
+		Script {
+			major: pex.major,
+			minor: pex.minor,
+			gameid: pex.gameid,
+			comptime: pex.comptime,
+			src: pex.src.clone(),
+			username: pex.username.clone(),
+			machine: pex.machine.clone(),
+			debuginfo: pex.debuginfo.as_ref().map(|d| DebugInfo {
+				modtime: d.modtime,
+				functions: d
+					.functions
+					.iter()
+					.map(|f| DebugFunction {
+						object: s(f.obj_name_idx),
+						state: s(f.state_name_idx),
+						function: s(f.fn_name_idx),
+						fn_type: f.fn_type,
+						lines: f.instructions.clone(),
+					})
+					.collect(),
+			}),
+			userflags: pex
+				.userflags
+				.iter()
+				.map(|(name_idx, bit)| (s(*name_idx), *bit))
+				.collect(),
+			objects: pex.objects.iter().map(object).collect(),
+		}
+	}
+}
+
+impl Script {
+	pub fn parse(bytes: &[u8]) -> PexResult<Self> {
+		Ok(Self::from(&pex::parse(bytes)?))
+	}
+
+	/// Lowers the script, interning every string into a new string table.
+	/// Fails if an instruction's operands don't fit its opcode.
+	pub fn to_pex(&self) -> PexResult<Pex> {
+		let mut pex = Pex {
+			major: self.major,
+			minor: self.minor,
+			gameid: self.gameid,
+			comptime: self.comptime,
+			src: self.src.clone(),
+			username: self.username.clone(),
+			machine: self.machine.clone(),
+			stringtable: vec![],
+			debuginfo: None,
+			userflags: vec![],
+			objects: vec![],
+		};
+
+		for object in &self.objects {
+			let name_idx = pex.intern(&object.name);
+			let data = lower_object(&mut pex, &self.userflags, object)?;
+			pex.objects.push((name_idx, data));
+		}
+
+		pex.userflags = self
+			.userflags
+			.iter()
+			.map(|(name, bit)| (pex.intern(name), *bit))
+			.collect();
+
+		pex.debuginfo = self.debuginfo.as_ref().map(|d| pex::DebugInfo {
+			modtime: d.modtime,
+			functions: d
+				.functions
+				.iter()
+				.map(|f| pex::DebugFunction {
+					obj_name_idx: pex.intern(&f.object),
+					state_name_idx: pex.intern(&f.state),
+					fn_name_idx: pex.intern(&f.function),
+					fn_type: f.fn_type,
+					instructions: f.lines.clone(),
+				})
+				.collect(),
+		});
+
+		Ok(pex)
+	}
+
+	/// Lowers and assembles the script.
+	pub fn assemble(&self) -> PexResult<Vec<u8>> {
+		pex::assemble(&self.to_pex()?)
+	}
+}
+
+fn lower_data(pex: &mut Pex, data: &VariableData) -> pex::VariableData {
+	match data {
+		VariableData::Null => pex::VariableData::Null,
+		VariableData::Ident(s) => pex::VariableData::Ident(pex.intern(s)),
+		VariableData::String(s) => pex::VariableData::String(pex.intern(s)),
+		VariableData::Int(i) => pex::VariableData::Int(*i),
+		VariableData::Float(f) => pex::VariableData::Float(*f),
+		VariableData::Bool(b) => pex::VariableData::Bool(*b),
+	}
+}
+
+fn lower_variable_types(pex: &mut Pex, vars: &[VariableType]) -> Vec<pex::VariableType> {
+	vars.iter()
+		.map(|v| pex::VariableType {
+			name_idx: pex.intern(&v.name),
+			type_idx: pex.intern(&v.type_name),
+		})
+		.collect()
+}
+
+/// Maps user flag names back to their bits through `table`.
+fn lower_user_flags(table: &[(String, u8)], names: &[String]) -> PexResult<pex::UserFlags> {
+	pex::UserFlags::from_names(names, table).map_err(PexError::Build)
+}
+
+fn lower_function(
+	pex: &mut Pex,
+	table: &[(String, u8)],
+	function: &Function,
+) -> PexResult<pex::Function> {
+	let instructions = function
+		.instructions
+		.iter()
+		.map(|i| {
+			let operands = i.operands.iter().map(|o| lower_data(pex, o)).collect();
+			let args = i.args.iter().map(|a| lower_data(pex, a)).collect();
+			pex::Instruction::from_operands(i.opcode, operands, args)
+		})
+		.collect::<PexResult<Vec<_>>>()?;
+
+	Ok(pex::Function {
+		return_type_idx: pex.intern(&function.return_type),
+		doc_string_idx: pex.intern(&function.doc_string),
+		user_flags: lower_user_flags(table, &function.user_flags)?,
+		flags: function.flags,
+		params: lower_variable_types(pex, &function.params),
+		locals: lower_variable_types(pex, &function.locals),
+		instructions,
+	})
+}
+
+fn lower_object(
+	pex: &mut Pex,
+	table: &[(String, u8)],
+	object: &Object,
+) -> PexResult<pex::ObjectData> {
+	let variables = object
+		.variables
+		.iter()
+		.map(|v| {
+			Ok((
+				pex.intern(&v.name),
+				pex.intern(&v.type_name),
+				lower_user_flags(table, &v.user_flags)?,
+				lower_data(pex, &v.value),
+			))
+		})
+		.collect::<PexResult<Vec<_>>>()?;
+
+	let properties = object
+		.properties
+		.iter()
+		.map(|p| {
+			Ok(pex::Property {
+				name_idx: pex.intern(&p.name),
+				type_idx: pex.intern(&p.type_name),
+				doc_string_idx: pex.intern(&p.doc_string),
+				user_flags: lower_user_flags(table, &p.user_flags)?,
+				flags: p.flags,
+				auto_var_name: p.auto_var_name.as_ref().map(|n| pex.intern(n)),
+				read_handler: p
+					.read_handler
+					.as_ref()
+					.map(|f| lower_function(pex, table, f))
+					.transpose()?,
+				write_handler: p
+					.write_handler
+					.as_ref()
+					.map(|f| lower_function(pex, table, f))
+					.transpose()?,
+			})
+		})
+		.collect::<PexResult<Vec<_>>>()?;
+
+	let states = object
+		.states
+		.iter()
+		.map(|st| {
+			Ok(pex::State {
+				name_idx: pex.intern(&st.name),
+				functions: st
+					.functions
+					.iter()
+					.map(|(name, f)| Ok((pex.intern(name), lower_function(pex, table, f)?)))
+					.collect::<PexResult<Vec<_>>>()?,
+			})
+		})
+		.collect::<PexResult<Vec<_>>>()?;
+
+	Ok(pex::ObjectData {
+		parent_name_idx: pex.intern(&object.parent),
+		doc_string_idx: pex.intern(&object.doc_string),
+		user_flags: lower_user_flags(table, &object.user_flags)?,
+		auto_state_name_idx: pex.intern(&object.auto_state),
+		variables,
+		properties,
+		states,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::builder::{FunctionBuilder, PropertyBuilder, fixture, ident, string};
+
+	#[test]
+	fn round_trip() {
+		let pex = fixture::builder()
+			.debug_info()
+			.comptime(1_320_969_600)
+			.user_flags(&["conditional"])
+			.variable("::count_var", "Int", VariableData::Int(2), &["conditional"])
+			.property(PropertyBuilder::auto("Count", "Int", VariableData::Int(0)).doc("How many"))
+			.property(
+				PropertyBuilder::new("Name", "String").getter(
+					FunctionBuilder::new()
+						.returns("String")
+						.line(3)
+						.op("RETURN", vec![string("TestScript")]),
+				),
+			)
+			.function(
+				"Run",
+				FunctionBuilder::new()
+					.user_flags(&["hidden"])
+					.param("akTarget", "ObjectReference")
+					.line(7)
+					.call(
+						"CALLSTATIC",
+						vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+						vec![string("zebra")],
+					)
+					.line(8)
+					.op("RETURN", vec![ident("::NoneVar")]),
+			)
+			.state_function(
+				"Busy",
+				"Run",
+				FunctionBuilder::new()
+					.param("akTarget", "ObjectReference")
+					.line(12)
+					.op("RETURN", vec![ident("::NoneVar")]),
+			)
+			.build()
+			.unwrap();
+
+		let bytes = pex::assemble(&pex).unwrap();
+		let parsed = pex::parse(&bytes).unwrap();
+		let model = Script::from(&parsed);
+		assert!(model.debuginfo.is_some());
+		assert_eq!(model.objects[0].user_flags, ["conditional"]);
+
+		let lowered = model.to_pex().unwrap();
+		assert_eq!(Script::from(&lowered), model);
+		assert_eq!(pex::assemble(&lowered).unwrap(), bytes);
+	}
+}
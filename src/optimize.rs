@@ -0,0 +1,444 @@
+//! Optimization passes over function bodies.
+
+use crate::{
+	edit::{self, Editor},
+	pex::{Instruction, Pex, VariableData},
+};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+	/// Arithmetic, negation and `STRCAT` on literals become an `ASSIGN` of the result.
+	FoldConstants,
+	/// Jumps to unconditional jumps go straight to the final target, and jumps to the next instruction are removed.
+	ThreadJumps,
+	RemoveNops,
+	RemoveUnreachable,
+	/// Side effect free stores to `::temp` locals that are never read, and those locals themselves.
+	RemoveDeadStores,
+}
+
+impl Pass {
+	/// Every pass, in an order where each one cleans up after the last.
+	/// Later passes can still open up work for earlier ones, so it's worth running until nothing changes.
+	pub const ALL: [Pass; 5] = [
+		Pass::FoldConstants,
+		Pass::RemoveNops,
+		Pass::ThreadJumps,
+		Pass::RemoveUnreachable,
+		Pass::RemoveDeadStores,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Pass::FoldConstants => "fold-constants",
+			Pass::ThreadJumps => "thread-jumps",
+			Pass::RemoveUnreachable => "remove-unreachable",
+			Pass::RemoveNops => "remove-nops",
+			Pass::RemoveDeadStores => "remove-dead-stores",
+		}
+	}
+
+	/// Runs the pass on one function, returning how many changes it made.
+	fn run(&self, strings: &mut Pex, editor: &mut Editor) -> usize {
+		match self {
+			Pass::FoldConstants => fold_constants(strings, editor),
+			Pass::ThreadJumps => thread_jumps(editor),
+			Pass::RemoveUnreachable => remove_unreachable(editor),
+			Pass::RemoveNops => remove_nops(editor),
+			Pass::RemoveDeadStores => remove_dead_stores(strings, editor),
+		}
+	}
+}
+
+impl std::fmt::Display for Pass {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+/// What a pass did to a function.
+#[derive(Debug, Clone)]
+pub struct Report {
+	pub path: String,
+	pub pass: Pass,
+	pub changes: usize,
+	/// Instruction counts before and after the pass.
+	pub before: usize,
+	pub after: usize,
+}
+
+impl std::fmt::Display for Report {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}: {} made {} changes, {} -> {} instructions",
+			self.path, self.pass, self.changes, self.before, self.after
+		)
+	}
+}
+
+/// Runs `passes` in order over every function, reporting each pass that changed something.
+pub fn optimize(pex: &mut Pex, passes: &[Pass]) -> Vec<Report> {
+	let mut reports = vec![];
+
+	for pass in passes {
+		// New strings are only ever appended, so indices held by the editors stay valid.
+		let mut strings = Pex {
+			stringtable: pex.stringtable.clone(),
+			..Default::default()
+		};
+
+		for (path, mut editor) in edit::editors(pex) {
+			let before = editor.function.instructions.len();
+			let changes = pass.run(&mut strings, &mut editor);
+
+			if changes > 0 {
+				reports.push(Report {
+					path,
+					pass: *pass,
+					changes,
+					before,
+					after: editor.function.instructions.len(),
+				});
+			}
+		}
+
+		pex.stringtable = strings.stringtable;
+	}
+
+	reports
+}
+
+fn fold(strings: &mut Pex, instruction: &Instruction) -> Option<VariableData> {
+	use VariableData::{Bool, Float, Int, String as Str};
+
+	Some(match instruction {
+		Instruction::IADD(_, Int(a), Int(b)) => Int(a.wrapping_add(*b)),
+		Instruction::ISUB(_, Int(a), Int(b)) => Int(a.wrapping_sub(*b)),
+		Instruction::IMUL(_, Int(a), Int(b)) => Int(a.wrapping_mul(*b)),
+		Instruction::IDIV(_, Int(a), Int(b)) if *b != 0 => Int(a.wrapping_div(*b)),
+		Instruction::IMOD(_, Int(a), Int(b)) if *b != 0 => Int(a.wrapping_rem(*b)),
+		Instruction::FADD(_, Float(a), Float(b)) => Float(a + b),
+		Instruction::FSUB(_, Float(a), Float(b)) => Float(a - b),
+		Instruction::FMUL(_, Float(a), Float(b)) => Float(a * b),
+		Instruction::FDIV(_, Float(a), Float(b)) if *b != 0.0 => Float(a / b),
+		Instruction::INEG(_, Int(a)) => Int(a.wrapping_neg()),
+		Instruction::FNEG(_, Float(a)) => Float(-a),
+		Instruction::NOT(_, Bool(a)) => Bool(!a),
+		Instruction::STRCAT(_, Str(a), Str(b)) => {
+			let s = format!("{}{}", strings.string(*a), strings.string(*b));
+			Str(strings.intern(&s))
+		}
+		_ => return None,
+	})
+}
+
+fn fold_constants(strings: &mut Pex, editor: &mut Editor) -> usize {
+	let mut changes = 0;
+
+	for instruction in &mut editor.function.instructions {
+		if let Some(value) = fold(strings, instruction)
+			&& let Some(dest) = instruction.destination()
+		{
+			*instruction = Instruction::ASSIGN(dest, value);
+			changes += 1;
+		}
+	}
+
+	changes
+}
+
+fn thread_jumps(editor: &mut Editor) -> usize {
+	let instructions = &mut editor.function.instructions;
+	let mut changes = 0;
+
+	for pc in 0..instructions.len() {
+		let Some(mut target) = instructions[pc].jump_target(pc) else {
+			continue;
+		};
+
+		// Bounded by the function length, so jump cycles can't hang us.
+		let original = target;
+		for _ in 0..instructions.len() {
+			match instructions.get(target) {
+				Some(jmp @ Instruction::JMP(_)) => match jmp.jump_target(target) {
+					Some(next) if next != target => target = next,
+					_ => break,
+				},
+				_ => break,
+			}
+		}
+
+		if target != original {
+			instructions[pc].set_jump_target(pc, target);
+			changes += 1;
+		}
+	}
+
+	// Jumps to the next instruction do nothing, as conditions are only ever variables or literals.
+	let mut pc = 0;
+	while pc < editor.function.instructions.len() {
+		if editor.function.instructions[pc].jump_target(pc) == Some(pc + 1) {
+			editor.remove(pc..pc + 1);
+			changes += 1;
+		} else {
+			pc += 1;
+		}
+	}
+
+	changes
+}
+
+fn remove_unreachable(editor: &mut Editor) -> usize {
+	let instructions = &editor.function.instructions;
+	let mut reachable = vec![false; instructions.len()];
+	let mut queue = vec![0];
+
+	while let Some(pc) = queue.pop() {
+		if pc >= instructions.len() || reachable[pc] {
+			continue;
+		}
+
+		reachable[pc] = true;
+		if instructions[pc].falls_through() {
+			queue.push(pc + 1);
+		}
+		queue.extend(instructions[pc].jump_target(pc));
+	}
+
+	// Remove from the back so earlier indices stay put.
+	let mut changes = 0;
+	let mut end = reachable.len();
+	while end > 0 {
+		if reachable[end - 1] {
+			end -= 1;
+			continue;
+		}
+
+		let start = reachable[..end]
+			.iter()
+			.rposition(|r| *r)
+			.map_or(0, |i| i + 1);
+		changes += end - start;
+		editor.remove(start..end);
+		end = start;
+	}
+
+	changes
+}
+
+fn remove_nops(editor: &mut Editor) -> usize {
+	let mut changes = 0;
+	let mut pc = 0;
+
+	while pc < editor.function.instructions.len() {
+		if editor.function.instructions[pc] == Instruction::NOP {
+			editor.remove(pc..pc + 1);
+			changes += 1;
+		} else {
+			pc += 1;
+		}
+	}
+
+	changes
+}
+
+/// Calls `f` with every variable an instruction reads, which is everything it mentions but its destination.
+fn for_each_read(instruction: &Instruction, mut f: impl FnMut(u16)) {
+	let mut dest = instruction.destination();
+	instruction.for_each_string(&mut |idx| match dest {
+		Some(d) if d == idx => dest = None,
+		_ => f(idx),
+	});
+}
+
+/// Whether removing the instruction can't change anything but its destination.
+fn is_pure(instruction: &Instruction) -> bool {
+	!matches!(
+		instruction,
+		Instruction::CALLMETHOD(..)
+			| Instruction::CALLPARENT(..)
+			| Instruction::CALLSTATIC(..)
+			| Instruction::PROPGET(..)
+			| Instruction::ARRAY_GETELEMENT(..)
+	) && instruction.destination().is_some()
+}
+
+fn remove_dead_stores(strings: &Pex, editor: &mut Editor) -> usize {
+	let temps = editor
+		.function
+		.locals
+		.iter()
+		.map(|l| l.name_idx)
+		.filter(|idx| strings.string(*idx).to_lowercase().starts_with("::temp"))
+		.collect::<Vec<_>>();
+
+	let instructions = &editor.function.instructions;
+
+	// How many reads each temp has left, and where each variable is stored to.
+	let mut counts = temps.iter().map(|t| (*t, 0)).collect::<HashMap<_, _>>();
+	let mut stores = HashMap::<u16, Vec<usize>>::new();
+	for (pc, instruction) in instructions.iter().enumerate() {
+		for_each_read(instruction, |idx| {
+			if let Some(count) = counts.get_mut(&idx) {
+				*count += 1;
+			}
+		});
+		if let Some(dest) = instruction.destination() {
+			stores.entry(dest).or_default().push(pc);
+		}
+	}
+
+	// Removing one store can leave the temps it read unused too, so those get another look.
+	let mut dead = vec![false; instructions.len()];
+	let mut queue = (0..instructions.len()).rev().collect::<Vec<_>>();
+	while let Some(pc) = queue.pop() {
+		let instruction = &instructions[pc];
+		let unread = instruction
+			.destination()
+			.is_some_and(|dest| counts.get(&dest) == Some(&0));
+		if dead[pc] || !unread || !is_pure(instruction) {
+			continue;
+		}
+
+		dead[pc] = true;
+		for_each_read(instruction, |idx| {
+			if let Some(count) = counts.get_mut(&idx) {
+				*count -= 1;
+				if *count == 0 {
+					queue.extend(stores.get(&idx).into_iter().flatten());
+				}
+			}
+		});
+	}
+
+	// Remove runs from the back so earlier indices stay put.
+	let mut changes = 0;
+	let mut end = dead.len();
+	while end > 0 {
+		if !dead[end - 1] {
+			end -= 1;
+			continue;
+		}
+
+		let start = dead[..end].iter().rposition(|d| !d).map_or(0, |i| i + 1);
+		changes += end - start;
+		editor.remove(start..end);
+		end = start;
+	}
+
+	let mut used = HashSet::new();
+	for instruction in &editor.function.instructions {
+		instruction.for_each_string(&mut |idx| {
+			used.insert(idx);
+		});
+	}
+
+	let before = editor.function.locals.len();
+	editor
+		.function
+		.locals
+		.retain(|l| !temps.contains(&l.name_idx) || used.contains(&l.name_idx));
+
+	changes + before - editor.function.locals.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture, ident, string},
+		model::VariableData::{Bool, Int},
+	};
+
+	fn script(function: FunctionBuilder) -> Pex {
+		fixture::script([("Run", function)])
+	}
+
+	fn body(pex: &Pex) -> Vec<String> {
+		fixture::listing(pex, "Run")
+	}
+
+	#[test]
+	fn fold_constants() {
+		let mut pex = script(
+			FunctionBuilder::new()
+				.returns("String")
+				.local("::temp0", "Int")
+				.local("::temp1", "String")
+				.op("IADD", vec![ident("::temp0"), Int(2), Int(3)])
+				.op("STRCAT", vec![ident("::temp1"), string("a"), string("b")])
+				.op("RETURN", vec![ident("::temp1")]),
+		);
+
+		let reports = optimize(&mut pex, &[Pass::FoldConstants]);
+		assert_eq!(reports[0].changes, 2);
+		assert!(body(&pex)[0].starts_with("ASSIGN ::temp0, 5"));
+		assert!(body(&pex)[1].contains("\"ab\""));
+	}
+
+	#[test]
+	fn remove_dead_store_chains() {
+		// ::temp1 is only read by a later store to ::temp2, which nothing reads,
+		// and ::temp0 is only read by an earlier one, so a single pass in order wouldn't catch it.
+		let mut pex = script(
+			FunctionBuilder::new()
+				.returns("Int")
+				.local("::temp0", "Int")
+				.local("::temp1", "Int")
+				.local("::temp2", "Int")
+				.local("::temp3", "Int")
+				.op("IADD", vec![ident("::temp3"), ident("::temp0"), Int(1)])
+				.op("ASSIGN", vec![ident("::temp0"), Int(1)])
+				.op("ASSIGN", vec![ident("::temp1"), Int(2)])
+				.op("IADD", vec![ident("::temp2"), ident("::temp1"), Int(1)])
+				.op("RETURN", vec![Int(0)]),
+		);
+
+		let reports = optimize(&mut pex, &[Pass::RemoveDeadStores]);
+		assert_eq!(reports[0].after, 1);
+		assert_eq!(body(&pex), ["RETURN 0"]);
+		assert!(
+			pex.find_function("TestScript", "", "Run")
+				.unwrap()
+				.locals
+				.is_empty()
+		);
+	}
+
+	#[test]
+	fn keep_stores_with_side_effects() {
+		let mut pex = script(
+			FunctionBuilder::new()
+				.local("::temp0", "Int")
+				.call(
+					"CALLMETHOD",
+					vec![ident("GetValue"), ident("self"), ident("::temp0")],
+					vec![],
+				)
+				.op("RETURN", vec![ident("::NoneVar")]),
+		);
+
+		assert!(optimize(&mut pex, &[Pass::RemoveDeadStores]).is_empty());
+		assert_eq!(body(&pex).len(), 2);
+	}
+
+	#[test]
+	fn thread_jumps() {
+		let mut pex = script(
+			FunctionBuilder::new()
+				.returns("Int")
+				.op("JMPT", vec![Bool(true), Int(2)])
+				.op("RETURN", vec![Int(1)])
+				.op("JMP", vec![Int(1)])
+				.op("RETURN", vec![Int(2)]),
+		);
+
+		// The JMP then only goes to the next instruction, so it's dropped.
+		optimize(&mut pex, &[Pass::ThreadJumps]);
+		let function = pex.find_function("TestScript", "", "Run").unwrap();
+		assert_eq!(function.instructions[0].jump_target(0), Some(2));
+		assert_eq!(body(&pex)[2], "RETURN 2");
+	}
+}
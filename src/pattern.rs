@@ -0,0 +1,768 @@
+//! Matching and rewriting instruction sequences, using the same syntax as [`crate::disasm`].
+//!
+//! ```text
+//! CALLMETHOD GetActorValue, $obj, $dest, ["Health"]; ASSIGN $x, $dest
+//! ```
+//!
+//! Instructions are separated by `;` or newlines. Operands can be:
+//! - `_` to match anything
+//! - `$name` to capture a value, which has to be the same everywhere it's used
+//! - identifiers, `"strings"`, numbers, `true`/`false` and `None` to match literally
+//!
+//! Call arguments go in brackets, where a trailing `..` matches any remaining arguments
+//! and `..$name` captures them. A mnemonic without operands matches any operands, and `*` matches any instruction.
+
+use crate::{
+	edit,
+	pex::{Function, Instruction, MNEMONICS, Pex, PexError, VariableData},
+};
+use std::collections::HashMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PatternError {
+	#[error("Syntax error at {0:?}: {1}")]
+	Syntax(String, &'static str),
+
+	#[error("Unknown mnemonic: {0}")]
+	UnknownMnemonic(String),
+
+	#[error("Capture ${0} is used in the template but never captured")]
+	UnboundCapture(String),
+
+	#[error("Templates can't contain wildcards")]
+	WildcardInTemplate,
+
+	#[error("Template for {0} doesn't have the operands that instruction takes")]
+	InvalidTemplate(&'static str),
+
+	#[error("Pex error: {0}")]
+	Pex(#[from] PexError),
+}
+
+pub type PatternResult<T> = Result<T, PatternError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+	Any,
+	Capture(String),
+	Ident(String),
+	String(String),
+	Int(i32),
+	Float(f32),
+	Bool(bool),
+	Null,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rest {
+	/// No more arguments allowed.
+	None,
+	/// `..`
+	Any,
+	/// `..$name`
+	Capture(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+	pub items: Vec<Operand>,
+	pub rest: Rest,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionPattern {
+	/// `None` for `*`.
+	pub opcode: Option<u8>,
+	/// `None` when no operands were written.
+	pub operands: Option<Vec<Operand>>,
+	pub args: Option<Args>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+	pub instructions: Vec<InstructionPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Capture {
+	Value(VariableData),
+	List(Vec<VariableData>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+	pub start: usize,
+	pub len: usize,
+	pub captures: HashMap<String, Capture>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Word(String),
+	Capture(String),
+	Str(String),
+	Number(String),
+	Comma,
+	Open,
+	Close,
+	Ellipsis,
+	Separator,
+}
+
+fn tokenize(src: &str) -> PatternResult<Vec<Token>> {
+	let mut tokens = vec![];
+	let mut chars = src.char_indices().peekable();
+
+	let word = |chars: &mut std::iter::Peekable<std::str::CharIndices>| {
+		let mut out = String::new();
+		while let Some(&(_, c)) = chars.peek() {
+			if !(c.is_alphanumeric() || c == '_' || c == ':') {
+				break;
+			}
+			out.push(c);
+			chars.next();
+		}
+		out
+	};
+
+	while let Some(&(pos, c)) = chars.peek() {
+		match c {
+			'\n' | ';' => {
+				chars.next();
+				tokens.push(Token::Separator);
+			}
+			c if c.is_whitespace() => {
+				chars.next();
+			}
+			',' => {
+				chars.next();
+				tokens.push(Token::Comma);
+			}
+			'[' => {
+				chars.next();
+				tokens.push(Token::Open);
+			}
+			']' => {
+				chars.next();
+				tokens.push(Token::Close);
+			}
+			'.' => {
+				chars.next();
+				if chars.next_if(|&(_, c)| c == '.').is_none() {
+					return Err(PatternError::Syntax(src[pos..].to_owned(), "expected .."));
+				}
+				tokens.push(Token::Ellipsis);
+			}
+			'$' => {
+				chars.next();
+				let name = word(&mut chars);
+				if name.is_empty() {
+					return Err(PatternError::Syntax(
+						src[pos..].to_owned(),
+						"expected capture name",
+					));
+				}
+				tokens.push(Token::Capture(name));
+			}
+			'"' => {
+				chars.next();
+				let mut out = String::new();
+				loop {
+					match chars.next() {
+						Some((_, '"')) => break,
+						Some((_, '\\')) => match chars.next() {
+							Some((_, 'n')) => out.push('\n'),
+							Some((_, 't')) => out.push('\t'),
+							Some((_, 'r')) => out.push('\r'),
+							Some((_, c @ ('"' | '\\' | '\''))) => out.push(c),
+							// Only \u{..}, so a missing brace can't swallow the rest of the pattern.
+							Some((_, 'u')) => {
+								let invalid = || {
+									PatternError::Syntax(
+										src[pos..].to_owned(),
+										"invalid unicode escape, expected \\u{..}",
+									)
+								};
+								if chars.next_if(|&(_, c)| c == '{').is_none() {
+									return Err(invalid());
+								}
+
+								let mut hex = String::new();
+								loop {
+									match chars.next() {
+										Some((_, '}')) => break,
+										Some((_, c)) if c.is_ascii_hexdigit() && hex.len() < 6 => {
+											hex.push(c)
+										}
+										_ => return Err(invalid()),
+									}
+								}
+
+								let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+								out.push(c.ok_or_else(invalid)?);
+							}
+							_ => {
+								return Err(PatternError::Syntax(
+									src[pos..].to_owned(),
+									"invalid escape",
+								));
+							}
+						},
+						Some((_, c)) => out.push(c),
+						None => {
+							return Err(PatternError::Syntax(
+								src[pos..].to_owned(),
+								"unterminated string",
+							));
+						}
+					}
+				}
+				tokens.push(Token::Str(out));
+			}
+			'*' => {
+				chars.next();
+				tokens.push(Token::Word("*".to_owned()));
+			}
+			c if c == '-' || c.is_ascii_digit() => {
+				let mut out = String::from(c);
+				chars.next();
+				while let Some((_, c)) = chars.next_if(|&(_, c)| {
+					c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+'
+				}) {
+					// Don't swallow a `..` following a number.
+					if c == '.' && chars.peek().is_some_and(|&(_, c)| c == '.') {
+						tokens.push(Token::Number(out));
+						tokens.push(Token::Ellipsis);
+						chars.next();
+						out = String::new();
+						break;
+					}
+					out.push(c);
+				}
+				if !out.is_empty() {
+					tokens.push(Token::Number(out));
+				}
+			}
+			c if c.is_alphanumeric() || c == '_' || c == ':' => {
+				tokens.push(Token::Word(word(&mut chars)));
+			}
+			_ => {
+				return Err(PatternError::Syntax(
+					src[pos..].to_owned(),
+					"unexpected character",
+				));
+			}
+		}
+	}
+
+	Ok(tokens)
+}
+
+fn operand(token: &Token) -> PatternResult<Operand> {
+	Ok(match token {
+		Token::Word(w) if w == "_" => Operand::Any,
+		Token::Word(w) if w == "true" => Operand::Bool(true),
+		Token::Word(w) if w == "false" => Operand::Bool(false),
+		Token::Word(w) if w == "None" => Operand::Null,
+		Token::Word(w) => Operand::Ident(w.clone()),
+		Token::Capture(c) => Operand::Capture(c.clone()),
+		Token::Str(s) => Operand::String(s.clone()),
+		Token::Number(n) => match n.parse::<i32>() {
+			Ok(i) => Operand::Int(i),
+			Err(_) => Operand::Float(
+				n.parse()
+					.map_err(|_| PatternError::Syntax(n.clone(), "invalid number"))?,
+			),
+		},
+		other => {
+			return Err(PatternError::Syntax(
+				format!("{other:?}"),
+				"expected an operand",
+			));
+		}
+	})
+}
+
+fn parse_args(tokens: &[Token]) -> PatternResult<Args> {
+	let mut items = vec![];
+	let mut rest = Rest::None;
+
+	let mut iter = tokens.split(|t| *t == Token::Comma).peekable();
+	while let Some(item) = iter.next() {
+		match item {
+			[] if tokens.is_empty() => break,
+			[Token::Ellipsis] if iter.peek().is_none() => rest = Rest::Any,
+			[Token::Ellipsis, Token::Capture(name)] if iter.peek().is_none() => {
+				rest = Rest::Capture(name.clone())
+			}
+			[token] => items.push(operand(token)?),
+			_ => {
+				return Err(PatternError::Syntax(
+					format!("{item:?}"),
+					"expected an argument",
+				));
+			}
+		}
+	}
+
+	Ok(Args { items, rest })
+}
+
+fn parse_instruction(tokens: &[Token]) -> PatternResult<InstructionPattern> {
+	let Some((Token::Word(mnemonic), tokens)) = tokens.split_first() else {
+		return Err(PatternError::Syntax(
+			format!("{tokens:?}"),
+			"expected a mnemonic",
+		));
+	};
+
+	let opcode = if mnemonic == "*" {
+		None
+	} else {
+		let opcode = MNEMONICS
+			.iter()
+			.position(|m| m.eq_ignore_ascii_case(mnemonic))
+			.ok_or_else(|| PatternError::UnknownMnemonic(mnemonic.clone()))?;
+		Some(opcode as u8)
+	};
+
+	if tokens.is_empty() {
+		return Ok(InstructionPattern {
+			opcode,
+			operands: None,
+			args: None,
+		});
+	}
+
+	let (tokens, args) = match tokens.iter().position(|t| *t == Token::Open) {
+		Some(open) => {
+			if tokens.last() != Some(&Token::Close) {
+				return Err(PatternError::Syntax(
+					mnemonic.clone(),
+					"expected ] at the end",
+				));
+			}
+			let args = parse_args(&tokens[open + 1..tokens.len() - 1])?;
+			let before = &tokens[..open];
+			(
+				before.strip_suffix(&[Token::Comma]).unwrap_or(before),
+				Some(args),
+			)
+		}
+		None => (tokens, None),
+	};
+
+	let operands = if tokens.is_empty() {
+		vec![]
+	} else {
+		tokens
+			.split(|t| *t == Token::Comma)
+			.map(|item| match item {
+				[token] => operand(token),
+				_ => Err(PatternError::Syntax(
+					format!("{item:?}"),
+					"expected an operand",
+				)),
+			})
+			.collect::<PatternResult<Vec<_>>>()?
+	};
+
+	Ok(InstructionPattern {
+		opcode,
+		operands: Some(operands),
+		args,
+	})
+}
+
+pub fn parse(src: &str) -> PatternResult<Pattern> {
+	let tokens = tokenize(src)?;
+	let instructions = tokens
+		.split(|t| *t == Token::Separator)
+		.filter(|t| !t.is_empty())
+		.map(parse_instruction)
+		.collect::<PatternResult<Vec<_>>>()?;
+
+	// An empty pattern never matches, but is a valid template for deleting matches.
+	Ok(Pattern { instructions })
+}
+
+fn match_operand(
+	strings: &[String],
+	pattern: &Operand,
+	value: &VariableData,
+	captures: &mut HashMap<String, Capture>,
+) -> bool {
+	let string = |idx: &u16| strings.get(*idx as usize).map(String::as_str);
+
+	match (pattern, value) {
+		(Operand::Any, _) => true,
+		(Operand::Capture(name), value) => match captures.get(name) {
+			Some(Capture::Value(existing)) => existing == value,
+			Some(Capture::List(_)) => false,
+			None => {
+				captures.insert(name.clone(), Capture::Value(value.clone()));
+				true
+			}
+		},
+		(Operand::Ident(s), VariableData::Ident(i)) => {
+			string(i).is_some_and(|i| i.eq_ignore_ascii_case(s))
+		}
+		(Operand::String(s), VariableData::String(i)) => string(i) == Some(s.as_str()),
+		(Operand::Int(a), VariableData::Int(b)) => a == b,
+		(Operand::Float(a), VariableData::Float(b)) => a == b,
+		(Operand::Bool(a), VariableData::Bool(b)) => a == b,
+		(Operand::Null, VariableData::Null) => true,
+		_ => false,
+	}
+}
+
+impl InstructionPattern {
+	fn matches(
+		&self,
+		strings: &[String],
+		instruction: &Instruction,
+		captures: &mut HashMap<String, Capture>,
+	) -> bool {
+		if self.opcode.is_some_and(|op| op != instruction.opcode()) {
+			return false;
+		}
+
+		let Some(patterns) = &self.operands else {
+			return true;
+		};
+
+		let (operands, varargs) = instruction.operands();
+		if patterns.len() != operands.len() {
+			return false;
+		}
+
+		let operands_match = patterns
+			.iter()
+			.zip(&operands)
+			.all(|(p, v)| match_operand(strings, p, v, captures));
+		if !operands_match {
+			return false;
+		}
+
+		let Some(args) = &self.args else {
+			return varargs.is_empty();
+		};
+
+		let count_ok = match args.rest {
+			Rest::None => varargs.len() == args.items.len(),
+			_ => varargs.len() >= args.items.len(),
+		};
+		if !count_ok {
+			return false;
+		}
+
+		let args_match = args
+			.items
+			.iter()
+			.zip(varargs)
+			.all(|(p, v)| match_operand(strings, p, v, captures));
+		if !args_match {
+			return false;
+		}
+
+		if let Rest::Capture(name) = &args.rest {
+			let rest = Capture::List(varargs[args.items.len()..].to_vec());
+			match captures.get(name) {
+				Some(existing) => return *existing == rest,
+				None => {
+					captures.insert(name.clone(), rest);
+				}
+			}
+		}
+
+		true
+	}
+}
+
+impl Pattern {
+	fn match_at(
+		&self,
+		strings: &[String],
+		instructions: &[Instruction],
+		start: usize,
+	) -> Option<Match> {
+		let window = instructions.get(start..start + self.instructions.len())?;
+
+		let mut captures = HashMap::new();
+		let all = self
+			.instructions
+			.iter()
+			.zip(window)
+			.all(|(p, i)| p.matches(strings, i, &mut captures));
+
+		all.then_some(Match {
+			start,
+			len: self.instructions.len(),
+			captures,
+		})
+	}
+
+	/// Non-overlapping matches in a function, in order.
+	pub fn find(&self, pex: &Pex, function: &Function) -> Vec<Match> {
+		self.find_in(&pex.stringtable, &function.instructions)
+	}
+
+	fn find_in(&self, strings: &[String], instructions: &[Instruction]) -> Vec<Match> {
+		let mut out = vec![];
+		let mut pc = 0;
+
+		if self.instructions.is_empty() {
+			return out;
+		}
+
+		while pc < instructions.len() {
+			match self.match_at(strings, instructions, pc) {
+				Some(m) => {
+					pc += m.len;
+					out.push(m);
+				}
+				None => pc += 1,
+			}
+		}
+
+		out
+	}
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+	Literal(VariableData),
+	Capture(String),
+}
+
+#[derive(Debug, Clone)]
+struct TemplateInstruction {
+	opcode: u8,
+	operands: Vec<Value>,
+	args: Vec<Value>,
+	/// `..$name` splice at the end of the arguments.
+	rest: Option<String>,
+}
+
+/// A rewrite template with its literals interned into a script's string table.
+struct Template {
+	instructions: Vec<TemplateInstruction>,
+}
+
+impl Template {
+	fn new(pex: &mut Pex, pattern: &Pattern) -> PatternResult<Self> {
+		let mut value = |op: &Operand| -> PatternResult<Value> {
+			Ok(match op {
+				Operand::Any => return Err(PatternError::WildcardInTemplate),
+				Operand::Capture(name) => Value::Capture(name.clone()),
+				Operand::Ident(s) => Value::Literal(VariableData::Ident(pex.intern(s))),
+				Operand::String(s) => Value::Literal(VariableData::String(pex.intern(s))),
+				Operand::Int(i) => Value::Literal(VariableData::Int(*i)),
+				Operand::Float(f) => Value::Literal(VariableData::Float(*f)),
+				Operand::Bool(b) => Value::Literal(VariableData::Bool(*b)),
+				Operand::Null => Value::Literal(VariableData::Null),
+			})
+		};
+
+		let instructions = pattern
+			.instructions
+			.iter()
+			.map(|ip| {
+				let opcode = ip.opcode.ok_or(PatternError::WildcardInTemplate)?;
+				let mnemonic = MNEMONICS[opcode as usize];
+
+				let operands = match &ip.operands {
+					Some(ops) => ops
+						.iter()
+						.map(&mut value)
+						.collect::<PatternResult<Vec<_>>>()?,
+					None if opcode == 0 => vec![],
+					None => return Err(PatternError::InvalidTemplate(mnemonic)),
+				};
+
+				let (args, rest) = match &ip.args {
+					Some(args) => {
+						let rest = match &args.rest {
+							Rest::None => None,
+							Rest::Any => return Err(PatternError::WildcardInTemplate),
+							Rest::Capture(name) => Some(name.clone()),
+						};
+						let items = args
+							.items
+							.iter()
+							.map(&mut value)
+							.collect::<PatternResult<Vec<_>>>()?;
+						(items, rest)
+					}
+					None => (vec![], None),
+				};
+
+				Ok(TemplateInstruction {
+					opcode,
+					operands,
+					args,
+					rest,
+				})
+			})
+			.collect::<PatternResult<Vec<_>>>()?;
+
+		Ok(Self { instructions })
+	}
+
+	fn instantiate(&self, captures: &HashMap<String, Capture>) -> PatternResult<Vec<Instruction>> {
+		let value = |v: &Value| match v {
+			Value::Literal(data) => Ok(data.clone()),
+			Value::Capture(name) => match captures.get(name) {
+				Some(Capture::Value(data)) => Ok(data.clone()),
+				_ => Err(PatternError::UnboundCapture(name.clone())),
+			},
+		};
+
+		self.instructions
+			.iter()
+			.map(|ti| {
+				let operands = ti
+					.operands
+					.iter()
+					.map(value)
+					.collect::<PatternResult<Vec<_>>>()?;
+				let mut args = ti
+					.args
+					.iter()
+					.map(value)
+					.collect::<PatternResult<Vec<_>>>()?;
+
+				if let Some(name) = &ti.rest {
+					match captures.get(name) {
+						Some(Capture::List(rest)) => args.extend(rest.iter().cloned()),
+						_ => return Err(PatternError::UnboundCapture(name.clone())),
+					}
+				}
+
+				Instruction::from_operands(ti.opcode, operands, args)
+					.map_err(|_| PatternError::InvalidTemplate(MNEMONICS[ti.opcode as usize]))
+			})
+			.collect()
+	}
+}
+
+/// Whether anything outside of a match jumps into the middle of it.
+fn jumped_into(instructions: &[Instruction], m: &Match) -> bool {
+	let inner = m.start + 1..m.start + m.len;
+
+	instructions.iter().enumerate().any(|(pc, instr)| {
+		!(m.start..m.start + m.len).contains(&pc)
+			&& instr.jump_target(pc).is_some_and(|t| inner.contains(&t))
+	})
+}
+
+/// Rewrites every match of `pattern` with `template` across all functions of a script.
+/// Matches that other code jumps into the middle of are skipped.
+/// Returns the number of rewrites for each function path that had any.
+pub fn rewrite(
+	pex: &mut Pex,
+	pattern: &Pattern,
+	template: &Pattern,
+) -> PatternResult<Vec<(String, usize)>> {
+	if pattern.instructions.is_empty() {
+		return Err(PatternError::Syntax(String::new(), "empty pattern"));
+	}
+
+	// Worked on a copy, so a template that fails halfway leaves the script as it was.
+	let mut rewritten = pex.clone();
+	let template = Template::new(&mut rewritten, template)?;
+	let strings = rewritten.stringtable.clone();
+	let mut out = vec![];
+
+	for (path, mut editor) in edit::editors(&mut rewritten) {
+		let mut count = 0;
+		let mut pc = 0;
+
+		while pc < editor.function.instructions.len() {
+			let Some(m) = pattern.match_at(&strings, &editor.function.instructions, pc) else {
+				pc += 1;
+				continue;
+			};
+
+			if jumped_into(&editor.function.instructions, &m) {
+				pc += 1;
+				continue;
+			}
+
+			let replacement = template.instantiate(&m.captures)?;
+			let advance = replacement.len();
+			editor.splice(m.start..m.start + m.len, replacement);
+
+			count += 1;
+			pc += advance;
+		}
+
+		if count > 0 {
+			out.push((path, count));
+		}
+	}
+
+	*pex = rewritten;
+	Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{
+			FunctionBuilder,
+			fixture::{self, listing},
+		},
+		model::VariableData::Int,
+	};
+
+	fn script() -> Pex {
+		let returns = |value| {
+			FunctionBuilder::new()
+				.returns("Int")
+				.op("RETURN", vec![Int(value)])
+		};
+		fixture::script([("One", returns(1)), ("Two", returns(2))])
+	}
+
+	#[test]
+	fn unicode_escape() {
+		let pattern = parse(r#"RETURN "\u{41}\u{e9}""#).unwrap();
+		assert_eq!(
+			pattern.instructions[0].operands,
+			Some(vec![Operand::String("Aé".into())])
+		);
+
+		assert!(parse(r#"RETURN "\u41}""#).is_err());
+		assert!(parse(r#"RETURN "\u{41""#).is_err());
+		assert!(parse(r#"RETURN "\u{1234567}""#).is_err());
+	}
+
+	#[test]
+	fn rewrite_matches() {
+		let mut pex = script();
+		let pattern = parse("RETURN 1").unwrap();
+		let template = parse("RETURN 3").unwrap();
+
+		let counts = rewrite(&mut pex, &pattern, &template).unwrap();
+		assert_eq!(counts, vec![("TestScript.One".to_owned(), 1)]);
+		assert_eq!(listing(&pex, "One"), ["RETURN 3"]);
+		assert_eq!(listing(&pex, "Two"), ["RETURN 2"]);
+	}
+
+	#[test]
+	fn failed_rewrite_leaves_script() {
+		let mut pex = script();
+		let before = pex.clone();
+		let pattern = parse("RETURN $x").unwrap();
+		let template = parse(r#"RETURN "Fresh"; RETURN $y"#).unwrap();
+
+		assert!(matches!(
+			rewrite(&mut pex, &pattern, &template),
+			Err(PatternError::UnboundCapture(_))
+		));
+		assert_eq!(pex.stringtable, before.stringtable);
+		assert_eq!(listing(&pex, "One"), listing(&before, "One"));
+	}
+}
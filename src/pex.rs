@@ -1,3 +1,4 @@
+use crate::edit;
 use nanoserde::{DeRon, SerRon};
 use std::io::{Read, Write};
 
@@ -265,22 +266,25 @@ impl<'a> Reader<'a> {
 		let name_idx = self.read_u16()?;
 		let type_idx = self.read_u16()?;
 		let doc_string_idx = self.read_u16()?;
-		let user_flags = self.read_u32()?;
-		let flags = self.read_u8()?;
+		let user_flags = UserFlags(self.read_u32()?);
+		let flags = PropertyFlags(self.read_u8()?);
 
-		let auto_var_name = if (flags & 4) != 0 {
+		let auto_var_name = if flags.contains(PropertyFlags::AUTOVAR) {
 			Some(self.read_u16()?)
 		} else {
 			None
 		};
 
-		let read_handler = if (flags & 5) == 1 {
+		// Auto properties are read and written through their variable, without handlers.
+		let handlers = !flags.contains(PropertyFlags::AUTOVAR);
+
+		let read_handler = if handlers && flags.contains(PropertyFlags::READ) {
 			Some(self.read_function()?)
 		} else {
 			None
 		};
 
-		let write_handler = if (flags & 6) == 2 {
+		let write_handler = if handlers && flags.contains(PropertyFlags::WRITE) {
 			Some(self.read_function()?)
 		} else {
 			None
@@ -301,8 +305,8 @@ impl<'a> Reader<'a> {
 	pub fn read_function(&mut self) -> PexResult<Function> {
 		let return_type_idx = self.read_u16()?;
 		let doc_string_idx = self.read_u16()?;
-		let user_flags = self.read_u32()?;
-		let flags = self.read_u8()?;
+		let user_flags = UserFlags(self.read_u32()?);
+		let flags = FunctionFlags(self.read_u8()?);
 
 		let params = {
 			let count = self.read_u16()?;
@@ -361,7 +365,7 @@ impl<'a> Reader<'a> {
 	pub fn read_object_data(&mut self) -> PexResult<ObjectData> {
 		let parent_name_idx = self.read_u16()?;
 		let doc_string_idx = self.read_u16()?;
-		let user_flags = self.read_u32()?;
+		let user_flags = UserFlags(self.read_u32()?);
 		let auto_state_name_idx = self.read_u16()?;
 
 		let variables = {
@@ -370,7 +374,7 @@ impl<'a> Reader<'a> {
 				.map(|_| {
 					let name_idx = self.read_u16()?;
 					let type_idx = self.read_u16()?;
-					let user_flags = self.read_u32()?;
+					let user_flags = UserFlags(self.read_u32()?);
 					let data = self.read_variable_data()?;
 
 					Ok((name_idx, type_idx, user_flags, data))
@@ -463,19 +467,22 @@ pub enum PexError {
 	#[error("Instruction was passed incorrect argument type")]
 	InvalidInstruction,
 
+	#[error("Failed to build script: {0}")]
+	Build(String),
+
 	#[error("IO error: {0}")]
 	IO(#[from] std::io::Error),
 }
 
 pub type PexResult<T> = Result<T, PexError>;
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct VariableType {
-	name_idx: u16,
-	type_idx: u16,
+	pub name_idx: u16,
+	pub type_idx: u16,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub enum VariableData {
 	Null,
 	Ident(u16),
@@ -485,9 +492,9 @@ pub enum VariableData {
 	Bool(bool),
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 #[non_exhaustive]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Instruction {
 	NOP,
 	IADD(u16, VariableData, VariableData),
@@ -527,62 +534,848 @@ pub enum Instruction {
 	ARRAY_RFINDELEMENT(u16, u16, VariableData, i32),
 }
 
-#[derive(Debug, DeRon, SerRon)]
+/// Mnemonics indexed by opcode.
+pub const MNEMONICS: [&str; 36] = [
+	"NOP",
+	"IADD",
+	"FADD",
+	"ISUB",
+	"FSUB",
+	"IMUL",
+	"FMUL",
+	"IDIV",
+	"FDIV",
+	"IMOD",
+	"NOT",
+	"INEG",
+	"FNEG",
+	"ASSIGN",
+	"CAST",
+	"CMP_EQ",
+	"CMP_LT",
+	"CMP_LE",
+	"CMP_GT",
+	"CMP_GE",
+	"JMP",
+	"JMPT",
+	"JMPF",
+	"CALLMETHOD",
+	"CALLPARENT",
+	"CALLSTATIC",
+	"RETURN",
+	"STRCAT",
+	"PROPGET",
+	"PROPSET",
+	"ARRAY_CREATE",
+	"ARRAY_LENGTH",
+	"ARRAY_GETELEMENT",
+	"ARRAY_SETELEMENT",
+	"ARRAY_FINDELEMENT",
+	"ARRAY_RFINDELEMENT",
+];
+
+impl Instruction {
+	pub fn opcode(&self) -> u8 {
+		match self {
+			Instruction::NOP => 0,
+			Instruction::IADD(..) => 1,
+			Instruction::FADD(..) => 2,
+			Instruction::ISUB(..) => 3,
+			Instruction::FSUB(..) => 4,
+			Instruction::IMUL(..) => 5,
+			Instruction::FMUL(..) => 6,
+			Instruction::IDIV(..) => 7,
+			Instruction::FDIV(..) => 8,
+			Instruction::IMOD(..) => 9,
+			Instruction::NOT(..) => 10,
+			Instruction::INEG(..) => 11,
+			Instruction::FNEG(..) => 12,
+			Instruction::ASSIGN(..) => 13,
+			Instruction::CAST(..) => 14,
+			Instruction::CMP_EQ(..) => 15,
+			Instruction::CMP_LT(..) => 16,
+			Instruction::CMP_LE(..) => 17,
+			Instruction::CMP_GT(..) => 18,
+			Instruction::CMP_GE(..) => 19,
+			Instruction::JMP(..) => 20,
+			Instruction::JMPT(..) => 21,
+			Instruction::JMPF(..) => 22,
+			Instruction::CALLMETHOD(..) => 23,
+			Instruction::CALLPARENT(..) => 24,
+			Instruction::CALLSTATIC(..) => 25,
+			Instruction::RETURN(..) => 26,
+			Instruction::STRCAT(..) => 27,
+			Instruction::PROPGET(..) => 28,
+			Instruction::PROPSET(..) => 29,
+			Instruction::ARRAY_CREATE(..) => 30,
+			Instruction::ARRAY_LENGTH(..) => 31,
+			Instruction::ARRAY_GETELEMENT(..) => 32,
+			Instruction::ARRAY_SETELEMENT(..) => 33,
+			Instruction::ARRAY_FINDELEMENT(..) => 34,
+			Instruction::ARRAY_RFINDELEMENT(..) => 35,
+		}
+	}
+
+	pub fn mnemonic(&self) -> &'static str {
+		MNEMONICS[self.opcode() as usize]
+	}
+
+	/// Operands in the order they're encoded, with the variadic arguments of calls split off.
+	pub fn operands(&self) -> (Vec<VariableData>, &[VariableData]) {
+		use VariableData::{Ident, Int};
+
+		match self {
+			Instruction::NOP => (vec![], &[]),
+			Instruction::IADD(a, b, c)
+			| Instruction::FADD(a, b, c)
+			| Instruction::ISUB(a, b, c)
+			| Instruction::FSUB(a, b, c)
+			| Instruction::IMUL(a, b, c)
+			| Instruction::FMUL(a, b, c)
+			| Instruction::IDIV(a, b, c)
+			| Instruction::FDIV(a, b, c)
+			| Instruction::IMOD(a, b, c)
+			| Instruction::CMP_EQ(a, b, c)
+			| Instruction::CMP_LT(a, b, c)
+			| Instruction::CMP_LE(a, b, c)
+			| Instruction::CMP_GT(a, b, c)
+			| Instruction::CMP_GE(a, b, c)
+			| Instruction::STRCAT(a, b, c)
+			| Instruction::ARRAY_SETELEMENT(a, b, c) => (vec![Ident(*a), b.clone(), c.clone()], &[]),
+			Instruction::NOT(a, b)
+			| Instruction::INEG(a, b)
+			| Instruction::FNEG(a, b)
+			| Instruction::ASSIGN(a, b)
+			| Instruction::CAST(a, b) => (vec![Ident(*a), b.clone()], &[]),
+			Instruction::JMP(a) | Instruction::RETURN(a) => (vec![a.clone()], &[]),
+			Instruction::JMPT(a, b) | Instruction::JMPF(a, b) => (vec![a.clone(), b.clone()], &[]),
+			Instruction::CALLMETHOD(a, b, c, d) => (vec![Ident(*a), b.clone(), Ident(*c)], d),
+			Instruction::CALLPARENT(a, b, c) => (vec![Ident(*a), Ident(*b)], c),
+			Instruction::CALLSTATIC(a, b, c, d) => (vec![Ident(*a), Ident(*b), Ident(*c)], d),
+			Instruction::PROPGET(a, b, c) => (vec![Ident(*a), Ident(*b), Ident(*c)], &[]),
+			Instruction::PROPSET(a, b, c) => (vec![Ident(*a), Ident(*b), c.clone()], &[]),
+			Instruction::ARRAY_CREATE(a, b) => (vec![Ident(*a), Int(*b as i32)], &[]),
+			Instruction::ARRAY_LENGTH(a, b) => (vec![Ident(*a), Ident(*b)], &[]),
+			Instruction::ARRAY_GETELEMENT(a, b, c) => (vec![Ident(*a), Ident(*b), c.clone()], &[]),
+			Instruction::ARRAY_FINDELEMENT(a, b, c, d)
+			| Instruction::ARRAY_RFINDELEMENT(a, b, c, d) => {
+				(vec![Ident(*a), Ident(*b), c.clone(), Int(*d)], &[])
+			}
+		}
+	}
+
+	/// Inverse of [`Instruction::operands`].
+	pub fn from_operands(
+		opcode: u8,
+		operands: Vec<VariableData>,
+		varargs: Vec<VariableData>,
+	) -> PexResult<Instruction> {
+		fn ident(v: &VariableData) -> PexResult<u16> {
+			match v {
+				VariableData::Ident(i) => Ok(*i),
+				_ => Err(PexError::InvalidInstruction),
+			}
+		}
+
+		fn int(v: &VariableData) -> PexResult<i32> {
+			match v {
+				VariableData::Int(i) => Ok(*i),
+				_ => Err(PexError::InvalidInstruction),
+			}
+		}
+
+		let is_call = matches!(opcode, 23..=25);
+		if !is_call && !varargs.is_empty() {
+			return Err(PexError::InvalidInstruction);
+		}
+
+		let instruction = match (opcode, &operands[..]) {
+			(0, []) => Instruction::NOP,
+			(1, [a, b, c]) => Instruction::IADD(ident(a)?, b.clone(), c.clone()),
+			(2, [a, b, c]) => Instruction::FADD(ident(a)?, b.clone(), c.clone()),
+			(3, [a, b, c]) => Instruction::ISUB(ident(a)?, b.clone(), c.clone()),
+			(4, [a, b, c]) => Instruction::FSUB(ident(a)?, b.clone(), c.clone()),
+			(5, [a, b, c]) => Instruction::IMUL(ident(a)?, b.clone(), c.clone()),
+			(6, [a, b, c]) => Instruction::FMUL(ident(a)?, b.clone(), c.clone()),
+			(7, [a, b, c]) => Instruction::IDIV(ident(a)?, b.clone(), c.clone()),
+			(8, [a, b, c]) => Instruction::FDIV(ident(a)?, b.clone(), c.clone()),
+			(9, [a, b, c]) => Instruction::IMOD(ident(a)?, b.clone(), c.clone()),
+			(10, [a, b]) => Instruction::NOT(ident(a)?, b.clone()),
+			(11, [a, b]) => Instruction::INEG(ident(a)?, b.clone()),
+			(12, [a, b]) => Instruction::FNEG(ident(a)?, b.clone()),
+			(13, [a, b]) => Instruction::ASSIGN(ident(a)?, b.clone()),
+			(14, [a, b]) => Instruction::CAST(ident(a)?, b.clone()),
+			(15, [a, b, c]) => Instruction::CMP_EQ(ident(a)?, b.clone(), c.clone()),
+			(16, [a, b, c]) => Instruction::CMP_LT(ident(a)?, b.clone(), c.clone()),
+			(17, [a, b, c]) => Instruction::CMP_LE(ident(a)?, b.clone(), c.clone()),
+			(18, [a, b, c]) => Instruction::CMP_GT(ident(a)?, b.clone(), c.clone()),
+			(19, [a, b, c]) => Instruction::CMP_GE(ident(a)?, b.clone(), c.clone()),
+			(20, [a]) => Instruction::JMP(a.clone()),
+			(21, [a, b]) => Instruction::JMPT(a.clone(), b.clone()),
+			(22, [a, b]) => Instruction::JMPF(a.clone(), b.clone()),
+			(23, [a, b, c]) => Instruction::CALLMETHOD(ident(a)?, b.clone(), ident(c)?, varargs),
+			(24, [a, b]) => Instruction::CALLPARENT(ident(a)?, ident(b)?, varargs),
+			(25, [a, b, c]) => Instruction::CALLSTATIC(ident(a)?, ident(b)?, ident(c)?, varargs),
+			(26, [a]) => Instruction::RETURN(a.clone()),
+			(27, [a, b, c]) => Instruction::STRCAT(ident(a)?, b.clone(), c.clone()),
+			(28, [a, b, c]) => Instruction::PROPGET(ident(a)?, ident(b)?, ident(c)?),
+			(29, [a, b, c]) => Instruction::PROPSET(ident(a)?, ident(b)?, c.clone()),
+			(30, [a, b]) => Instruction::ARRAY_CREATE(ident(a)?, int(b)? as u32),
+			(31, [a, b]) => Instruction::ARRAY_LENGTH(ident(a)?, ident(b)?),
+			(32, [a, b, c]) => Instruction::ARRAY_GETELEMENT(ident(a)?, ident(b)?, c.clone()),
+			(33, [a, b, c]) => Instruction::ARRAY_SETELEMENT(ident(a)?, b.clone(), c.clone()),
+			(34, [a, b, c, d]) => {
+				Instruction::ARRAY_FINDELEMENT(ident(a)?, ident(b)?, c.clone(), int(d)?)
+			}
+			(35, [a, b, c, d]) => {
+				Instruction::ARRAY_RFINDELEMENT(ident(a)?, ident(b)?, c.clone(), int(d)?)
+			}
+			_ => return Err(PexError::InvalidInstruction),
+		};
+
+		Ok(instruction)
+	}
+
+	/// Relative offset of a jump instruction. Papyrus jumps are relative to the jump itself.
+	pub fn jump_offset(&self) -> Option<i32> {
+		match self {
+			Instruction::JMP(VariableData::Int(offset))
+			| Instruction::JMPT(_, VariableData::Int(offset))
+			| Instruction::JMPF(_, VariableData::Int(offset)) => Some(*offset),
+			_ => None,
+		}
+	}
+
+	pub fn jump_target(&self, pc: usize) -> Option<usize> {
+		self.jump_offset()
+			.and_then(|offset| pc.checked_add_signed(offset as isize))
+	}
+
+	/// Points a jump instruction at `target`, given that it sits at `pc`. Does nothing to other instructions.
+	pub fn set_jump_target(&mut self, pc: usize, target: usize) {
+		let offset = VariableData::Int(target as i32 - pc as i32);
+		match self {
+			Instruction::JMP(o) | Instruction::JMPT(_, o) | Instruction::JMPF(_, o) => *o = offset,
+			_ => {}
+		}
+	}
+
+	/// The variable an instruction stores its result in, if any.
+	pub fn destination(&self) -> Option<u16> {
+		match self {
+			Instruction::IADD(dest, ..)
+			| Instruction::FADD(dest, ..)
+			| Instruction::ISUB(dest, ..)
+			| Instruction::FSUB(dest, ..)
+			| Instruction::IMUL(dest, ..)
+			| Instruction::FMUL(dest, ..)
+			| Instruction::IDIV(dest, ..)
+			| Instruction::FDIV(dest, ..)
+			| Instruction::IMOD(dest, ..)
+			| Instruction::NOT(dest, ..)
+			| Instruction::INEG(dest, ..)
+			| Instruction::FNEG(dest, ..)
+			| Instruction::ASSIGN(dest, ..)
+			| Instruction::CAST(dest, ..)
+			| Instruction::CMP_EQ(dest, ..)
+			| Instruction::CMP_LT(dest, ..)
+			| Instruction::CMP_LE(dest, ..)
+			| Instruction::CMP_GT(dest, ..)
+			| Instruction::CMP_GE(dest, ..)
+			| Instruction::CALLMETHOD(_, _, dest, _)
+			| Instruction::CALLPARENT(_, dest, _)
+			| Instruction::CALLSTATIC(_, _, dest, _)
+			| Instruction::STRCAT(dest, ..)
+			| Instruction::PROPGET(_, _, dest)
+			| Instruction::ARRAY_CREATE(dest, _)
+			| Instruction::ARRAY_LENGTH(dest, _)
+			| Instruction::ARRAY_GETELEMENT(dest, ..)
+			| Instruction::ARRAY_FINDELEMENT(_, dest, ..)
+			| Instruction::ARRAY_RFINDELEMENT(_, dest, ..) => Some(*dest),
+			_ => None,
+		}
+	}
+
+	/// Whether execution can continue onto the next instruction.
+	pub fn falls_through(&self) -> bool {
+		!matches!(self, Instruction::JMP(..) | Instruction::RETURN(..))
+	}
+
+	/// Visits every string table index referenced by the instruction, without changing them.
+	pub fn for_each_string<F: FnMut(u16)>(&self, f: &mut F) {
+		let (operands, args) = self.operands();
+		for data in operands.iter().chain(args) {
+			data.for_each_string(f);
+		}
+	}
+
+	/// Visits every string table index referenced by the instruction.
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		match self {
+			Instruction::NOP => {}
+			Instruction::IADD(a, b, c)
+			| Instruction::FADD(a, b, c)
+			| Instruction::ISUB(a, b, c)
+			| Instruction::FSUB(a, b, c)
+			| Instruction::IMUL(a, b, c)
+			| Instruction::FMUL(a, b, c)
+			| Instruction::IDIV(a, b, c)
+			| Instruction::FDIV(a, b, c)
+			| Instruction::IMOD(a, b, c)
+			| Instruction::CMP_EQ(a, b, c)
+			| Instruction::CMP_LT(a, b, c)
+			| Instruction::CMP_LE(a, b, c)
+			| Instruction::CMP_GT(a, b, c)
+			| Instruction::CMP_GE(a, b, c)
+			| Instruction::STRCAT(a, b, c)
+			| Instruction::ARRAY_SETELEMENT(a, b, c) => {
+				f(a);
+				b.for_each_string_mut(f);
+				c.for_each_string_mut(f);
+			}
+			Instruction::NOT(a, b)
+			| Instruction::INEG(a, b)
+			| Instruction::FNEG(a, b)
+			| Instruction::ASSIGN(a, b)
+			| Instruction::CAST(a, b) => {
+				f(a);
+				b.for_each_string_mut(f);
+			}
+			Instruction::JMP(a) | Instruction::RETURN(a) => a.for_each_string_mut(f),
+			Instruction::JMPT(a, b) | Instruction::JMPF(a, b) => {
+				a.for_each_string_mut(f);
+				b.for_each_string_mut(f);
+			}
+			Instruction::CALLMETHOD(a, b, c, d) => {
+				f(a);
+				b.for_each_string_mut(f);
+				f(c);
+				d.iter_mut().for_each(|arg| arg.for_each_string_mut(f));
+			}
+			Instruction::CALLPARENT(a, b, c) => {
+				f(a);
+				f(b);
+				c.iter_mut().for_each(|arg| arg.for_each_string_mut(f));
+			}
+			Instruction::CALLSTATIC(a, b, c, d) => {
+				f(a);
+				f(b);
+				f(c);
+				d.iter_mut().for_each(|arg| arg.for_each_string_mut(f));
+			}
+			Instruction::PROPGET(a, b, c) => {
+				f(a);
+				f(b);
+				f(c);
+			}
+			Instruction::PROPSET(a, b, c)
+			| Instruction::ARRAY_GETELEMENT(a, b, c)
+			| Instruction::ARRAY_FINDELEMENT(a, b, c, _)
+			| Instruction::ARRAY_RFINDELEMENT(a, b, c, _) => {
+				f(a);
+				f(b);
+				c.for_each_string_mut(f);
+			}
+			Instruction::ARRAY_CREATE(a, _) => f(a),
+			Instruction::ARRAY_LENGTH(a, b) => {
+				f(a);
+				f(b);
+			}
+		}
+	}
+}
+
+impl VariableData {
+	pub fn for_each_string<F: FnMut(u16)>(&self, f: &mut F) {
+		if let VariableData::Ident(idx) | VariableData::String(idx) = self {
+			f(*idx);
+		}
+	}
+
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		if let VariableData::Ident(idx) | VariableData::String(idx) = self {
+			f(idx);
+		}
+	}
+}
+
+impl Function {
+	/// Native functions are implemented by the game, and have no instructions.
+	pub fn is_native(&self) -> bool {
+		self.flags.contains(FunctionFlags::NATIVE)
+	}
+
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		f(&mut self.return_type_idx);
+		f(&mut self.doc_string_idx);
+
+		for var in self.params.iter_mut().chain(&mut self.locals) {
+			f(&mut var.name_idx);
+			f(&mut var.type_idx);
+		}
+
+		for instruction in &mut self.instructions {
+			instruction.for_each_string_mut(f);
+		}
+	}
+}
+
+impl Property {
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		f(&mut self.name_idx);
+		f(&mut self.type_idx);
+		f(&mut self.doc_string_idx);
+
+		if let Some(name) = &mut self.auto_var_name {
+			f(name);
+		}
+
+		for handler in [&mut self.read_handler, &mut self.write_handler]
+			.into_iter()
+			.flatten()
+		{
+			handler.for_each_string_mut(f);
+		}
+	}
+}
+
+impl State {
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		f(&mut self.name_idx);
+
+		for (name_idx, function) in &mut self.functions {
+			f(name_idx);
+			function.for_each_string_mut(f);
+		}
+	}
+}
+
+impl ObjectData {
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		f(&mut self.parent_name_idx);
+		f(&mut self.doc_string_idx);
+		f(&mut self.auto_state_name_idx);
+
+		for (name_idx, type_idx, _, data) in &mut self.variables {
+			f(name_idx);
+			f(type_idx);
+			data.for_each_string_mut(f);
+		}
+
+		for property in &mut self.properties {
+			property.for_each_string_mut(f);
+		}
+
+		for state in &mut self.states {
+			state.for_each_string_mut(f);
+		}
+	}
+}
+
+impl ObjectData {
+	/// State functions followed by property handlers, in the same order as [`Pex::function_paths`].
+	pub fn functions_mut(&mut self) -> impl Iterator<Item = &mut Function> {
+		let states = self
+			.states
+			.iter_mut()
+			.flat_map(|s| s.functions.iter_mut().map(|(_, f)| f));
+
+		let handlers = self.properties.iter_mut().flat_map(|p| {
+			[&mut p.read_handler, &mut p.write_handler]
+				.into_iter()
+				.flatten()
+		});
+
+		states.chain(handlers)
+	}
+}
+
+impl DebugFunction {
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		f(&mut self.obj_name_idx);
+		f(&mut self.state_name_idx);
+		f(&mut self.fn_name_idx);
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct Function {
 	pub return_type_idx: u16,
 	pub doc_string_idx: u16,
-	pub user_flags: u32,
-	pub flags: u8,
+	pub user_flags: UserFlags,
+	pub flags: FunctionFlags,
 	pub params: Vec<VariableType>,
 	pub locals: Vec<VariableType>,
 	pub instructions: Vec<Instruction>,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct Property {
-	name_idx: u16,
-	type_idx: u16,
-	doc_string_idx: u16,
-	user_flags: u32,
-	flags: u8,
-	auto_var_name: Option<u16>,
-	read_handler: Option<Function>,
-	write_handler: Option<Function>,
+	pub name_idx: u16,
+	pub type_idx: u16,
+	pub doc_string_idx: u16,
+	pub user_flags: UserFlags,
+	pub flags: PropertyFlags,
+	// nanoserde falls back to `else { None }` for missing Option fields, which clippy wants written with `?`.
+	#[nserde(default_with = "Option::default")]
+	pub auto_var_name: Option<u16>,
+	#[nserde(default_with = "Option::default")]
+	pub read_handler: Option<Function>,
+	#[nserde(default_with = "Option::default")]
+	pub write_handler: Option<Function>,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct State {
 	pub name_idx: u16,
 	pub functions: Vec<(u16, Function)>,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct ObjectData {
 	pub parent_name_idx: u16,
 	pub doc_string_idx: u16,
-	pub user_flags: u32,
+	pub user_flags: UserFlags,
 	pub auto_state_name_idx: u16,
-	pub variables: Vec<(u16, u16, u32, VariableData)>,
+	pub variables: Vec<(u16, u16, UserFlags, VariableData)>,
 	pub properties: Vec<Property>,
 	pub states: Vec<State>,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct DebugInfo {
-	modtime: u64,
-	functions: Vec<DebugFunction>,
+	pub modtime: u64,
+	pub functions: Vec<DebugFunction>,
+}
+
+impl DebugInfo {
+	/// Sets `modtime` to now, for line tables that no longer come straight from the source.
+	pub fn touch(&mut self) {
+		self.modtime = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map_or(0, |d| d.as_secs());
+	}
+}
+
+/// Path of a state function as used across the crate: `Object.Function`, or `Object.State.Function`
+/// outside of the default state.
+pub fn function_path(object: &str, state: &str, function: &str) -> String {
+	if state.is_empty() {
+		format!("{object}.{function}")
+	} else {
+		format!("{object}.{state}.{function}")
+	}
+}
+
+/// Formats a unix timestamp like [`Pex::comptime`] as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(secs: u64) -> String {
+	let (days, rest) = (secs / 86400, secs % 86400);
+
+	// Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+
+	format!(
+		"{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+		rest / 3600,
+		rest % 3600 / 60,
+		rest % 60
+	)
+}
+
+/// Parses a timestamp written by [`format_timestamp`]. The time of day can be left out.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+	let (date, time) = s.trim().split_once(' ').unwrap_or((s.trim(), "00:00:00"));
+
+	let mut date = date.split('-').map(str::parse::<i64>);
+	let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+	let mut time = time.trim().split(':').map(str::parse::<u64>);
+	let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+	if date.next().is_some()
+		|| time.next().is_some()
+		|| !(1..=12).contains(&month)
+		|| !(1..=31).contains(&day)
+		|| hour > 23
+		|| minute > 59
+		|| second > 59
+	{
+		return None;
+	}
+
+	// Days since the epoch from a civil date, the inverse of the above.
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = y.div_euclid(400);
+	let yoe = y.rem_euclid(400);
+	let mp = if month > 2 { month - 3 } else { month + 9 };
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+
+	Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// [`DebugFunction::fn_type`] of a regular function.
+pub const FN_TYPE_METHOD: u8 = 0;
+/// [`DebugFunction::fn_type`] of a property's read handler, named after the property.
+pub const FN_TYPE_GETTER: u8 = 1;
+/// [`DebugFunction::fn_type`] of a property's write handler, named after the property.
+pub const FN_TYPE_SETTER: u8 = 2;
+
+/// A set of flag bits, read and written in RON as their names, e.g. `["global", "native"]`.
+/// Bits without a name go by `bitN`.
+macro_rules! flag_set {
+	(
+		$(#[$meta:meta])*
+		$name:ident {
+			$($(#[$flag_meta:meta])* $flag:ident = $bit:literal, $flag_name:literal;)*
+		}
+	) => {
+		$(#[$meta])*
+		#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+		pub struct $name(pub u8);
+
+		impl $name {
+			$($(#[$flag_meta])* pub const $flag: Self = Self(1 << $bit);)*
+
+			pub const NAMES: &[(&str, Self)] = &[$(($flag_name, Self::$flag)),*];
+
+			pub fn contains(self, other: Self) -> bool {
+				self.0 & other.0 == other.0
+			}
+
+			pub fn set(&mut self, other: Self, on: bool) {
+				if on {
+					self.0 |= other.0;
+				} else {
+					self.0 &= !other.0;
+				}
+			}
+
+			pub fn names(self) -> Vec<String> {
+				(0..8)
+					.filter(|bit| self.0 & (1 << bit) != 0)
+					.map(|bit| {
+						Self::NAMES
+							.iter()
+							.find(|(_, flag)| flag.0 == 1 << bit)
+							.map_or_else(|| format!("bit{bit}"), |(name, _)| (*name).to_owned())
+					})
+					.collect()
+			}
+
+			/// Inverse of [`Self::names`], ignoring case.
+			pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+				names.iter().try_fold(Self::default(), |flags, name| {
+					let name = name.as_ref();
+					let flag = Self::NAMES
+						.iter()
+						.find(|(n, _)| n.eq_ignore_ascii_case(name))
+						.map(|(_, flag)| *flag)
+						.or_else(|| {
+							let bit = name.strip_prefix("bit")?.parse::<u8>().ok()?;
+							(bit < 8).then(|| Self(1 << bit))
+						})
+						.ok_or_else(|| format!("unknown flag {name:?}"))?;
+					Ok(flags | flag)
+				})
+			}
+		}
+
+		impl std::ops::BitOr for $name {
+			type Output = Self;
+
+			fn bitor(self, other: Self) -> Self {
+				Self(self.0 | other.0)
+			}
+		}
+
+		impl std::fmt::Display for $name {
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				write!(f, "{}", self.names().join(" "))
+			}
+		}
+
+		impl SerRon for $name {
+			fn ser_ron(&self, indent_level: usize, state: &mut nanoserde::SerRonState) {
+				self.names().ser_ron(indent_level, state);
+			}
+		}
+
+		impl DeRon for $name {
+			fn de_ron(
+				state: &mut nanoserde::DeRonState,
+				input: &mut std::str::Chars,
+			) -> Result<Self, nanoserde::DeRonErr> {
+				let names = Vec::<String>::de_ron(state, input)?;
+				Self::from_names(&names).map_err(|e| state.err_parse(&e))
+			}
+		}
+	};
+}
+
+flag_set! {
+	/// [`Function::flags`].
+	FunctionFlags {
+		/// Called without an object.
+		GLOBAL = 0, "global";
+		/// Implemented by the game, so there are no instructions.
+		NATIVE = 1, "native";
+	}
+}
+
+flag_set! {
+	/// [`Property::flags`].
+	PropertyFlags {
+		READ = 0, "read";
+		WRITE = 1, "write";
+		/// Backed by [`Property::auto_var_name`] instead of handlers.
+		AUTOVAR = 2, "autovar";
+	}
+}
+
+/// User flags of an object, variable, property or function. Their names live in [`Pex::userflags`],
+/// so RON only names them inside [`Pex::with_user_flag_names`] and falls back to `bitN` elsewhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserFlags(pub u32);
+
+thread_local! {
+	/// The user flag table [`UserFlags`] resolves its RON names through.
+	static USER_FLAG_NAMES: std::cell::RefCell<Vec<(String, u8)>> =
+		const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Puts back the names [`Pex::with_user_flag_names`] replaced, even when unwinding.
+struct RestoreUserFlagNames(Vec<(String, u8)>);
+
+impl Drop for RestoreUserFlagNames {
+	fn drop(&mut self) {
+		USER_FLAG_NAMES.set(std::mem::take(&mut self.0));
+	}
+}
+
+impl UserFlags {
+	pub fn contains(self, bit: u8) -> bool {
+		bit < 32 && self.0 & (1 << bit) != 0
+	}
+
+	pub fn set(&mut self, bit: u8, on: bool) {
+		if bit < 32 {
+			if on {
+				self.0 |= 1 << bit;
+			} else {
+				self.0 &= !(1 << bit);
+			}
+		}
+	}
+
+	/// Names of the set bits in `table`, `bitN` for bits it doesn't name.
+	pub fn names<S: AsRef<str>>(self, table: &[(S, u8)]) -> Vec<String> {
+		(0..32)
+			.filter(|bit| self.contains(*bit))
+			.map(|bit| {
+				table
+					.iter()
+					.find(|(_, b)| *b == bit)
+					.map_or_else(|| format!("bit{bit}"), |(name, _)| name.as_ref().to_owned())
+			})
+			.collect()
+	}
+
+	/// Inverse of [`Self::names`], ignoring case.
+	pub fn from_names<S: AsRef<str>, N: AsRef<str>>(
+		names: &[N],
+		table: &[(S, u8)],
+	) -> Result<Self, String> {
+		names.iter().try_fold(Self::default(), |mut flags, name| {
+			let name = name.as_ref();
+			let bit = table
+				.iter()
+				.find(|(n, _)| n.as_ref().eq_ignore_ascii_case(name))
+				.map(|(_, bit)| *bit)
+				.or_else(|| name.strip_prefix("bit")?.parse::<u8>().ok())
+				.filter(|bit| *bit < 32)
+				.ok_or_else(|| format!("unknown user flag {name:?}"))?;
+			flags.set(bit, true);
+			Ok(flags)
+		})
+	}
+}
+
+impl SerRon for UserFlags {
+	fn ser_ron(&self, indent_level: usize, state: &mut nanoserde::SerRonState) {
+		USER_FLAG_NAMES
+			.with_borrow(|table| self.names(table))
+			.ser_ron(indent_level, state);
+	}
+}
+
+impl DeRon for UserFlags {
+	fn de_ron(
+		state: &mut nanoserde::DeRonState,
+		input: &mut std::str::Chars,
+	) -> Result<Self, nanoserde::DeRonErr> {
+		// Older RON wrote the raw bits.
+		if let nanoserde::DeRonTok::U64(_) = state.tok {
+			return u32::de_ron(state, input).map(Self);
+		}
+		let names = Vec::<String>::de_ron(state, input)?;
+		USER_FLAG_NAMES
+			.with_borrow(|table| Self::from_names(&names, table))
+			.map_err(|e| state.err_parse(&e))
+	}
 }
 
-#[derive(Debug, DeRon, SerRon)]
+#[derive(Debug, Clone, PartialEq, DeRon, SerRon)]
 pub struct DebugFunction {
-	obj_name_idx: u16,
-	state_name_idx: u16,
-	fn_name_idx: u16,
-	fn_type: u8,
-	instructions: Vec<u16>,
+	pub obj_name_idx: u16,
+	pub state_name_idx: u16,
+	pub fn_name_idx: u16,
+	pub fn_type: u8,
+	pub instructions: Vec<u16>,
 }
 
-#[derive(Debug, DeRon, SerRon)]
+/// A native function's signature, with names resolved through the string table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeSignature {
+	/// As in [`Pex::function_paths`].
+	pub path: String,
+	pub name: String,
+	pub return_type: String,
+	/// Type and name of each parameter.
+	pub params: Vec<(String, String)>,
+	pub global: bool,
+}
+
+impl std::fmt::Display for NativeSignature {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let params = self
+			.params
+			.iter()
+			.map(|(type_name, name)| format!("{type_name} {name}"))
+			.collect::<Vec<_>>();
+
+		write!(
+			f,
+			"{} {}({})",
+			self.return_type,
+			self.name,
+			params.join(", ")
+		)?;
+		if self.global {
+			write!(f, " Global")?;
+		}
+		write!(f, " Native")
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, DeRon, SerRon)]
 pub struct Pex {
 	pub major: u8,
 	pub minor: u8,
@@ -592,11 +1385,478 @@ pub struct Pex {
 	pub username: String,
 	pub machine: String,
 	pub stringtable: Vec<String>,
+	#[nserde(default_with = "Option::default")]
 	pub debuginfo: Option<DebugInfo>,
 	pub userflags: Vec<(u16, u8)>,
 	pub objects: Vec<(u16, ObjectData)>,
 }
 
+impl Pex {
+	/// Looks up a string table entry, returning an empty string for out of range indices.
+	pub fn string(&self, idx: u16) -> &str {
+		self.stringtable
+			.get(idx as usize)
+			.map(String::as_str)
+			.unwrap_or_default()
+	}
+
+	/// Returns the index of `s` in the string table, appending it if it isn't there yet.
+	///
+	/// # Panics
+	/// If the table already holds [`MAX_STRINGS`], as many as a script can have.
+	pub fn intern(&mut self, s: &str) -> u16 {
+		match self.stringtable.iter().position(|existing| existing == s) {
+			Some(idx) => idx as u16,
+			None => {
+				let idx = self.stringtable.len();
+				assert!(
+					idx < MAX_STRINGS,
+					"string table is full, scripts can't have more than {MAX_STRINGS} strings"
+				);
+				self.stringtable.push(s.to_owned());
+				idx as u16
+			}
+		}
+	}
+
+	/// Visits every string table index referenced anywhere in the script.
+	pub fn for_each_string_mut<F: FnMut(&mut u16)>(&mut self, f: &mut F) {
+		if let Some(debug) = &mut self.debuginfo {
+			for function in &mut debug.functions {
+				function.for_each_string_mut(f);
+			}
+		}
+
+		for (name_idx, _) in &mut self.userflags {
+			f(name_idx);
+		}
+
+		for (name_idx, object) in &mut self.objects {
+			f(name_idx);
+			object.for_each_string_mut(f);
+		}
+	}
+
+	/// How many times each string table entry is referenced, through [`Pex::for_each_string_mut`].
+	pub fn string_uses(&mut self) -> Vec<usize> {
+		let mut uses = vec![0; self.stringtable.len()];
+		self.for_each_string_mut(&mut |idx| {
+			if let Some(uses) = uses.get_mut(*idx as usize) {
+				*uses += 1;
+			}
+		});
+		uses
+	}
+
+	/// Drops string table entries nothing refers to and merges duplicates, renumbering every reference.
+	/// With `sort`, the table is sorted too, so the same script always comes out the same.
+	/// Returns how many entries were dropped.
+	pub fn compact_strings(&mut self, sort: bool) -> usize {
+		let uses = self.string_uses();
+		let mut kept = (0..uses.len()).filter(|i| uses[*i] > 0).collect::<Vec<_>>();
+		if sort {
+			// Stable, so duplicates still merge into the first one.
+			kept.sort_by(|a, b| self.stringtable[*a].cmp(&self.stringtable[*b]));
+		}
+
+		let mut remap = vec![0; uses.len()];
+		let mut table = Vec::<String>::with_capacity(kept.len());
+		let mut seen = std::collections::HashMap::new();
+		for old in kept {
+			let s = &self.stringtable[old];
+			remap[old] = *seen.entry(s.as_str()).or_insert_with(|| {
+				table.push(s.clone());
+				(table.len() - 1) as u16
+			});
+		}
+		drop(seen);
+
+		// Nothing moved, so there's nothing to renumber.
+		let dropped = self.stringtable.len() - table.len();
+		if dropped == 0 && !sort {
+			return 0;
+		}
+
+		self.stringtable = table;
+		self.for_each_string_mut(&mut |idx| {
+			if let Some(new) = remap.get(*idx as usize) {
+				*idx = *new;
+			}
+		});
+
+		dropped
+	}
+
+	/// Removes an entry nothing refers to, shifting down the indices after it.
+	/// Returns false, changing nothing, if it's still referenced.
+	pub fn remove_string(&mut self, idx: u16) -> bool {
+		if self.string_uses().get(idx as usize) != Some(&0) {
+			return false;
+		}
+
+		self.stringtable.remove(idx as usize);
+		self.for_each_string_mut(&mut |i| {
+			if *i > idx {
+				*i -= 1;
+			}
+		});
+		true
+	}
+
+	/// Points the uses of entry `idx` as text, like literals and doc strings, at an entry holding `value`,
+	/// leaving the names that share it alone. Returns the entry they use now.
+	pub fn split_string(&mut self, idx: u16, value: &str) -> u16 {
+		let new = self.intern(value);
+		let text = |i: &mut u16| {
+			if *i == idx {
+				*i = new;
+			}
+		};
+		let literal = |data: &mut VariableData| {
+			if let VariableData::String(i) = data {
+				text(i);
+			}
+		};
+
+		for (_, object) in &mut self.objects {
+			text(&mut object.doc_string_idx);
+			for (_, _, _, data) in &mut object.variables {
+				literal(data);
+			}
+			for property in &mut object.properties {
+				text(&mut property.doc_string_idx);
+			}
+
+			for function in object.functions_mut() {
+				text(&mut function.doc_string_idx);
+				for instruction in &mut function.instructions {
+					let (mut operands, args) = instruction.operands();
+					let mut args = args.to_vec();
+					operands.iter_mut().chain(&mut args).for_each(literal);
+
+					if let Ok(split) =
+						Instruction::from_operands(instruction.opcode(), operands, args)
+					{
+						*instruction = split;
+					}
+				}
+			}
+		}
+
+		new
+	}
+
+	/// Parses an initial value for a variable of type `type_name`, interning strings.
+	/// `None` fits any type, anything else has to match it, so `5.0` isn't an `Int`.
+	pub fn parse_value(&mut self, type_name: &str, text: &str) -> Option<VariableData> {
+		let text = text.trim();
+		if text.eq_ignore_ascii_case("none") {
+			return Some(VariableData::Null);
+		}
+
+		match type_name.to_lowercase().as_str() {
+			"int" => text.parse().ok().map(VariableData::Int),
+			"float" => text.parse().ok().map(VariableData::Float),
+			"bool" if text.eq_ignore_ascii_case("true") => Some(VariableData::Bool(true)),
+			"bool" if text.eq_ignore_ascii_case("false") => Some(VariableData::Bool(false)),
+			"string" => {
+				let text = text
+					.strip_prefix('"')
+					.and_then(|t| t.strip_suffix('"'))
+					.unwrap_or(text);
+				Some(VariableData::String(self.intern(text)))
+			}
+			_ => None,
+		}
+	}
+
+	/// Every function with its path: `Object.Function`, `Object.State.Function`,
+	/// or `Object.Property.Get`/`Set` for property handlers.
+	pub fn function_paths(&self) -> Vec<(String, &Function)> {
+		self.functions_with_debug()
+			.into_iter()
+			.map(|(path, function, _)| (path, function))
+			.collect()
+	}
+
+	/// Like [`Pex::function_paths`], along with each function's debug info entry if there is one.
+	pub fn functions_with_debug(&self) -> Vec<(String, &Function, Option<&DebugFunction>)> {
+		let mut out = vec![];
+
+		for (obj_name, obj) in &self.objects {
+			let obj_name = self.string(*obj_name);
+
+			for state in &obj.states {
+				let state_name = self.string(state.name_idx);
+
+				for (name_idx, function) in &state.functions {
+					let name = self.string(*name_idx);
+					let debug = self.debug_function(obj_name, state_name, name, FN_TYPE_METHOD);
+					out.push((function_path(obj_name, state_name, name), function, debug));
+				}
+			}
+
+			for property in &obj.properties {
+				let prop_name = self.string(property.name_idx);
+				let handlers = [
+					("Get", FN_TYPE_GETTER, &property.read_handler),
+					("Set", FN_TYPE_SETTER, &property.write_handler),
+				];
+
+				for (kind, fn_type, handler) in handlers {
+					if let Some(function) = handler {
+						let debug = self.debug_function(obj_name, "", prop_name, fn_type);
+						out.push((format!("{obj_name}.{prop_name}.{kind}"), function, debug));
+					}
+				}
+			}
+		}
+
+		out
+	}
+
+	pub fn functions_mut(&mut self) -> impl Iterator<Item = &mut Function> {
+		self.objects.iter_mut().flat_map(|(_, o)| o.functions_mut())
+	}
+
+	/// Signatures of every native function, what the script expects the game to provide.
+	pub fn native_functions(&self) -> Vec<NativeSignature> {
+		let mut out = vec![];
+
+		for (obj_name, obj) in &self.objects {
+			let obj_name = self.string(*obj_name);
+
+			for state in &obj.states {
+				let state_name = self.string(state.name_idx);
+
+				for (name_idx, function) in &state.functions {
+					if !function.is_native() {
+						continue;
+					}
+
+					let name = self.string(*name_idx);
+					out.push(NativeSignature {
+						path: function_path(obj_name, state_name, name),
+						name: name.to_owned(),
+						return_type: self.string(function.return_type_idx).to_owned(),
+						params: function
+							.params
+							.iter()
+							.map(|p| {
+								(
+									self.string(p.type_idx).to_owned(),
+									self.string(p.name_idx).to_owned(),
+								)
+							})
+							.collect(),
+						global: function.flags.contains(FunctionFlags::GLOBAL),
+					});
+				}
+			}
+		}
+
+		out
+	}
+
+	/// The `userflags` table with names resolved through the string table.
+	pub fn user_flag_table(&self) -> Vec<(&str, u8)> {
+		self.userflags
+			.iter()
+			.map(|(name_idx, bit)| (self.string(*name_idx), *bit))
+			.collect()
+	}
+
+	/// Names of the user flags set in `flags`, resolved through the `userflags` table.
+	pub fn user_flag_names(&self, flags: UserFlags) -> Vec<String> {
+		flags.names(&self.user_flag_table())
+	}
+
+	/// Runs `f` with [`UserFlags`] written to and read from RON by this script's flag names.
+	pub fn with_user_flag_names<R>(&self, f: impl FnOnce() -> R) -> R {
+		let table = self
+			.user_flag_table()
+			.into_iter()
+			.map(|(name, bit)| (name.to_owned(), bit))
+			.collect();
+		let _restore = RestoreUserFlagNames(USER_FLAG_NAMES.replace(table));
+		f()
+	}
+
+	/// Finds a function by object, state and function name. The default state is named "".
+	pub fn find_function(&self, object: &str, state: &str, function: &str) -> Option<&Function> {
+		self.objects
+			.iter()
+			.filter(|(name_idx, _)| self.string(*name_idx).eq_ignore_ascii_case(object))
+			.flat_map(|(_, obj)| &obj.states)
+			.filter(|s| self.string(s.name_idx).eq_ignore_ascii_case(state))
+			.flat_map(|s| &s.functions)
+			.find(|(name_idx, _)| self.string(*name_idx).eq_ignore_ascii_case(function))
+			.map(|(_, f)| f)
+	}
+
+	/// Finds a function's debug info entry. Property handlers are named after their property,
+	/// and their state isn't checked.
+	pub fn debug_function(
+		&self,
+		object: &str,
+		state: &str,
+		function: &str,
+		fn_type: u8,
+	) -> Option<&DebugFunction> {
+		self.debuginfo.iter().flat_map(|d| &d.functions).find(|d| {
+			d.fn_type == fn_type
+				&& self.string(d.obj_name_idx).eq_ignore_ascii_case(object)
+				&& self.string(d.fn_name_idx).eq_ignore_ascii_case(function)
+				&& (fn_type != FN_TYPE_METHOD
+					|| self.string(d.state_name_idx).eq_ignore_ascii_case(state))
+		})
+	}
+
+	/// Debug info brought in line with the functions as they are now, leaving `modtime` alone.
+	///
+	/// Entries of removed functions are dropped. A function without an entry takes over an unused one
+	/// of the same object, state and kind, as happens when it was renamed, if it's the only one left
+	/// or the only one covering as many instructions. Line tables that don't cover every instruction are realigned
+	/// against the function in `before`, the script as it was when they last matched, like
+	/// [`crate::edit::Editor::set_function`] does. Without it they're cut short or padded with their last line.
+	pub fn synced_debuginfo(&self, before: Option<&Pex>) -> Option<DebugInfo> {
+		let debug = self.debuginfo.as_ref()?;
+
+		// (object, state, name, fn_type, function). Property handlers have no state.
+		let mut functions = vec![];
+		for (obj_name, obj) in &self.objects {
+			for state in &obj.states {
+				for (name_idx, function) in &state.functions {
+					functions.push((
+						*obj_name,
+						state.name_idx,
+						*name_idx,
+						FN_TYPE_METHOD,
+						function,
+					));
+				}
+			}
+
+			for property in &obj.properties {
+				let handlers = [
+					(FN_TYPE_GETTER, &property.read_handler),
+					(FN_TYPE_SETTER, &property.write_handler),
+				];
+				for (fn_type, handler) in handlers {
+					if let Some(function) = handler {
+						functions.push((*obj_name, 0, property.name_idx, fn_type, function));
+					}
+				}
+			}
+		}
+
+		type Entry<'f> = (u16, u16, u16, u8, &'f Function);
+
+		let same = |a: u16, b: u16| self.string(a).eq_ignore_ascii_case(self.string(b));
+		let owner = |d: &DebugFunction, (object, state, _, fn_type, _): Entry| {
+			d.fn_type == fn_type
+				&& same(d.obj_name_idx, object)
+				&& (fn_type != FN_TYPE_METHOD || same(d.state_name_idx, state))
+		};
+
+		// The function an entry was made for, as it was in `before`.
+		let earlier = |d: &DebugFunction| {
+			let before = before?;
+			let (object, name) = (self.string(d.obj_name_idx), self.string(d.fn_name_idx));
+			let before_debug =
+				before.debug_function(object, self.string(d.state_name_idx), name, d.fn_type)?;
+			before
+				.functions_with_debug()
+				.into_iter()
+				.find(|(_, _, debug)| debug.is_some_and(|debug| std::ptr::eq(debug, before_debug)))
+				.map(|(_, function, _)| function)
+		};
+
+		let mut entries = debug.functions.iter().map(Some).collect::<Vec<_>>();
+		let mut synced = vec![None; entries.len()];
+
+		let mut take = |function: Entry, renamed: bool| {
+			let (_, _, name, _, current) = function;
+			let len = current.instructions.len();
+			let candidates = entries
+				.iter()
+				.enumerate()
+				.filter(|(_, d)| {
+					d.is_some_and(|d| owner(d, function) && (renamed || same(d.fn_name_idx, name)))
+				})
+				.map(|(idx, _)| idx)
+				.collect::<Vec<_>>();
+
+			// A renamed function only takes over the last entry left, or the one covering as many
+			// instructions. When several do there's no telling which was its own.
+			let idx = match candidates[..] {
+				[idx] => idx,
+				_ if renamed => {
+					let same_len = candidates
+						.iter()
+						.filter(|idx| entries[**idx].is_some_and(|d| d.instructions.len() == len))
+						.collect::<Vec<_>>();
+					match same_len[..] {
+						[idx] => *idx,
+						_ => return None,
+					}
+				}
+				_ => *candidates.first()?,
+			};
+
+			let entry = entries[idx].take()?;
+			let instructions = match earlier(entry) {
+				Some(old) if entry.instructions.len() != len => edit::realign_lines(
+					&old.instructions,
+					&current.instructions,
+					&entry.instructions,
+				),
+				_ => {
+					let mut lines = entry.instructions.clone();
+					let last = lines.last().copied().unwrap_or_default();
+					lines.resize(len, last);
+					lines
+				}
+			};
+
+			synced[idx] = Some(DebugFunction {
+				fn_name_idx: name,
+				instructions,
+				..entry.clone()
+			});
+			Some(())
+		};
+
+		// Match by name first, so renames can't take entries from functions that kept theirs.
+		let unmatched = functions
+			.into_iter()
+			.filter(|f| take(*f, false).is_none())
+			.collect::<Vec<_>>();
+		for function in unmatched {
+			take(function, true);
+		}
+
+		Some(DebugInfo {
+			modtime: debug.modtime,
+			functions: synced.into_iter().flatten().collect(),
+		})
+	}
+
+	/// Replaces the debug info with [`Pex::synced_debuginfo`], and [`DebugInfo::touch`]es it
+	/// if that changed anything or the functions changed since `before`. Returns whether it did.
+	pub fn sync_debuginfo(&mut self, before: Option<&Pex>) -> bool {
+		let synced = self.synced_debuginfo(before);
+		let changed = synced != self.debuginfo
+			|| before.is_some_and(|before| before.function_paths() != self.function_paths());
+
+		self.debuginfo = synced;
+		if changed && let Some(debug) = &mut self.debuginfo {
+			debug.touch();
+		}
+		changed
+	}
+}
+
 pub fn parse(pex: &[u8]) -> PexResult<Pex> {
 	let mut reader = Reader::new(pex);
 
@@ -674,15 +1934,14 @@ pub fn parse(pex: &[u8]) -> PexResult<Pex> {
 	})
 }
 
+#[derive(Default)]
 pub struct Writer {
 	cursor: std::io::Cursor<Vec<u8>>,
 }
 
 impl Writer {
 	pub fn new() -> Self {
-		Self {
-			cursor: std::io::Cursor::new(Vec::new()),
-		}
+		Self::default()
 	}
 
 	pub fn write_u8(&mut self, value: u8) -> std::io::Result<()> {
@@ -927,7 +2186,6 @@ impl Writer {
 				self.write_variable_data(c)?;
 				self.write_variable_data(&VariableData::Int(*d))?;
 			}
-			_ => todo!("Write instruction {:?}", value),
 		}
 		Ok(())
 	}
@@ -968,8 +2226,8 @@ impl Writer {
 		self.write_u16(value.name_idx)?;
 		self.write_u16(value.type_idx)?;
 		self.write_u16(value.doc_string_idx)?;
-		self.write_u32(value.user_flags)?;
-		self.write_u8(value.flags)?;
+		self.write_u32(value.user_flags.0)?;
+		self.write_u8(value.flags.0)?;
 
 		if let Some(name) = value.auto_var_name {
 			self.write_u16(name)?;
@@ -989,8 +2247,8 @@ impl Writer {
 	pub fn write_function(&mut self, value: &Function) -> PexResult<()> {
 		self.write_u16(value.return_type_idx)?;
 		self.write_u16(value.doc_string_idx)?;
-		self.write_u32(value.user_flags)?;
-		self.write_u8(value.flags)?;
+		self.write_u32(value.user_flags.0)?;
+		self.write_u8(value.flags.0)?;
 
 		self.write_u16(value.params.len() as u16)?;
 		for param in &value.params {
@@ -1029,14 +2287,14 @@ impl Writer {
 	pub fn write_object_data(&mut self, value: &ObjectData) -> PexResult<()> {
 		self.write_u16(value.parent_name_idx)?;
 		self.write_u16(value.doc_string_idx)?;
-		self.write_u32(value.user_flags)?;
+		self.write_u32(value.user_flags.0)?;
 		self.write_u16(value.auto_state_name_idx)?;
 
 		self.write_u16(value.variables.len() as u16)?;
 		for var in &value.variables {
 			self.write_u16(var.0)?;
 			self.write_u16(var.1)?;
-			self.write_u32(var.2)?;
+			self.write_u32(var.2.0)?;
 			self.write_variable_data(&var.3)?;
 		}
 
@@ -1078,7 +2336,40 @@ impl Writer {
 	}
 }
 
+/// How many strings a script can have, as the string table's length is written as a `u16`.
+pub const MAX_STRINGS: usize = u16::MAX as usize;
+
+/// What [`assemble_with`] does to the string table before writing it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringTable {
+	/// Written as it is, so a script that wasn't changed comes out byte for byte the same.
+	#[default]
+	Keep,
+	/// Unused and duplicate strings are left out, see [`Pex::compact_strings`].
+	Compact,
+	/// Compacted, then sorted, so the same script always assembles the same way.
+	Sort,
+}
+
+/// Writes a script, with its debug info synced to the functions and the string table as it is.
 pub fn assemble(pex: &Pex) -> PexResult<Vec<u8>> {
+	assemble_with(pex, StringTable::Keep)
+}
+
+/// Writes a script, with its debug info synced to the functions.
+pub fn assemble_with(pex: &Pex, strings: StringTable) -> PexResult<Vec<u8>> {
+	let mut pex = pex.clone();
+	pex.sync_debuginfo(None);
+	match strings {
+		StringTable::Keep => {}
+		StringTable::Compact => {
+			pex.compact_strings(false);
+		}
+		StringTable::Sort => {
+			pex.compact_strings(true);
+		}
+	}
+
 	let mut writer = Writer::new();
 
 	writer.write_u32(0xFA57C0DE)?;
@@ -1090,6 +2381,12 @@ pub fn assemble(pex: &Pex) -> PexResult<Vec<u8>> {
 	writer.write_wstring(&pex.username)?;
 	writer.write_wstring(&pex.machine)?;
 
+	if pex.stringtable.len() > MAX_STRINGS {
+		return Err(PexError::Build(format!(
+			"{} strings, scripts can't have more than {MAX_STRINGS}",
+			pex.stringtable.len()
+		)));
+	}
 	writer.write_u16(pex.stringtable.len() as u16)?;
 	for s in &pex.stringtable {
 		writer.write_wstring(s)?;
@@ -1118,3 +2415,326 @@ pub fn assemble(pex: &Pex) -> PexResult<Vec<u8>> {
 
 	Ok(writer.cursor.into_inner())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, fixture, ident, string},
+		disasm,
+		model::{Script, VariableData::Int},
+	};
+
+	/// Functions with one `RETURN` per line, starting at line 1.
+	fn script(functions: &[(&str, u16)]) -> Pex {
+		let mut builder = fixture::builder().debug_info();
+		for (name, count) in functions {
+			let mut function = FunctionBuilder::new().returns("Int");
+			for line in 1..=*count {
+				function = function.line(line).op("RETURN", vec![Int(line as i32)]);
+			}
+			builder = builder.function(name, function);
+		}
+		builder.build().unwrap()
+	}
+
+	fn function_mut<'p>(pex: &'p mut Pex, name: &str) -> &'p mut (u16, Function) {
+		let name = pex.intern(name);
+		let state = &mut pex.objects[0].1.states[0];
+		state
+			.functions
+			.iter_mut()
+			.find(|(n, _)| *n == name)
+			.unwrap()
+	}
+
+	fn lines<'p>(pex: &'p Pex, name: &str) -> Option<&'p [u16]> {
+		let debug = pex.debug_function("TestScript", "", name, FN_TYPE_METHOD)?;
+		Some(&debug.instructions)
+	}
+
+	#[test]
+	fn sync_middle_insert() {
+		let mut pex = script(&[("Run", 4)]);
+		let before = pex.clone();
+		function_mut(&mut pex, "Run")
+			.1
+			.instructions
+			.insert(2, Instruction::NOP);
+
+		let mut synced = pex.clone();
+		assert!(synced.sync_debuginfo(Some(&before)));
+		assert_eq!(lines(&synced, "Run"), Some(&[1, 2, 2, 3, 4][..]));
+
+		// Without knowing what changed, the table can only be padded at the end.
+		pex.sync_debuginfo(None);
+		assert_eq!(lines(&pex, "Run"), Some(&[1, 2, 3, 4, 4][..]));
+	}
+
+	#[test]
+	fn sync_renames() {
+		// The only entry left goes to the renamed function.
+		let mut pex = script(&[("One", 1), ("Two", 2)]);
+		function_mut(&mut pex, "One").0 = pex.intern("Uno");
+		pex.sync_debuginfo(None);
+		assert_eq!(lines(&pex, "Uno"), Some(&[1][..]));
+		assert_eq!(lines(&pex, "Two"), Some(&[1, 2][..]));
+
+		// Several are left, so it goes by instruction count.
+		let mut pex = script(&[("One", 1), ("Two", 2)]);
+		function_mut(&mut pex, "One").0 = pex.intern("Uno");
+		function_mut(&mut pex, "Two").0 = pex.intern("Dos");
+		pex.sync_debuginfo(None);
+		assert_eq!(lines(&pex, "Uno"), Some(&[1][..]));
+		assert_eq!(lines(&pex, "Dos"), Some(&[1, 2][..]));
+
+		// Both are as long as the renamed function, so neither is taken.
+		let mut pex = script(&[("One", 1), ("Two", 1)]);
+		function_mut(&mut pex, "One").0 = pex.intern("Uno");
+		function_mut(&mut pex, "Two").0 = pex.intern("Dos");
+		pex.sync_debuginfo(None);
+		assert_eq!(lines(&pex, "Uno"), None);
+		assert_eq!(lines(&pex, "Dos"), None);
+
+		// Nothing to go by, so no lines rather than someone else's.
+		let mut pex = script(&[("One", 1), ("Two", 2)]);
+		pex.objects[0].1.states[0]
+			.functions
+			.retain(|(n, _)| pex.stringtable[*n as usize] != "Two");
+		function_mut(&mut pex, "One").0 = pex.intern("Uno");
+		function_mut(&mut pex, "Uno")
+			.1
+			.instructions
+			.push(Instruction::NOP);
+		function_mut(&mut pex, "Uno")
+			.1
+			.instructions
+			.push(Instruction::NOP);
+		pex.sync_debuginfo(None);
+		assert_eq!(lines(&pex, "Uno"), None);
+	}
+
+	#[test]
+	fn sync_touches() {
+		let modtime = |pex: &Pex| pex.debuginfo.as_ref().unwrap().modtime;
+		let pex = script(&[("Run", 2)]);
+		assert_eq!(modtime(&pex), 0);
+
+		let mut unchanged = pex.clone();
+		assert!(!unchanged.sync_debuginfo(Some(&pex)));
+		assert_eq!(modtime(&unchanged), 0);
+
+		// Lines that still line up, but no longer for the same instructions.
+		let mut replaced = pex.clone();
+		function_mut(&mut replaced, "Run").1.instructions[0] = Instruction::NOP;
+		assert!(!replaced.clone().sync_debuginfo(None));
+		assert!(replaced.sync_debuginfo(Some(&pex)));
+		assert_ne!(modtime(&replaced), 0);
+
+		let mut grown = pex.clone();
+		function_mut(&mut grown, "Run")
+			.1
+			.instructions
+			.push(Instruction::NOP);
+		assert!(grown.sync_debuginfo(None));
+		assert_ne!(modtime(&grown), 0);
+	}
+
+	#[test]
+	fn assemble_is_deterministic() {
+		let mut pex = script(&[("Run", 2)]);
+		function_mut(&mut pex, "Run")
+			.1
+			.instructions
+			.push(Instruction::NOP);
+		pex.sync_debuginfo(None);
+
+		let bytes = assemble(&pex).unwrap();
+		assert_eq!(bytes, assemble(&pex).unwrap());
+
+		let parsed = parse(&bytes).unwrap();
+		assert_eq!(
+			parsed.debuginfo.unwrap().modtime,
+			pex.debuginfo.unwrap().modtime
+		);
+	}
+
+	/// Every function's instructions, as text so they can be compared across string tables.
+	fn listing(pex: &Pex) -> Vec<String> {
+		(pex.function_paths().into_iter())
+			.flat_map(|(path, f)| {
+				f.instructions
+					.iter()
+					.map(move |i| format!("{path}: {}", disasm::instruction(pex, i)))
+			})
+			.collect()
+	}
+
+	fn traced() -> Pex {
+		fixture::builder()
+			.debug_info()
+			.function(
+				"Run",
+				FunctionBuilder::new()
+					.call(
+						"CALLSTATIC",
+						vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+						vec![string("zebra")],
+					)
+					.op("RETURN", vec![ident("::NoneVar")]),
+			)
+			.build()
+			.unwrap()
+	}
+
+	#[test]
+	fn reassemble_unchanged() {
+		let bytes = assemble(&traced()).unwrap();
+		let pex = parse(&bytes).unwrap();
+
+		assert_eq!(assemble(&pex).unwrap(), bytes);
+		assert_eq!(assemble_with(&pex, StringTable::Keep).unwrap(), bytes);
+	}
+
+	#[test]
+	fn keep_strings() {
+		let mut pex = traced();
+		pex.stringtable.push("Unused".into());
+
+		let kept = parse(&assemble(&pex).unwrap()).unwrap();
+		assert_eq!(kept.stringtable, pex.stringtable);
+
+		let compacted = parse(&assemble_with(&pex, StringTable::Compact).unwrap()).unwrap();
+		assert!(!compacted.stringtable.contains(&"Unused".to_owned()));
+	}
+
+	#[test]
+	fn compact_round_trip() {
+		let mut pex = traced();
+		let before = listing(&pex);
+
+		// A dead string, and a duplicate that something refers to.
+		pex.stringtable.push("Unused".into());
+		pex.stringtable.push("zebra".into());
+		let dup = (pex.stringtable.len() - 1) as u16;
+		pex.objects[0].1.doc_string_idx = dup;
+
+		assert_eq!(pex.compact_strings(true), 2);
+		assert_eq!(listing(&pex), before);
+		assert_eq!(pex.string(pex.objects[0].1.doc_string_idx), "zebra");
+		assert!(pex.stringtable.is_sorted());
+
+		let bytes = assemble_with(&pex, StringTable::Keep).unwrap();
+		let parsed = parse(&bytes).unwrap();
+		assert_eq!(parsed.stringtable, pex.stringtable);
+		assert_eq!(listing(&parsed), before);
+		assert_eq!(assemble_with(&pex, StringTable::Sort).unwrap(), bytes);
+	}
+
+	#[test]
+	fn user_flag_names_restored_after_panic() {
+		let pex = fixture::builder()
+			.user_flags(&["conditional"])
+			.build()
+			.unwrap();
+		let panicked = std::panic::catch_unwind(|| {
+			pex.with_user_flag_names(|| panic!("failed while serializing"))
+		});
+		assert!(panicked.is_err());
+		assert!(USER_FLAG_NAMES.with_borrow(Vec::is_empty));
+		assert!(UserFlags(1).serialize_ron().contains("\"bit0\""));
+	}
+
+	#[test]
+	#[should_panic(expected = "string table is full")]
+	fn intern_into_full_table() {
+		let mut pex = fixture::builder().build().unwrap();
+		pex.stringtable.resize(MAX_STRINGS, String::new());
+		assert!(assemble(&pex).is_ok());
+
+		pex.intern("");
+		pex.intern("One too many");
+	}
+
+	#[test]
+	fn assemble_full_table() {
+		let mut pex = fixture::builder().build().unwrap();
+		pex.stringtable.resize(MAX_STRINGS + 1, String::new());
+		assert!(matches!(assemble(&pex), Err(PexError::Build(_))));
+	}
+
+	#[test]
+	fn split_string() {
+		let mut pex = fixture::builder()
+			.function(
+				"Run",
+				FunctionBuilder::new().call(
+					"CALLSTATIC",
+					vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+					vec![string("Run")],
+				),
+			)
+			.build()
+			.unwrap();
+
+		let idx = pex.stringtable.iter().position(|s| s == "Run").unwrap() as u16;
+		let new = pex.split_string(idx, "Walk");
+
+		assert_ne!(new, idx);
+		assert!(pex.find_function("TestScript", "", "Run").is_some());
+		assert_eq!(
+			listing(&pex),
+			["TestScript.Run: CALLSTATIC Debug, Trace, ::NoneVar, [\"Walk\"]"]
+		);
+	}
+
+	#[test]
+	fn user_flags_by_name() {
+		let pex = fixture::builder()
+			.user_flags(&["conditional"])
+			.function(
+				"Run",
+				FunctionBuilder::new().user_flags(&["hidden", "conditional"]),
+			)
+			.build()
+			.unwrap();
+		let function = pex.find_function("TestScript", "", "Run").unwrap();
+		assert_eq!(
+			pex.user_flag_names(function.user_flags),
+			["hidden", "conditional"]
+		);
+		assert_eq!(
+			disasm::declaration(&pex, "Run", function),
+			"None Run() Hidden Conditional"
+		);
+
+		let flat = |ron: String| ron.split_whitespace().collect::<String>();
+		let ron = flat(pex.with_user_flag_names(|| function.serialize_ron()));
+		assert!(
+			ron.contains(r#"user_flags:["hidden","conditional",]"#),
+			"{ron}"
+		);
+		let parsed = pex.with_user_flag_names(|| Function::deserialize_ron(&ron));
+		assert_eq!(parsed.unwrap(), *function);
+		let unknown = ron.replace("hidden", "missing");
+		assert!(
+			pex.with_user_flag_names(|| Function::deserialize_ron(&unknown))
+				.is_err()
+		);
+
+		// Without the table the bits go by number, and older RON with raw bits still reads.
+		assert!(flat(function.serialize_ron()).contains(r#"["bit0","bit1",]"#));
+		let legacy = ron.replace(r#"["hidden","conditional",]"#, "3");
+		assert_eq!(Function::deserialize_ron(&legacy).unwrap(), *function);
+
+		let model = Script::from(&pex);
+		assert_eq!(model.objects[0].user_flags, ["conditional"]);
+		let mut renamed = model.clone();
+		renamed.objects[0].user_flags = vec!["missing".to_owned()];
+		assert!(renamed.to_pex().is_err());
+		assert_eq!(
+			model.to_pex().unwrap().objects[0].1.user_flags,
+			pex.objects[0].1.user_flags
+		);
+	}
+}
@@ -0,0 +1,309 @@
+//! Release builds: stripping debug info and doc strings, and bringing them back from an earlier build,
+//! and scrubbing where a script was built for reproducible output.
+
+use crate::{
+	disasm, edit,
+	pex::{self, DebugFunction, DebugInfo, Function, Pex},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stripped {
+	/// Functions whose debug info entries were removed.
+	pub functions: usize,
+	pub doc_strings: usize,
+	/// String table entries nothing referred to anymore.
+	pub strings: usize,
+}
+
+impl std::fmt::Display for Stripped {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"removed debug info of {} functions, {} doc strings and {} strings",
+			self.functions, self.doc_strings, self.strings
+		)
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Restored {
+	/// Functions that got their debug info entry back.
+	pub functions: usize,
+	pub doc_strings: usize,
+	/// Paths of functions whose instructions differ from the earlier build, so nothing was restored.
+	pub mismatched: Vec<String>,
+}
+
+impl std::fmt::Display for Restored {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"restored debug info of {} functions and {} doc strings",
+			self.functions, self.doc_strings
+		)
+	}
+}
+
+/// Removes debug info and doc strings, then the strings only they used.
+pub fn strip(pex: &mut Pex) -> Stripped {
+	let mut stripped = Stripped {
+		functions: pex.debuginfo.take().map_or(0, |d| d.functions.len()),
+		..Default::default()
+	};
+
+	// Strings can be in the table more than once, so only doc strings with text count as stripped.
+	let empty = pex.intern("");
+	let strings = pex.stringtable.clone();
+	let mut blank = |idx: &mut u16| {
+		if strings.get(*idx as usize).is_some_and(|s| !s.is_empty()) {
+			stripped.doc_strings += 1;
+		}
+		*idx = empty;
+	};
+
+	for (_, object) in &mut pex.objects {
+		blank(&mut object.doc_string_idx);
+		for property in &mut object.properties {
+			blank(&mut property.doc_string_idx);
+		}
+	}
+	for function in pex.functions_mut() {
+		blank(&mut function.doc_string_idx);
+	}
+
+	stripped.strings = pex.compact_strings(false);
+	stripped
+}
+
+/// What identifies a function's body across builds, since string indices don't carry over.
+fn body(pex: &Pex, function: &Function) -> Vec<String> {
+	function
+		.instructions
+		.iter()
+		.map(|i| disasm::instruction(pex, i))
+		.collect()
+}
+
+/// Brings back debug info and doc strings from an `earlier` build of the same script.
+/// Only functions with the same instructions as in the earlier build get theirs back,
+/// and nothing that's already there is replaced.
+pub fn restore(pex: &mut Pex, earlier: &Pex) -> Restored {
+	let mut restored = Restored::default();
+
+	let earlier_functions = earlier.functions_with_debug();
+	let find_earlier = |path: &str| {
+		earlier_functions
+			.iter()
+			.find(|(p, _, _)| p.eq_ignore_ascii_case(path))
+	};
+
+	// (path, debug entry as strings, doc string) to restore, worked out before changing anything.
+	let mut plans = vec![];
+	for (path, function, debug) in pex.functions_with_debug() {
+		let Some((_, old, old_debug)) = find_earlier(&path) else {
+			continue;
+		};
+
+		if body(pex, function) != body(earlier, old) {
+			restored.mismatched.push(path);
+			continue;
+		}
+
+		let debug = old_debug.filter(|_| debug.is_none()).map(|d| {
+			(
+				earlier.string(d.obj_name_idx).to_owned(),
+				earlier.string(d.state_name_idx).to_owned(),
+				earlier.string(d.fn_name_idx).to_owned(),
+				d.fn_type,
+				d.instructions.clone(),
+			)
+		});
+		let doc = Some(earlier.string(old.doc_string_idx))
+			.filter(|doc| !doc.is_empty() && pex.string(function.doc_string_idx).is_empty())
+			.map(str::to_owned);
+
+		plans.push((path, debug, doc));
+	}
+
+	let mut docs = vec![];
+	for (path, debug, doc) in plans {
+		if let Some((object, state, name, fn_type, instructions)) = debug {
+			let entry = DebugFunction {
+				obj_name_idx: pex.intern(&object),
+				state_name_idx: pex.intern(&state),
+				fn_name_idx: pex.intern(&name),
+				fn_type,
+				instructions,
+			};
+
+			pex.debuginfo
+				.get_or_insert_with(|| DebugInfo {
+					modtime: earlier.debuginfo.as_ref().map_or(0, |d| d.modtime),
+					functions: vec![],
+				})
+				.functions
+				.push(entry);
+			restored.functions += 1;
+		}
+
+		if let Some(doc) = doc {
+			docs.push((path, pex.intern(&doc)));
+		}
+	}
+
+	for (path, editor) in edit::editors(pex) {
+		if let Some((_, doc)) = docs.iter().find(|(p, _)| *p == path) {
+			editor.function.doc_string_idx = *doc;
+			restored.doc_strings += 1;
+		}
+	}
+
+	// Objects and properties are matched by name.
+	for object_idx in 0..pex.objects.len() {
+		let (name_idx, object) = &pex.objects[object_idx];
+		let Some((_, old)) = earlier.objects.iter().find(|(n, _)| {
+			earlier
+				.string(*n)
+				.eq_ignore_ascii_case(pex.string(*name_idx))
+		}) else {
+			continue;
+		};
+
+		let mut docs = vec![];
+		if pex.string(object.doc_string_idx).is_empty() {
+			docs.push((None, earlier.string(old.doc_string_idx)));
+		}
+		for (property_idx, property) in object.properties.iter().enumerate() {
+			let name = pex.string(property.name_idx);
+			if let Some(old) = old
+				.properties
+				.iter()
+				.find(|p| earlier.string(p.name_idx).eq_ignore_ascii_case(name))
+				&& pex.string(property.doc_string_idx).is_empty()
+			{
+				docs.push((Some(property_idx), earlier.string(old.doc_string_idx)));
+			}
+		}
+
+		for (property_idx, doc) in docs {
+			if doc.is_empty() {
+				continue;
+			}
+
+			let doc = pex.intern(doc);
+			let object = &mut pex.objects[object_idx].1;
+			match property_idx {
+				Some(idx) => object.properties[idx].doc_string_idx = doc,
+				None => object.doc_string_idx = doc,
+			}
+			restored.doc_strings += 1;
+		}
+	}
+
+	restored
+}
+
+/// What to put in place of the details a compiled script gives away about where it was built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scrub {
+	pub username: String,
+	pub machine: String,
+	/// Folder to put the source in. Without one, only the source's file name is kept.
+	pub src_dir: Option<String>,
+	/// Compile and debug info time. Without one it's derived from the script's content,
+	/// so the same script always gets the same time.
+	pub time: Option<u64>,
+}
+
+/// Replaces the username, machine name, source path and timestamps, returning whether any changed.
+pub fn scrub(pex: &mut Pex, scrub: &Scrub) -> bool {
+	let before = (
+		pex.username.clone(),
+		pex.machine.clone(),
+		pex.src.clone(),
+		pex.comptime,
+		pex.debuginfo.as_ref().map(|d| d.modtime),
+	);
+
+	// Synced before pinning the time, so writing it out has nothing left to touch.
+	pex.sync_debuginfo(None);
+	pex.username = scrub.username.clone();
+	pex.machine = scrub.machine.clone();
+
+	// Compiled on Windows, so split on backslashes too.
+	let name = pex.src.rsplit(['/', '\\']).next().unwrap_or_default();
+	pex.src = match &scrub.src_dir {
+		Some(dir) => {
+			let sep = if dir.contains('/') { '/' } else { '\\' };
+			format!("{}{sep}{name}", dir.trim_end_matches(['/', '\\']))
+		}
+		None => name.to_owned(),
+	};
+
+	let time = scrub.time.unwrap_or_else(|| content_time(pex));
+	pex.comptime = time;
+	if let Some(debug) = &mut pex.debuginfo {
+		debug.modtime = time;
+	}
+
+	before
+		!= (
+			pex.username.clone(),
+			pex.machine.clone(),
+			pex.src.clone(),
+			pex.comptime,
+			pex.debuginfo.as_ref().map(|d| d.modtime),
+		)
+}
+
+/// A timestamp within ten years of Skyrim's release, picked by hashing everything but the header.
+fn content_time(pex: &Pex) -> u64 {
+	const SKYRIM_RELEASE: u64 = 1_320_969_600;
+	const TEN_YEARS: u64 = 10 * 365 * 86400;
+
+	let mut content = pex.clone();
+	content.username.clear();
+	content.machine.clear();
+	content.src.clear();
+	content.comptime = 0;
+	if let Some(debug) = &mut content.debuginfo {
+		debug.modtime = 0;
+	}
+
+	// FNV-1a, since std's hashers aren't guaranteed to stay the same across releases.
+	// Compacted, so strings nothing uses don't change the time.
+	let hash = pex::assemble_with(&content, pex::StringTable::Compact)
+		.unwrap_or_default()
+		.iter()
+		.fold(0xcbf29ce484222325u64, |hash, byte| {
+			(hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+		});
+
+	SKYRIM_RELEASE + hash % TEN_YEARS
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::builder::{FunctionBuilder, fixture};
+
+	#[test]
+	fn strip_counts_doc_strings() {
+		let mut pex = fixture::builder()
+			.debug_info()
+			.function("Documented", FunctionBuilder::new().doc("Does things"))
+			.function("Undocumented", FunctionBuilder::new())
+			.build()
+			.unwrap();
+
+		// A second empty string, as other tools sometimes write.
+		pex.stringtable.push(String::new());
+		pex.objects[0].1.doc_string_idx = (pex.stringtable.len() - 1) as u16;
+
+		let stripped = strip(&mut pex);
+		assert_eq!(stripped.doc_strings, 1);
+		assert_eq!(stripped.functions, 2);
+		assert!(pex.debuginfo.is_none());
+		assert!(!pex.stringtable.iter().any(|s| s == "Does things"));
+	}
+}
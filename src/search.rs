@@ -0,0 +1,571 @@
+use crate::{
+	disasm,
+	pex::{Function, Instruction, Pex, VariableData, function_path},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+	String(u16),
+	Function {
+		object: usize,
+		state: usize,
+		function: usize,
+	},
+	Instruction {
+		object: usize,
+		state: usize,
+		function: usize,
+		instruction: usize,
+	},
+	/// A property's read (0) or write (1) handler.
+	Handler {
+		object: usize,
+		property: usize,
+		handler: usize,
+	},
+	HandlerInstruction {
+		object: usize,
+		property: usize,
+		handler: usize,
+		instruction: usize,
+	},
+}
+
+impl Location {
+	/// Where this is, like `MyScript › (default) › OnInit › #3`.
+	/// `None` if the script changed since and it isn't there anymore.
+	pub fn describe(&self, pex: &Pex) -> Option<String> {
+		let (object, function, place) = match *self {
+			Location::String(i) => {
+				pex.stringtable.get(i as usize)?;
+				return Some(format!("string #{i}"));
+			}
+			Location::Function {
+				object,
+				state,
+				function,
+			}
+			| Location::Instruction {
+				object,
+				state,
+				function,
+				..
+			} => {
+				let state = pex.objects.get(object)?.1.states.get(state)?;
+				let (name_idx, function) = state.functions.get(function)?;
+				let state_name = match pex.string(state.name_idx) {
+					"" => "(default)",
+					name => name,
+				};
+				(
+					object,
+					function,
+					format!("{state_name} › {}", pex.string(*name_idx)),
+				)
+			}
+			Location::Handler {
+				object,
+				property,
+				handler,
+			}
+			| Location::HandlerInstruction {
+				object,
+				property,
+				handler,
+				..
+			} => {
+				let property = pex.objects.get(object)?.1.properties.get(property)?;
+				let function = [&property.read_handler, &property.write_handler]
+					.get(handler)?
+					.as_ref()?;
+				let kind = if handler == 0 { "Get" } else { "Set" };
+				(
+					object,
+					function,
+					format!("{} › {kind}", pex.string(property.name_idx)),
+				)
+			}
+		};
+
+		let mut out = format!("{} › {place}", pex.string(pex.objects[object].0));
+		if let Location::Instruction {
+			instruction: pc, ..
+		}
+		| Location::HandlerInstruction {
+			instruction: pc, ..
+		} = *self
+		{
+			function.instructions.get(pc)?;
+			out += &format!(" › #{pc}");
+		}
+		Some(out)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+	pub location: Location,
+	/// What matched, rendered for display.
+	pub text: String,
+}
+
+/// Splits a query into case insensitive terms. Quoted terms keep their quotes,
+/// so `"MQ101"` only matches string literals in instructions.
+fn terms(query: &str) -> Vec<String> {
+	let mut out = vec![];
+	let mut chars = query.chars().peekable();
+
+	while let Some(&c) = chars.peek() {
+		if c.is_whitespace() || c == ',' {
+			chars.next();
+		} else if c == '"' {
+			let mut term = String::from(chars.next().unwrap());
+			for c in chars.by_ref() {
+				term.push(c);
+				if c == '"' {
+					break;
+				}
+			}
+			out.push(term.to_lowercase());
+		} else {
+			let mut term = String::new();
+			while let Some(&c) = chars.peek() {
+				if c.is_whitespace() || c == ',' {
+					break;
+				}
+				term.push(c);
+				chars.next();
+			}
+			out.push(term.to_lowercase());
+		}
+	}
+
+	out
+}
+
+/// Searches the string table, function names and disassembled instructions.
+/// Every term of the query has to appear for something to match, e.g. `CALLSTATIC Debug Trace`.
+pub fn search(pex: &Pex, query: &str) -> Vec<Hit> {
+	let terms = terms(query);
+	if terms.is_empty() {
+		return vec![];
+	}
+
+	let matches = |haystack: &str| {
+		let haystack = haystack.to_lowercase();
+		terms.iter().all(|t| haystack.contains(t.as_str()))
+	};
+
+	let mut hits = pex
+		.stringtable
+		.iter()
+		.enumerate()
+		.filter(|(_, s)| matches(&format!("{s:?}")))
+		.map(|(i, s)| Hit {
+			location: Location::String(i as u16),
+			text: s.clone(),
+		})
+		.collect::<Vec<_>>();
+
+	for (object, (_, obj)) in pex.objects.iter().enumerate() {
+		for (state, st) in obj.states.iter().enumerate() {
+			for (function, (name_idx, func)) in st.functions.iter().enumerate() {
+				let at = |instruction| match instruction {
+					Some(instruction) => Location::Instruction {
+						object,
+						state,
+						function,
+						instruction,
+					},
+					None => Location::Function {
+						object,
+						state,
+						function,
+					},
+				};
+				search_function(pex, &matches, pex.string(*name_idx), func, at, &mut hits);
+			}
+		}
+
+		for (property, prop) in obj.properties.iter().enumerate() {
+			let handlers = [("Get", &prop.read_handler), ("Set", &prop.write_handler)];
+			for (handler, (kind, func)) in handlers.into_iter().enumerate() {
+				let Some(func) = func else {
+					continue;
+				};
+
+				let at = |instruction| match instruction {
+					Some(instruction) => Location::HandlerInstruction {
+						object,
+						property,
+						handler,
+						instruction,
+					},
+					None => Location::Handler {
+						object,
+						property,
+						handler,
+					},
+				};
+				let name = format!("{}.{kind}", pex.string(prop.name_idx));
+				search_function(pex, &matches, &name, func, at, &mut hits);
+			}
+		}
+	}
+
+	hits
+}
+
+/// Adds hits for a function's name and instructions, placed by `at` with the instruction index if there is one.
+fn search_function(
+	pex: &Pex,
+	matches: &impl Fn(&str) -> bool,
+	name: &str,
+	function: &Function,
+	at: impl Fn(Option<usize>) -> Location,
+	hits: &mut Vec<Hit>,
+) {
+	if matches(name) {
+		hits.push(Hit {
+			location: at(None),
+			text: disasm::declaration(pex, name, function),
+		});
+	}
+
+	for (pc, instruction) in function.instructions.iter().enumerate() {
+		let text = disasm::instruction(pex, instruction);
+		if matches(&text) {
+			hits.push(Hit {
+				location: at(Some(pc)),
+				text,
+			});
+		}
+	}
+}
+
+/// A place in the script that refers to a string table entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+	/// Where, e.g. `MyScript.OnInit #3` or `MyScript.Count: return type`.
+	pub place: String,
+	/// Whether the string is used as text, like a literal or doc string, rather than as a name.
+	pub literal: bool,
+}
+
+struct References<'a> {
+	pex: &'a Pex,
+	idx: u16,
+	out: Vec<Reference>,
+}
+
+impl References<'_> {
+	fn check(&mut self, found: u16, place: impl FnOnce() -> String, literal: bool) {
+		if found == self.idx {
+			self.out.push(Reference {
+				place: place(),
+				literal,
+			});
+		}
+	}
+
+	fn function(&mut self, path: &str, function: &Function) {
+		let pex = self.pex;
+		self.check(
+			function.return_type_idx,
+			|| format!("{path}: return type"),
+			false,
+		);
+		self.check(
+			function.doc_string_idx,
+			|| format!("{path}: doc string"),
+			true,
+		);
+
+		for var in function.params.iter().chain(&function.locals) {
+			let name = pex.string(var.name_idx);
+			self.check(var.name_idx, || format!("{path}: variable {name}"), false);
+			self.check(var.type_idx, || format!("{path}: type of {name}"), false);
+		}
+
+		for (pc, instruction) in function.instructions.iter().enumerate() {
+			let (operands, args) = instruction.operands();
+			for operand in operands.iter().chain(args) {
+				match operand {
+					VariableData::Ident(i) => self.check(*i, || format!("{path} #{pc}"), false),
+					VariableData::String(i) => self.check(*i, || format!("{path} #{pc}"), true),
+					_ => {}
+				}
+			}
+		}
+	}
+}
+
+/// Every place that refers to string table entry `idx`, in the order they're stored.
+pub fn references(pex: &Pex, idx: u16) -> Vec<Reference> {
+	let mut refs = References {
+		pex,
+		idx,
+		out: vec![],
+	};
+
+	for debug in pex.debuginfo.iter().flat_map(|d| &d.functions) {
+		let place = || {
+			let path = function_path(
+				pex.string(debug.obj_name_idx),
+				pex.string(debug.state_name_idx),
+				pex.string(debug.fn_name_idx),
+			);
+			format!("debug info of {path}")
+		};
+		refs.check(debug.obj_name_idx, place, false);
+		refs.check(debug.state_name_idx, place, false);
+		refs.check(debug.fn_name_idx, place, false);
+	}
+
+	for (name_idx, _) in &pex.userflags {
+		refs.check(*name_idx, || "user flag".to_owned(), false);
+	}
+
+	for (obj_name_idx, obj) in &pex.objects {
+		let object = pex.string(*obj_name_idx);
+		refs.check(*obj_name_idx, || format!("{object}: name"), false);
+		refs.check(obj.parent_name_idx, || format!("{object}: parent"), false);
+		refs.check(obj.doc_string_idx, || format!("{object}: doc string"), true);
+		refs.check(
+			obj.auto_state_name_idx,
+			|| format!("{object}: auto state"),
+			false,
+		);
+
+		for (name_idx, type_idx, _, data) in &obj.variables {
+			let name = pex.string(*name_idx);
+			refs.check(*name_idx, || format!("{object}.{name}"), false);
+			refs.check(*type_idx, || format!("{object}.{name}: type"), false);
+			if let VariableData::String(i) = data {
+				refs.check(*i, || format!("{object}.{name}: value"), true);
+			}
+		}
+
+		for property in &obj.properties {
+			let name = pex.string(property.name_idx);
+			refs.check(property.name_idx, || format!("{object}.{name}"), false);
+			refs.check(
+				property.type_idx,
+				|| format!("{object}.{name}: type"),
+				false,
+			);
+			refs.check(
+				property.doc_string_idx,
+				|| format!("{object}.{name}: doc string"),
+				true,
+			);
+			if let Some(var) = property.auto_var_name {
+				refs.check(var, || format!("{object}.{name}: auto variable"), false);
+			}
+
+			let handlers = [
+				("Get", &property.read_handler),
+				("Set", &property.write_handler),
+			];
+			for (kind, handler) in handlers {
+				if let Some(function) = handler {
+					refs.function(&format!("{object}.{name}.{kind}"), function);
+				}
+			}
+		}
+
+		for state in &obj.states {
+			let state_name = pex.string(state.name_idx);
+			refs.check(
+				state.name_idx,
+				|| format!("{object}: state {state_name:?}"),
+				false,
+			);
+
+			for (name_idx, function) in &state.functions {
+				let path = function_path(object, state_name, pex.string(*name_idx));
+				refs.check(*name_idx, || path.clone(), false);
+				refs.function(&path, function);
+			}
+		}
+	}
+
+	refs.out
+}
+
+/// Every place in `object` that reads or writes its variable `name`: instructions naming it,
+/// and auto properties backed by it.
+pub fn variable_references(pex: &Pex, object: usize, name: &str) -> Vec<Reference> {
+	let (obj_name_idx, obj) = &pex.objects[object];
+	let object = pex.string(*obj_name_idx);
+	let mut out = vec![];
+
+	for property in &obj.properties {
+		let prop_name = pex.string(property.name_idx);
+		if let Some(var) = property.auto_var_name
+			&& pex.string(var).eq_ignore_ascii_case(name)
+		{
+			out.push(Reference {
+				place: format!("{object}.{prop_name}: auto variable"),
+				literal: false,
+			});
+		}
+	}
+
+	let handlers = obj.properties.iter().flat_map(|property| {
+		let prop_name = pex.string(property.name_idx);
+		[
+			("Get", &property.read_handler),
+			("Set", &property.write_handler),
+		]
+		.into_iter()
+		.filter_map(move |(kind, handler)| {
+			Some((format!("{object}.{prop_name}.{kind}"), handler.as_ref()?))
+		})
+	});
+	let functions = obj.states.iter().flat_map(|state| {
+		state.functions.iter().map(|(name_idx, function)| {
+			(
+				function_path(object, pex.string(state.name_idx), pex.string(*name_idx)),
+				function,
+			)
+		})
+	});
+
+	for (path, function) in handlers.chain(functions) {
+		// A local of the same name hides the variable.
+		let shadowed = function
+			.params
+			.iter()
+			.chain(&function.locals)
+			.any(|var| pex.string(var.name_idx).eq_ignore_ascii_case(name));
+		if shadowed {
+			continue;
+		}
+
+		for (pc, instruction) in function.instructions.iter().enumerate() {
+			let (operands, args) = instruction.operands();
+			let variables = operands.iter().skip(member_operands(instruction));
+			let uses = variables.chain(args).any(|operand| {
+				matches!(operand, VariableData::Ident(i) if pex.string(*i).eq_ignore_ascii_case(name))
+			});
+
+			if uses {
+				out.push(Reference {
+					place: format!("{path} #{pc}"),
+					literal: false,
+				});
+			}
+		}
+	}
+
+	out
+}
+
+/// How many leading operands name a member rather than hold a variable: the method of a call,
+/// the script and function of a static call and the property of `PROPGET` and `PROPSET`.
+fn member_operands(instruction: &Instruction) -> usize {
+	match instruction {
+		Instruction::CALLMETHOD(..)
+		| Instruction::CALLPARENT(..)
+		| Instruction::PROPGET(..)
+		| Instruction::PROPSET(..) => 1,
+		Instruction::CALLSTATIC(..) => 2,
+		_ => 0,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		builder::{FunctionBuilder, PropertyBuilder, fixture::builder, ident, string},
+		model::VariableData::Int,
+	};
+
+	fn script() -> Pex {
+		builder()
+			.variable("Count", "Int", Int(0), &[])
+			.property(
+				PropertyBuilder::new("Total", "Int").getter(
+					FunctionBuilder::new()
+						.call(
+							"CALLSTATIC",
+							vec![ident("Debug"), ident("Trace"), ident("::NoneVar")],
+							vec![string("in getter")],
+						)
+						.op("RETURN", vec![ident("Count")]),
+				),
+			)
+			.function(
+				"Reset",
+				FunctionBuilder::new().op("ASSIGN", vec![ident("Count"), Int(0)]),
+			)
+			// Members sharing the variable's name don't use it.
+			.function(
+				"Members",
+				FunctionBuilder::new()
+					.call(
+						"CALLMETHOD",
+						vec![ident("Count"), ident("self"), ident("::NoneVar")],
+						vec![],
+					)
+					.call(
+						"CALLSTATIC",
+						vec![ident("Count"), ident("Count"), ident("::NoneVar")],
+						vec![],
+					)
+					.op("PROPSET", vec![ident("Count"), ident("self"), Int(1)]),
+			)
+			.build()
+			.unwrap()
+	}
+
+	#[test]
+	fn finds_instructions_in_property_handlers() {
+		let pex = script();
+		let hits = search(&pex, "\"in getter\"")
+			.into_iter()
+			.filter(|h| !matches!(h.location, Location::String(_)))
+			.collect::<Vec<_>>();
+
+		assert_eq!(hits.len(), 1);
+		assert_eq!(
+			hits[0].location,
+			Location::HandlerInstruction {
+				object: 0,
+				property: 0,
+				handler: 0,
+				instruction: 0,
+			}
+		);
+		assert_eq!(
+			hits[0].location.describe(&pex).as_deref(),
+			Some("TestScript › Total › Get › #0")
+		);
+	}
+
+	#[test]
+	fn stale_locations_describe_as_none() {
+		let mut pex = script();
+		let hit = search(&pex, "Reset")
+			.into_iter()
+			.find(|h| matches!(h.location, Location::Function { .. }))
+			.unwrap();
+		assert!(hit.location.describe(&pex).is_some());
+
+		pex.objects[0].1.states[0].functions.clear();
+		assert_eq!(hit.location.describe(&pex), None);
+	}
+
+	#[test]
+	fn variable_references_cover_functions_and_handlers() {
+		let pex = script();
+		let places = variable_references(&pex, 0, "count")
+			.into_iter()
+			.map(|r| r.place)
+			.collect::<Vec<_>>();
+
+		assert_eq!(places, ["TestScript.Total.Get #1", "TestScript.Reset #0"]);
+	}
+}
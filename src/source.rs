@@ -0,0 +1,153 @@
+//! Mapping instructions to lines of the original `.psc` source through debug info, and back.
+
+use crate::pex::Pex;
+use std::path::{Path, PathBuf};
+
+/// Source lines of every instruction in a script, by function path.
+#[derive(Debug, Clone, Default)]
+pub struct LineMap {
+	functions: Vec<(String, Vec<u16>)>,
+}
+
+impl LineMap {
+	/// Scripts compiled without debug info map to nothing.
+	pub fn new(pex: &Pex) -> Self {
+		Self {
+			functions: pex
+				.functions_with_debug()
+				.into_iter()
+				.filter_map(|(path, _, debug)| Some((path, debug?.instructions.clone())))
+				.collect(),
+		}
+	}
+
+	/// Line numbers of a function's instructions, by path as in [`Pex::function_paths`].
+	pub fn function(&self, path: &str) -> Option<&[u16]> {
+		self.functions
+			.iter()
+			.find(|(p, _)| p.eq_ignore_ascii_case(path))
+			.map(|(_, lines)| lines.as_slice())
+	}
+
+	/// The source line an instruction was compiled from.
+	pub fn line(&self, path: &str, pc: usize) -> Option<u16> {
+		self.function(path)?.get(pc).copied()
+	}
+
+	/// Every instruction compiled from a line, as function paths and indices.
+	pub fn instructions(&self, line: u16) -> Vec<(&str, usize)> {
+		self.functions
+			.iter()
+			.flat_map(|(path, lines)| {
+				lines
+					.iter()
+					.enumerate()
+					.filter(move |(_, l)| **l == line)
+					.map(move |(pc, _)| (path.as_str(), pc))
+			})
+			.collect()
+	}
+}
+
+/// Looks for the source of a script: where [`Pex::src`] says it was compiled from,
+/// then by file name next to the `.pex`, in the game's usual source folders, and in `source_dirs`.
+pub fn find_source(pex: &Pex, pex_path: &Path, source_dirs: &[PathBuf]) -> Option<PathBuf> {
+	let original = Path::new(&pex.src);
+	if original.is_file() {
+		return Some(original.to_owned());
+	}
+
+	// Compiled on Windows, so split on backslashes too.
+	let name = pex
+		.src
+		.rsplit(['/', '\\'])
+		.next()
+		.filter(|n| !n.is_empty())?;
+
+	let dir = pex_path.parent().unwrap_or(Path::new(""));
+	let mut candidates = vec![
+		dir.to_owned(),
+		dir.join("Source"),
+		dir.join("..").join("Source").join("Scripts"),
+	];
+	candidates.extend(source_dirs.iter().cloned());
+
+	candidates
+		.into_iter()
+		.map(|dir| dir.join(name))
+		.find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::builder::{FunctionBuilder, fixture, ident};
+	use std::fs;
+
+	fn nop(line: u16) -> FunctionBuilder {
+		FunctionBuilder::new().line(line).op("NOP", vec![])
+	}
+
+	#[test]
+	fn line_map() {
+		let run = nop(3)
+			.op("NOP", vec![])
+			.line(4)
+			.op("RETURN", vec![ident("::NoneVar")]);
+		let pex = fixture::builder()
+			.debug_info()
+			.function("Run", run)
+			.state_function("Busy", "Run", nop(4))
+			.build()
+			.unwrap();
+
+		let lines = LineMap::new(&pex);
+		assert_eq!(lines.function("TestScript.Run"), Some(&[3, 3, 4][..]));
+		assert_eq!(lines.line("testscript.busy.RUN", 0), Some(4));
+		assert_eq!(lines.line("TestScript.Run", 3), None);
+		assert_eq!(lines.function("TestScript.Missing"), None);
+		assert_eq!(
+			lines.instructions(4),
+			[("TestScript.Run", 2), ("TestScript.Busy.Run", 0)]
+		);
+		assert!(lines.instructions(5).is_empty());
+
+		let pex = fixture::script([("Run", nop(3))]);
+		assert_eq!(LineMap::new(&pex).function("TestScript.Run"), None);
+	}
+
+	#[test]
+	fn find_windows_source() {
+		let dir = std::env::temp_dir().join(format!("pexspy-source-{}", std::process::id()));
+		let scripts = dir.join("Data").join("Scripts");
+		let source = dir.join("Data").join("Source").join("Scripts");
+		let extra = dir.join("Extra");
+		for dir in [&scripts, &source, &extra] {
+			fs::create_dir_all(dir).unwrap();
+		}
+		fs::write(source.join("TestScript.psc"), "").unwrap();
+		fs::write(extra.join("Other.psc"), "").unwrap();
+
+		let pex_path = scripts.join("TestScript.pex");
+		let find = |src: &str| {
+			let pex = fixture::builder().source(src).build().unwrap();
+			find_source(&pex, &pex_path, std::slice::from_ref(&extra))
+		};
+
+		assert_eq!(
+			find(r"C:\Modding\Data\Source\Scripts\TestScript.psc"),
+			Some(
+				scripts
+					.join("..")
+					.join("Source")
+					.join("Scripts")
+					.join("TestScript.psc")
+			)
+		);
+		assert_eq!(find(r"C:\Modding\Other.psc"), Some(extra.join("Other.psc")));
+		assert_eq!(find(r"C:\Modding\Missing.psc"), None);
+		assert_eq!(find(r"C:\Modding\"), None);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}